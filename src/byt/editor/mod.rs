@@ -20,13 +20,17 @@ mod tests;
 use byt::views::file::FileView;
 use byt::io::binds::{Keymaster, KeyInput};
 use byt::render;
-use byt::editor::mutator::MutatePair;
+use byt::editor::mutator::{Mutatable, MutatePair};
 
 #[derive(Clone, PartialEq, Debug)]
 /// An action will try to run the function in the scope specified.
 pub enum Action {
     Mutator(String),
     There(String),
+    /// Close the current file, or the editor if it's the last one.
+    Quit,
+    /// Show some text in a scratch buffer in front of the current file.
+    Scratch(String),
 }
 
 /// Allows for the entity to produce Actions to be executed.
@@ -35,6 +39,9 @@ pub trait Actionable {
     fn actions(&mut self) -> Vec<Action>;
 }
 
+/// Makes a mutator for a file that was just opened.
+type FileMutatorFactory = dyn Fn() -> Box<dyn mutator::Mutator<FileView>>;
+
 /// Contains all editor state, responds to user input, and
 /// renders appropriately.
 pub struct Editor {
@@ -55,6 +62,9 @@ pub struct Editor {
     /// All of the global mutators for the editor.
     mutators : Vec<Box<mutator::Mutator<Editor>>>,
 
+    /// Make the mutators that every file gets when it's opened.
+    file_mutators : Vec<Box<FileMutatorFactory>>,
+
     /// Whether or not we should render at the next opportunity.
     should_render : bool,
 }
@@ -67,10 +77,45 @@ impl Editor {
             current_file : 0,
             should_render : false,
             mutators : Vec::new(),
+            file_mutators : Vec::new(),
             actions : Vec::new(),
         }
     }
 
+    /// Add a file to the editor with every file mutator registered on it
+    /// and make it the current pane.
+    fn add_file(&mut self, view : FileView) -> io::Result<()> {
+        let mut file = MutatePair::new(view);
+
+        for make in self.file_mutators.iter() {
+            file.register_mutator(make())?;
+        }
+
+        self.files.push(file);
+        self.current_file = self.files.len() - 1;
+        Ok(())
+    }
+
+    /// Close the current file and go back to the one opened before it.
+    /// Closing the last file closes the editor.
+    fn close_current(&mut self) {
+        if self.files.len() == 1 {
+            self.actions.push(Action::Quit);
+            return;
+        }
+
+        self.files.remove(self.current_file);
+        self.current_file = self.files.len() - 1;
+        self.files[self.current_file].target_mut().redraw();
+    }
+
+    /// Register a mutator on every file opened from now on. `make` is
+    /// called to make a new one for each of them.
+    pub fn add_file_mutator<F>(&mut self, make : F)
+        where F: Fn() -> Box<dyn mutator::Mutator<FileView>> + 'static {
+        self.file_mutators.push(Box::new(make));
+    }
+
     /// Get the file that's currently open.
     pub fn current_file(&mut self) -> Option<&mut MutatePair<FileView>> {
         self.files.get_mut(self.current_file)
@@ -78,37 +123,65 @@ impl Editor {
 
     /// Attempt to open a file and make it the current pane.
     pub fn open(&mut self, path : &str) -> io::Result<()> {
-        self.files.push(MutatePair::new(FileView::new(path)?));
-        self.current_file = self.files.len() - 1;
-        Ok(())
+        self.add_file(FileView::new(path)?)
     }
 
     /// Make a new empty file.
     pub fn open_empty(&mut self) -> io::Result<()> {
-        self.files.push(MutatePair::new(FileView::empty()?));
-        self.current_file = self.files.len() - 1;
-        Ok(())
+        self.add_file(FileView::empty()?)
+    }
+
+    /// Show some text in a scratch buffer and make it the current pane.
+    pub fn open_scratch(&mut self, text : &str) -> io::Result<()> {
+        self.add_file(FileView::scratch(text)?)
     }
 }
 
 impl KeyInput for Editor {
     fn consume(&mut self, key : Key) -> Option<()> {
+        let mut requests = Vec::new();
+
         {
-            let mut file = self.current_file().unwrap();
+            let file   = self.files.get_mut(self.current_file).unwrap();
             let result = file.consume(key);
 
-            if result.is_some() {
-                for action in file.actions() {
-                    if let Action::Mutator(name) = action {
-                        file.call_action(name.as_str(), key);
+            if result.is_none() {
+                return self.keys.consume(key);
+            }
+
+            let mut actions = file.actions();
+
+            // Functions can have actions of their own, like quitting,
+            // which are for the editor to deal with.
+            while !actions.is_empty() {
+                for action in actions {
+                    match action {
+                        Action::Mutator(name) => {
+                            if let Err(err) = file.call_action(name.as_str(), key) {
+                                file.target_mut().show_message(err.to_string().as_str());
+                            }
+                        },
+                        action                => requests.push(action),
                     }
                 }
 
-                return Some(());
+                actions = file.actions();
+            }
+        }
+
+        for action in requests {
+            match action {
+                Action::Quit          => self.close_current(),
+                Action::Scratch(text) => {
+                    if let Err(err) = self.open_scratch(text.as_str()) {
+                        self.files[self.current_file].target_mut().show_message(err.to_string().as_str());
+                    }
+                },
+                action                => self.actions.push(action),
             }
         }
 
-        self.keys.consume(key)
+        Some(())
     }
 }
 
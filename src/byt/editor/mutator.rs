@@ -40,17 +40,24 @@ pub trait Scope<T> {
     fn call(&mut self, name : &str, target : &mut T, key : Key) -> io::Result<()>;
 }
 
+/// A closure that a RustScope can call.
+type Closure<'a, S, T, I> = dyn Fn(&mut S, &mut T, I) + 'a;
+
 /// Stores and allows the invocation of any procedures defined in Rust.
 /// Each closure is given a mutable reference to some kind of state storage
 /// (usually a struct or even the mutator itself) and the closure's target,
 /// which would be something like the editor or a pane.
-pub struct RustScope<'a, S, T> {
-    map : HashMap<String, Box<Fn(&mut S, &mut T, Key) + 'a>>,
+///
+/// Closures are also given the input that triggered them, which is just
+/// the key that was pressed unless the scope's owner has more to say about
+/// it, like a count typed before the key.
+pub struct RustScope<'a, S, T, I = Key> {
+    map : HashMap<String, Box<Closure<'a, S, T, I>>>,
     state : S,
 }
 
-impl<'a, S, T> RustScope<'a, S, T> {
-    pub fn new(state : S) -> RustScope<'a, S, T> {
+impl<'a, S, T, I> RustScope<'a, S, T, I> {
+    pub fn new(state : S) -> RustScope<'a, S, T, I> {
         RustScope {
             map : HashMap::new(),
             state
@@ -68,27 +75,38 @@ impl<'a, S, T> RustScope<'a, S, T> {
     }
 
     /// Register a closure with a name.
-    pub fn register<F: Fn(&mut S, &mut T, Key) + 'a, N: AsRef<str>>(&mut self, name: N, closure: F) {
-        self.map.insert(String::from(name.as_ref()), Box::new(closure) as Box<Fn(&mut S, &mut T, Key) + 'a>);
+    pub fn register<F: Fn(&mut S, &mut T, I) + 'a, N: AsRef<str>>(&mut self, name: N, closure: F) {
+        self.map.insert(String::from(name.as_ref()), Box::new(closure) as Box<Closure<'a, S, T, I>>);
     }
-}
 
-impl<'a, S, T> Scope<T> for RustScope<'a, S, T> {
-    fn has_function(&self, name : &str) -> bool {
-        self.map.get(name).is_some()
-    }
-
-    fn call(&mut self, name : &str, target : &mut T, key : Key) -> io::Result<()> {
+    /// Call the closure registered with a name, giving it some input.
+    /// Will error if the name has no association.
+    pub fn call_with(&mut self, name : &str, target : &mut T, input : I) -> io::Result<()> {
         let closure = self.map.get(name);
 
         if closure.is_none() {
             return Err(Error::new(ErrorKind::InvalidInput, "Closure not found for name"));
         }
 
-        closure.unwrap()(&mut self.state, target, key);
+        closure.unwrap()(&mut self.state, target, input);
 
         Ok(())
     }
+
+    /// Whether or not a closure is registered with a name.
+    pub fn has_function(&self, name : &str) -> bool {
+        self.map.contains_key(name)
+    }
+}
+
+impl<'a, S, T> Scope<T> for RustScope<'a, S, T> {
+    fn has_function(&self, name : &str) -> bool {
+        self.map.contains_key(name)
+    }
+
+    fn call(&mut self, name : &str, target : &mut T, key : Key) -> io::Result<()> {
+        self.call_with(name, target, key)
+    }
 }
 
 /// A Mutator describes a set of bindings, actions, and hooks that manipulate a n instance of a
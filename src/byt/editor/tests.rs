@@ -4,6 +4,8 @@
 use termion::event::Key;
 use super::*;
 use super::mutator::Scope;
+#[cfg(test)]
+use byt::mutators::vym::Vym;
 
 #[test]
 fn it_uses_a_rust_closure() {
@@ -30,3 +32,44 @@ fn it_uses_a_rust_closure_with_state() {
     rust.call("foo", &mut bar, Key::Char('a'));
     assert!(*rust.state());
 }
+
+#[test]
+fn it_gives_a_rust_closure_its_input() {
+    let mut bar  = 0;
+    let mut rust = mutator::RustScope::new(());
+
+    rust.register("foo", |_state, target, count : usize| {
+        *target += count;
+    });
+
+    rust.call_with("foo", &mut bar, 3).unwrap();
+    assert_eq!(bar, 3);
+    assert!(rust.call_with("bar", &mut bar, 3).is_err());
+}
+
+#[test]
+fn it_closes_a_scratch_buffer_back_to_the_file() {
+    let mut editor = Editor::new();
+    editor.add_file_mutator(|| Box::new(Vym::new()));
+    editor.open_empty().unwrap();
+    editor.open_scratch("foo\nbar\n").unwrap();
+
+    assert_eq!(editor.files.len(), 2);
+    assert!(editor.files[1].target().file().is_modified());
+    assert!(!editor.current_file().unwrap().target().is_modified());
+
+    for key in ":q\n".chars() {
+        editor.consume(Key::Char(key));
+    }
+
+    // Only closing the last file closes the editor.
+    assert_eq!(editor.files.len(), 1);
+    assert_eq!(editor.current_file, 0);
+    assert!(editor.actions().is_empty());
+
+    for key in ":q\n".chars() {
+        editor.consume(Key::Char(key));
+    }
+
+    assert_eq!(editor.actions(), vec![Action::Quit]);
+}
@@ -95,6 +95,14 @@ impl BindingTable {
         self.id
     }
 
+    /// Get what a key is bound to, without falling back to the wildcard.
+    fn find_binding(&self, key : Key) -> Option<&Arrow> {
+        self.bindings
+            .iter()
+            .find(|x| x.key == key)
+            .map(|binding| &binding.result)
+    }
+
     // ###############################
     // P U B L I C  F U N C T I O N S
     // ###############################
@@ -142,9 +150,7 @@ impl BindingTable {
     /// the given key or the wildcard otherwise. Return
     /// that key's action if so.
     pub fn search_key(&self, key : Key) -> Option<&Arrow> {
-        let entry = self.bindings
-            .iter()
-            .find(|ref x| x.key == key);
+        let entry = self.find_binding(key);
 
         if entry.is_none() {
             if let Arrow::Nothing = self.wildcard {
@@ -154,7 +160,7 @@ impl BindingTable {
             return Some(&self.wildcard);
         }
 
-        Some(&entry.unwrap().result)
+        entry
     }
 
     /// Set the wildcard action.
@@ -296,7 +302,7 @@ impl Keymaster {
 
             for key in prefix.as_ref().iter() {
                 let mut table = self.get_table_by_id(max_id).unwrap();
-                let binding = table.search_key(*key);
+                let binding = table.find_binding(*key);
 
                 if binding.is_none() {
                     should_make = true;
@@ -320,7 +326,7 @@ impl Keymaster {
                 let mut last    = max_id;
                 let mut current = self.id_counter;
 
-                for key in rest.iter() {
+                for _key in rest.iter() {
                     self.new_table();
                 }
 
@@ -381,12 +387,12 @@ impl Keymaster {
         self.bind(sequence, action)
     }
 
-    /// Set a wildcard to some action in particular.
-    pub fn bind_wildcard<T: AsRef<[Key]>>(&mut self, sequence : T, action : &str) -> io::Result<()> {
-        let sequence       = sequence.as_ref();
-        let (prefix, last) = sequence.split_at(sequence.len() - 1);
-        let table          = self.make_prefix(prefix)?;
-        let action         = self.mutator_action(action);
+    /// Run a mutator action for any key that isn't bound after `prefix`.
+    /// The action is given the key. An empty prefix sets the root table's
+    /// wildcard.
+    pub fn bind_wildcard<T: AsRef<[Key]>>(&mut self, prefix : T, action : &str) -> io::Result<()> {
+        let table  = self.make_prefix(prefix)?;
+        let action = self.mutator_action(action);
 
         self.get_table_by_id(table).unwrap().set_wildcard(action);
         Ok(())
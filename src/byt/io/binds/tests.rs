@@ -116,3 +116,29 @@ fn it_handles_depth() {
     assert_eq!(master.current_table, 2);
     assert!(master.consume(Key::Char('r')).is_some());
 }
+
+#[test]
+fn it_binds_a_wildcard_after_a_prefix() {
+    let mut master = Keymaster::new();
+
+    master.bind_wildcard([Key::Char('"')], "register").unwrap();
+
+    assert!(master.consume(Key::Char('a')).is_none());
+    assert!(master.consume(Key::Char('"')).is_some());
+    assert!(master.consume(Key::Char('a')).is_some());
+    assert_eq!(master.actions(), vec![Action::Mutator(String::from("register"))]);
+}
+
+#[test]
+fn it_binds_a_prefix_in_a_table_with_a_wildcard() {
+    let mut master = Keymaster::new();
+
+    let wildcard = master.mutator_action("wildcard");
+    master.get_root().set_wildcard(wildcard);
+    master.bind_action([Key::Ctrl('r'), Key::Char('a')], "prefixed").unwrap();
+
+    assert!(master.consume(Key::Ctrl('r')).is_some());
+    assert!(master.actions().is_empty());
+    assert!(master.consume(Key::Char('a')).is_some());
+    assert_eq!(master.actions(), vec![Action::Mutator(String::from("prefixed"))]);
+}
@@ -0,0 +1,221 @@
+//! byt - io::diff
+//!
+//! A small line-based diff, used to show how a file on disk differs from
+//! the text in the editor. This is the linear space version of the
+//! algorithm from Eugene Myers' "An O(ND) Difference Algorithm and Its
+//! Variations", which finds the shortest sequence of line insertions and
+//! deletions between two texts.
+
+// EXTERNS
+
+// LIBRARY INCLUDES
+use std::cmp;
+
+// SUBMODULES
+mod tests;
+
+// LOCAL INCLUDES
+
+/// A single line of a diff.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    /// The line is in both texts.
+    Same(String),
+    /// The line is only in the new text.
+    Insert(String),
+    /// The line is only in the old text.
+    Delete(String),
+}
+
+/// Find the changes that turn `old` into `new`, line by line.
+pub fn diff_lines(old : &str, new : &str) -> Vec<Change> {
+    let old : Vec<&str> = old.lines().collect();
+    let new : Vec<&str> = new.lines().collect();
+
+    let mut changes = Vec::with_capacity(cmp::max(old.len(), new.len()));
+    diff_range(&old, &new, &mut changes);
+
+    // Searching from both ends can leave insertions ahead of deletions.
+    // Put the deletions first in each run of changes, like `diff` does.
+    for run in changes.split_mut(|change| matches!(*change, Change::Same(_))) {
+        run.sort_by_key(|change| match *change { Change::Delete(_) => 0, _ => 1 });
+    }
+
+    changes
+}
+
+/// Add the changes that turn `old` into `new` to `changes`. The middle
+/// snake splits the problem in two, so only the search's frontier is kept
+/// rather than every step of it.
+fn diff_range(old : &[&str], new : &[&str], changes : &mut Vec<Change>) {
+    let prefix = old.iter().zip(new.iter()).take_while(|&(a, b)| a == b).count();
+    let suffix = old[prefix ..].iter().rev()
+        .zip(new[prefix ..].iter().rev())
+        .take_while(|&(a, b)| a == b)
+        .count();
+
+    let same    = |lines : &[&str], changes : &mut Vec<Change>| {
+        changes.extend(lines.iter().map(|line| Change::Same(String::from(*line))));
+    };
+    let old_mid = &old[prefix .. old.len() - suffix];
+    let new_mid = &new[prefix .. new.len() - suffix];
+
+    same(&old[.. prefix], changes);
+
+    if old_mid.is_empty() {
+        changes.extend(new_mid.iter().map(|line| Change::Insert(String::from(*line))));
+    } else if new_mid.is_empty() {
+        changes.extend(old_mid.iter().map(|line| Change::Delete(String::from(*line))));
+    } else {
+        let (x, y, u, v) = middle_snake(old_mid, new_mid);
+
+        diff_range(&old_mid[.. x], &new_mid[.. y], changes);
+        same(&old_mid[x .. u], changes);
+        diff_range(&old_mid[u ..], &new_mid[v ..], changes);
+    }
+
+    same(&old[old.len() - suffix ..], changes);
+}
+
+/// Find the middle snake of the shortest way to turn `old` into `new`,
+/// which both have lines and differ at both ends. That's the run of lines
+/// they share halfway along it, found by searching from both ends at once.
+/// Returns where it starts and ends in each of them.
+fn middle_snake(old : &[&str], new : &[&str]) -> (usize, usize, usize, usize) {
+    let n      = old.len() as isize;
+    let m      = new.len() as isize;
+    let delta  = n - m;
+    let odd    = delta % 2 != 0;
+    let max    = (n + m + 1) / 2;
+    let offset = max + 1;
+
+    // `forward[k + offset]` holds the furthest x reached from the start on
+    // diagonal k, where k = x - y. `backward[c + offset]` holds how many
+    // lines of `old` have been passed from the end on the diagonal c of
+    // the reversed texts, which is diagonal `delta - c` of these ones.
+    let mut forward  = vec![0isize; (2 * offset + 1) as usize];
+    let mut backward = vec![0isize; (2 * offset + 1) as usize];
+
+    for edits in 0 .. max + 1 {
+        let mut k = -edits;
+        while k <= edits {
+            let index = (k + offset) as usize;
+            // Move down, i.e insert a line from `new`, or right, i.e delete
+            // a line from `old`.
+            let start = if k == -edits || (k != edits && forward[index - 1] < forward[index + 1]) {
+                forward[index + 1]
+            } else {
+                forward[index - 1] + 1
+            };
+
+            // Follow any lines that are the same in both.
+            let mut x = start;
+            while x < n && x - k < m && old[x as usize] == new[(x - k) as usize] {
+                x += 1;
+            }
+
+            forward[index] = x;
+
+            let c = delta - k;
+            if odd && c >= -(edits - 1) && c < edits && x + backward[(c + offset) as usize] >= n {
+                return (start as usize, (start - k) as usize, x as usize, (x - k) as usize);
+            }
+
+            k += 2;
+        }
+
+        let mut c = -edits;
+        while c <= edits {
+            let index = (c + offset) as usize;
+            let start = if c == -edits || (c != edits && backward[index - 1] < backward[index + 1]) {
+                backward[index + 1]
+            } else {
+                backward[index - 1] + 1
+            };
+
+            let mut x = start;
+            while x < n && x - c < m && old[(n - 1 - x) as usize] == new[(m - 1 - x + c) as usize] {
+                x += 1;
+            }
+
+            backward[index] = x;
+
+            let k = delta - c;
+            if !odd && k >= -edits && k <= edits && forward[(k + offset) as usize] + x >= n {
+                return ((n - x) as usize, (m - x + c) as usize, (n - start) as usize, (m - start + c) as usize);
+            }
+
+            c += 2;
+        }
+    }
+
+    unreachable!("texts that differ always have a middle snake")
+}
+
+/// Write out changes the way `diff -u` does, as hunks of changed lines
+/// with `context` unchanged lines around them.
+pub fn unified(changes : &[Change], context : usize) -> String {
+    let is_change  = |change : &Change| !matches!(*change, Change::Same(_));
+
+    let mut output   = String::new();
+    let mut index    = 0;
+    // How many lines of each text come before `index`.
+    let mut old_line = 0;
+    let mut new_line = 0;
+
+    while let Some(found) = changes[index ..].iter().position(&is_change) {
+        let first    = index + found;
+        let mut last = first;
+
+        // Changes close enough together that their context would touch go
+        // in the same hunk.
+        while let Some(found) = changes[last + 1 ..].iter().position(&is_change) {
+            if found > 2 * context {
+                break;
+            }
+
+            last += found + 1;
+        }
+
+        let start = cmp::max(index, first.saturating_sub(context));
+        let end   = cmp::min(changes.len(), last + context + 1);
+
+        for change in &changes[index .. start] {
+            if let Change::Same(_) = *change {
+                old_line += 1;
+                new_line += 1;
+            }
+        }
+
+        let mut hunk      = String::new();
+        let mut old_count = 0;
+        let mut new_count = 0;
+
+        for change in &changes[start .. end] {
+            let (prefix, line) = match *change {
+                Change::Same(ref line)   => { old_count += 1; new_count += 1; (' ', line) },
+                Change::Delete(ref line) => { old_count += 1; ('-', line) },
+                Change::Insert(ref line) => { new_count += 1; ('+', line) },
+            };
+
+            hunk.push(prefix);
+            hunk.push_str(line);
+            hunk.push('\n');
+        }
+
+        // An empty range is numbered after the line it follows.
+        let range = |line : usize, count : usize| match count {
+            0     => format!("{},0", line),
+            count => format!("{},{}", line + 1, count),
+        };
+
+        output.push_str(format!("@@ -{} +{} @@\n", range(old_line, old_count), range(new_line, new_count)).as_str());
+        output.push_str(hunk.as_str());
+
+        old_line += old_count;
+        new_line += new_count;
+        index     = end;
+    }
+
+    output
+}
@@ -0,0 +1,107 @@
+/// Tests for the line-based diff.
+#[cfg(test)]
+use super::*;
+
+#[test]
+fn it_diffs_identical_text() {
+    let changes = diff_lines("foo\nbar\n", "foo\nbar\n");
+    assert_eq!(changes, vec![
+        Change::Same(String::from("foo")),
+        Change::Same(String::from("bar")),
+    ]);
+}
+
+#[test]
+fn it_diffs_empty_text() {
+    assert_eq!(diff_lines("", ""), vec![]);
+    assert_eq!(diff_lines("", "foo"), vec![Change::Insert(String::from("foo"))]);
+    assert_eq!(diff_lines("foo", ""), vec![Change::Delete(String::from("foo"))]);
+}
+
+#[test]
+fn it_diffs_changed_lines() {
+    let changes = diff_lines("a\nb\nc\nd\n", "a\nc\nx\nd\n");
+    assert_eq!(changes, vec![
+        Change::Same(String::from("a")),
+        Change::Delete(String::from("b")),
+        Change::Same(String::from("c")),
+        Change::Insert(String::from("x")),
+        Change::Same(String::from("d")),
+    ]);
+}
+
+#[test]
+fn it_writes_a_unified_diff() {
+    let old     = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n";
+    let new     = "1\nx\n3\n4\n5\n6\n7\n8\n9\n";
+    let changes = diff_lines(old, new);
+
+    assert_eq!(unified(&changes, 1), "@@ -1,3 +1,3 @@\n 1\n-2\n+x\n 3\n@@ -9,2 +9,1 @@\n 9\n-10\n");
+    assert_eq!(unified(&changes, 4), "@@ -1,10 +1,9 @@\n 1\n-2\n+x\n 3\n 4\n 5\n 6\n 7\n 8\n 9\n-10\n");
+    assert_eq!(unified(&diff_lines("a\n", "a\n"), 3), "");
+    assert_eq!(unified(&diff_lines("", "a\n"), 3), "@@ -0,0 +1,1 @@\n+a\n");
+}
+
+#[test]
+fn it_finds_the_shortest_diff() {
+    // Every text of up to five lines made of `a` and `b`.
+    let mut texts = vec![String::new()];
+    for _ in 0 .. 5 {
+        let longer : Vec<String> = texts.iter()
+            .filter(|text| text.len() == texts.last().unwrap().len())
+            .flat_map(|text| vec![format!("{}a\n", text), format!("{}b\n", text)])
+            .collect();
+        texts.extend(longer);
+    }
+
+    for old in texts.iter() {
+        for new in texts.iter() {
+            let changes = diff_lines(old, new);
+            let (mut before, mut after, mut edits) = (String::new(), String::new(), 0);
+
+            for change in changes {
+                match change {
+                    Change::Same(line)   => { before += &line; before += "\n"; after += &line; after += "\n"; },
+                    Change::Delete(line) => { before += &line; before += "\n"; edits += 1; },
+                    Change::Insert(line) => { after += &line; after += "\n"; edits += 1; },
+                }
+            }
+
+            assert_eq!(&before, old);
+            assert_eq!(&after, new);
+
+            // The fewest edits leave the longest common subsequence alone.
+            let old : Vec<char> = old.chars().filter(|&c| c != '\n').collect();
+            let new : Vec<char> = new.chars().filter(|&c| c != '\n').collect();
+            let mut common = vec![vec![0; new.len() + 1]; old.len() + 1];
+            for i in 1 ..= old.len() {
+                for j in 1 ..= new.len() {
+                    common[i][j] = if old[i - 1] == new[j - 1] {
+                        common[i - 1][j - 1] + 1
+                    } else {
+                        cmp::max(common[i - 1][j], common[i][j - 1])
+                    };
+                }
+            }
+
+            assert_eq!(edits, old.len() + new.len() - 2 * common[old.len()][new.len()]);
+        }
+    }
+}
+
+#[test]
+fn it_diffs_long_text() {
+    let old : String = (0 .. 100000).map(|line| format!("{}\n", line)).collect();
+    let new         = old.replace("\n500\n", "\n").replace("\n99999\n", "\nx\n");
+
+    let changes : Vec<Change> = diff_lines(&old, &new)
+        .into_iter()
+        .filter(|change| !matches!(*change, Change::Same(_)))
+        .collect();
+
+    assert_eq!(changes, vec![
+        Change::Delete(String::from("500")),
+        Change::Delete(String::from("99999")),
+        Change::Insert(String::from("x")),
+    ]);
+}
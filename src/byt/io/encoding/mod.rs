@@ -0,0 +1,254 @@
+//! byt - io::encoding
+//!
+//! Detection and conversion of the text encodings byt understands. byt
+//! always edits text as UTF-8, so files in any other encoding are decoded
+//! when they are opened and encoded again when they are saved.
+
+// EXTERNS
+
+// LIBRARY INCLUDES
+use std::io::{Error, ErrorKind, Read, Write};
+use std::io;
+use std::str;
+
+// SUBMODULES
+mod tests;
+
+// LOCAL INCLUDES
+
+/// The byte order mark that starts a UTF-8 file.
+const UTF8_BOM : &[u8] = &[0xEF, 0xBB, 0xBF];
+/// The byte order mark that starts a little-endian UTF-16 file.
+const UTF16_LE_BOM : &[u8] = &[0xFF, 0xFE];
+/// The byte order mark that starts a big-endian UTF-16 file.
+const UTF16_BE_BOM : &[u8] = &[0xFE, 0xFF];
+/// The number of bytes read at a time when decoding a stream.
+const DECODE_CHUNK_SIZE : usize = 64 * 1024;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Encoding {
+    /// UTF-8 without a byte order mark.
+    Utf8,
+    /// UTF-8 that starts with a byte order mark.
+    Utf8Bom,
+    /// Little-endian UTF-16 that starts with a byte order mark.
+    Utf16Le,
+    /// Big-endian UTF-16 that starts with a byte order mark.
+    Utf16Be,
+    /// ISO-8859-1, in which every byte is the character with the same
+    /// code point.
+    Latin1,
+}
+
+impl Encoding {
+    /// Get the byte order mark that starts files in this encoding. Empty
+    /// if there isn't one.
+    pub fn bom(&self) -> &'static [u8] {
+        match *self {
+            Encoding::Utf8Bom => UTF8_BOM,
+            Encoding::Utf16Le => UTF16_LE_BOM,
+            Encoding::Utf16Be => UTF16_BE_BOM,
+            _                 => &[],
+        }
+    }
+
+    /// Check whether text in this encoding is stored as UTF-8, meaning
+    /// that everything after the byte order mark can be used as it is.
+    pub fn is_utf8(&self) -> bool {
+        *self == Encoding::Utf8 || *self == Encoding::Utf8Bom
+    }
+
+    /// Get the name of the encoding.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Encoding::Utf8    => "utf-8",
+            Encoding::Utf8Bom => "utf-8-bom",
+            Encoding::Utf16Le => "utf-16le",
+            Encoding::Utf16Be => "utf-16be",
+            Encoding::Latin1  => "latin1",
+        }
+    }
+}
+
+// #################################
+// P R I V A T E  F U N C T I O N S
+// #################################
+
+/// Convert text that doesn't start with a byte order mark to UTF-8.
+fn decode_text(bytes : &[u8], encoding : Encoding) -> io::Result<Vec<u8>> {
+    match encoding {
+        Encoding::Utf8 | Encoding::Utf8Bom => Ok(bytes.to_vec()),
+        Encoding::Utf16Le | Encoding::Utf16Be => {
+            if !bytes.len().is_multiple_of(2) {
+                return Err(Error::new(ErrorKind::InvalidData, "Odd number of bytes in UTF-16 text"));
+            }
+
+            let units = bytes.chunks(2).map(|pair| {
+                if encoding == Encoding::Utf16Le {
+                    (pair[1] as u16) << 8 | pair[0] as u16
+                } else {
+                    (pair[0] as u16) << 8 | pair[1] as u16
+                }
+            });
+
+            let mut text = String::with_capacity(bytes.len());
+
+            for c in ::std::char::decode_utf16(units) {
+                match c {
+                    Ok(c)  => text.push(c),
+                    Err(_) => return Err(Error::new(ErrorKind::InvalidData, "Invalid UTF-16 text")),
+                }
+            }
+
+            Ok(text.into_bytes())
+        },
+        Encoding::Latin1 => {
+            let text : String = bytes.iter().map(|&byte| byte as char).collect();
+            Ok(text.into_bytes())
+        },
+    }
+}
+
+/// Get how many bytes at the start of some text can be decoded without
+/// splitting a character whose rest is still to come.
+fn complete_length(bytes : &[u8], encoding : Encoding) -> usize {
+    match encoding {
+        Encoding::Utf8 | Encoding::Utf8Bom => match str::from_utf8(bytes) {
+            Err(err) if err.error_len().is_none() => err.valid_up_to(),
+            _                                     => bytes.len(),
+        },
+        Encoding::Utf16Le | Encoding::Utf16Be => {
+            let length = bytes.len() - bytes.len() % 2;
+
+            if length == 0 {
+                return 0;
+            }
+
+            // A high surrogate needs the unit after it.
+            let last = if encoding == Encoding::Utf16Le {
+                bytes[length - 1]
+            } else {
+                bytes[length - 2]
+            };
+
+            if last & 0xFC == 0xD8 { length - 2 } else { length }
+        },
+        Encoding::Latin1 => bytes.len(),
+    }
+}
+
+// ###############################
+// P U B L I C  F U N C T I O N S
+// ###############################
+
+/// Guess the encoding of a file from the bytes at its start, which are the
+/// whole file if `complete` is set. Byte order marks are trusted. Otherwise
+/// anything that is valid UTF-8 is UTF-8 and everything else is Latin-1,
+/// which can represent any sequence of bytes.
+pub fn detect(sample : &[u8], complete : bool) -> Encoding {
+    if sample.starts_with(UTF8_BOM) {
+        return Encoding::Utf8Bom;
+    }
+
+    if sample.starts_with(UTF16_LE_BOM) {
+        return Encoding::Utf16Le;
+    }
+
+    if sample.starts_with(UTF16_BE_BOM) {
+        return Encoding::Utf16Be;
+    }
+
+    match str::from_utf8(sample) {
+        Ok(_)  => Encoding::Utf8,
+        // The sample may well end in the middle of a character.
+        Err(err) if err.error_len().is_none() && !complete => Encoding::Utf8,
+        Err(_) => Encoding::Latin1,
+    }
+}
+
+/// Convert everything `reader` has to UTF-8 a chunk at a time, writing it
+/// to `writer`, so that the text never has to be in memory all at once.
+/// The byte order mark is dropped if there is one. Returns the number of
+/// bytes written.
+pub fn decode_stream<R : Read, W : Write>(reader : &mut R, writer : &mut W, encoding : Encoding) -> io::Result<usize> {
+    let bom         = encoding.bom();
+    let mut chunk   = vec![0u8; DECODE_CHUNK_SIZE];
+    let mut pending = Vec::new();
+    let mut first   = true;
+    let mut written = 0;
+
+    loop {
+        let num_bytes = reader.read(&mut chunk)?;
+        let finished  = num_bytes == 0;
+
+        pending.extend_from_slice(&chunk[.. num_bytes]);
+
+        // Wait until there is enough to tell whether there is a byte order
+        // mark.
+        if first && pending.len() < bom.len() && !finished {
+            continue;
+        }
+
+        if first && pending.starts_with(bom) {
+            pending.drain(.. bom.len());
+        }
+
+        first = false;
+
+        // Whatever is left at the end is decoded as it is so that a
+        // partial character is an error.
+        let length = if finished { pending.len() } else { complete_length(&pending, encoding) };
+        let text   = decode_text(&pending[.. length], encoding)?;
+
+        writer.write_all(&text)?;
+        written += text.len();
+        pending.drain(.. length);
+
+        if finished {
+            return Ok(written);
+        }
+    }
+}
+
+/// Convert UTF-8 text to an encoding. The byte order mark is not
+/// included. Fails if the text can't be represented in the encoding.
+pub fn encode(text : &[u8], encoding : Encoding) -> io::Result<Vec<u8>> {
+    if encoding.is_utf8() {
+        return Ok(text.to_vec());
+    }
+
+    let text = match str::from_utf8(text) {
+        Ok(text) => text,
+        Err(_)   => return Err(Error::new(ErrorKind::InvalidData, "Text is not valid UTF-8")),
+    };
+
+    let mut bytes = Vec::with_capacity(text.len());
+
+    match encoding {
+        Encoding::Utf16Le | Encoding::Utf16Be => {
+            for unit in text.encode_utf16() {
+                let (high, low) = ((unit >> 8) as u8, unit as u8);
+
+                if encoding == Encoding::Utf16Le {
+                    bytes.push(low);
+                    bytes.push(high);
+                } else {
+                    bytes.push(high);
+                    bytes.push(low);
+                }
+            }
+        },
+        _ => {
+            for c in text.chars() {
+                if c as u32 > 0xFF {
+                    return Err(Error::new(ErrorKind::InvalidData,
+                                          format!("'{}' can't be represented in {}", c, encoding.name())));
+                }
+
+                bytes.push(c as u8);
+            }
+        },
+    }
+
+    Ok(bytes)
+}
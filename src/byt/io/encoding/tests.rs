@@ -0,0 +1,63 @@
+/// Tests for encoding detection and conversion.
+#[cfg(test)]
+use super::*;
+
+/// Decode some bytes all at once.
+#[cfg(test)]
+fn decode(bytes : &[u8], encoding : Encoding) -> io::Result<Vec<u8>> {
+    let mut text = Vec::new();
+    decode_stream(&mut &bytes[..], &mut text, encoding).map(|_| text)
+}
+
+#[test]
+fn it_detects_encodings() {
+    assert_eq!(detect(b"foo\n", true), Encoding::Utf8);
+    assert_eq!(detect(b"\xEF\xBB\xBFfoo", true), Encoding::Utf8Bom);
+    assert_eq!(detect(b"\xFF\xFEf\x00", true), Encoding::Utf16Le);
+    assert_eq!(detect(b"\xFE\xFF\x00f", true), Encoding::Utf16Be);
+    assert_eq!(detect(b"caf\xE9", true), Encoding::Latin1);
+}
+
+#[test]
+fn it_detects_utf8_cut_off_mid_character() {
+    assert_eq!(detect(b"caf\xC3", false), Encoding::Utf8);
+    assert_eq!(detect(b"caf\xC3", true), Encoding::Latin1);
+}
+
+#[test]
+fn it_converts_utf16() {
+    let text = decode(b"\xFF\xFEc\x00a\x00f\x00\xE9\x00", Encoding::Utf16Le).unwrap();
+    assert_eq!(text, "caf\u{e9}".as_bytes().to_vec());
+
+    let bytes = encode(&text, Encoding::Utf16Be).unwrap();
+    assert_eq!(bytes, b"\x00c\x00a\x00f\x00\xE9".to_vec());
+    assert!(decode(b"\xFF\xFEc", Encoding::Utf16Le).is_err());
+}
+
+#[test]
+fn it_converts_latin1() {
+    let text = decode(b"caf\xE9", Encoding::Latin1).unwrap();
+    assert_eq!(text, "caf\u{e9}".as_bytes().to_vec());
+    assert_eq!(encode(&text, Encoding::Latin1).unwrap(), b"caf\xE9".to_vec());
+
+    let err = encode("\u{20ac}".as_bytes(), Encoding::Latin1).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn it_decodes_a_stream_split_mid_character() {
+    use std::io::Read;
+
+    // The byte order mark and a surrogate pair are both split between
+    // reads.
+    let mut reader = (&b"\xFF"[..])
+        .chain(&b"\xFEa\x00\x3D"[..])
+        .chain(&b"\xD8\x00\xDE"[..]);
+    let mut text   = Vec::new();
+
+    assert_eq!(decode_stream(&mut reader, &mut text, Encoding::Utf16Le).unwrap(), 5);
+    assert_eq!(text, "a\u{1f600}".as_bytes().to_vec());
+
+    let mut reader = &b"\xFF\xFEa\x00\x3D\xD8"[..];
+    assert!(decode_stream(&mut reader, &mut Vec::new(), Encoding::Utf16Le).is_err());
+}
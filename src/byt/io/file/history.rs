@@ -0,0 +1,393 @@
+//! byt - io::file::history
+//!
+//! Keeps a PieceFile's undo tree around between sessions. When a file is
+//! saved its actions and append buffer are written to an undo store, and
+//! the next time the file is opened they are read back so that undo picks
+//! up where it left off.
+//!
+//! Undo stores are kept together in an undo directory rather than next to
+//! the files they belong to. It's `$BYT_UNDO_DIR` if that is set, and
+//! otherwise `byt/undo` in `$XDG_STATE_HOME` or `~/.local/state`. Nothing
+//! is kept unless that directory exists, so making it turns this on.
+//!
+//! The store only makes sense for the exact contents it was saved with, so
+//! it records a hash of the text and is thrown away if the file no longer
+//! matches.
+
+// EXTERNS
+
+// LIBRARY INCLUDES
+#[cfg(test)]
+use std::cell::RefCell;
+use std::cmp;
+#[cfg(not(test))]
+use std::env;
+use std::fs::File;
+use std::fs;
+use std::io::{BufWriter, Error, ErrorKind, Read, Write};
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// SUBMODULES
+
+// LOCAL INCLUDES
+use byt::io::journal::{read_u64, write_u64};
+use super::{Action, Operation, Piece, PieceFile, SourceFile};
+
+/// Identifies undo stores and the version of their format.
+pub const MAGIC : &[u8] = b"BYTUNDO2\n";
+/// The number of bytes hashed at a time.
+const HASH_CHUNK_SIZE : usize = 64 * 1024;
+/// Marks a missing index.
+const NONE : u64 = u64::MAX;
+
+#[cfg(test)]
+thread_local! {
+    /// The undo directory for tests, which keep their stores to themselves
+    /// rather than putting them wherever the user keeps theirs.
+    pub static TEST_UNDO_DIR : RefCell<Option<PathBuf>> = const { RefCell::new(None) };
+}
+
+/// Hashes text with 64-bit FNV-1a, a piece at a time.
+pub struct Hasher {
+    hash : u64,
+}
+
+impl Hasher {
+    pub fn new() -> Hasher {
+        Hasher {
+            hash : 0xcbf29ce484222325,
+        }
+    }
+
+    /// Add the next piece of the text to the hash.
+    pub fn write(&mut self, bytes : &[u8]) {
+        for &byte in bytes {
+            self.hash ^= byte as u64;
+            self.hash  = self.hash.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    /// Get the hash of all of the text so far.
+    pub fn finish(&self) -> u64 {
+        self.hash
+    }
+}
+
+/// Get the directory undo stores are kept in, if it exists.
+#[cfg(not(test))]
+fn undo_dir() -> Option<PathBuf> {
+    // Relative paths in the XDG variables are meant to be ignored.
+    let absolute = |name : &str| env::var_os(name).map(PathBuf::from).filter(|path| path.is_absolute());
+
+    let directory = match env::var_os("BYT_UNDO_DIR") {
+        Some(directory) => PathBuf::from(directory),
+        None            => match absolute("XDG_STATE_HOME") {
+            Some(state) => state.join("byt").join("undo"),
+            None        => absolute("HOME")?.join(".local").join("state").join("byt").join("undo"),
+        },
+    };
+
+    if directory.is_dir() { Some(directory) } else { None }
+}
+
+#[cfg(test)]
+fn undo_dir() -> Option<PathBuf> {
+    TEST_UNDO_DIR.with(|directory| directory.borrow().clone())
+}
+
+/// Get the path of the undo store for a file, or None if undo history
+/// isn't being kept. Stores are named after the whole path of the file
+/// with each `/` turned into a `%`, like vim's.
+pub fn path_for(path : &str) -> Option<PathBuf> {
+    let directory = undo_dir()?;
+    let path      = fs::canonicalize(path).ok()?;
+
+    Some(directory.join(path.to_string_lossy().replace('/', "%")))
+}
+
+/// Hash the text of a PieceFile that was just opened.
+fn hash(file : &mut PieceFile) -> io::Result<u64> {
+    let length     = file.len();
+    let seek       = file.offset;
+    let mut hasher = Hasher::new();
+    let mut offset = 0;
+
+    while offset < length {
+        let num_bytes = cmp::min(HASH_CHUNK_SIZE, length - offset);
+        hasher.write(&file.read_bytes_at(offset, num_bytes)?);
+        offset += num_bytes;
+    }
+
+    file.offset = seek;
+
+    Ok(hasher.finish())
+}
+
+fn write_index<W: Write>(out : &mut W, index : Option<usize>) -> io::Result<()> {
+    write_u64(out, index.map_or(NONE, |index| index as u64))
+}
+
+fn read_index(bytes : &mut &[u8]) -> io::Result<Option<usize>> {
+    let index = read_u64(bytes)?;
+    Ok(if index == NONE { None } else { Some(index as usize) })
+}
+
+fn read_bytes<'a>(bytes : &mut &'a [u8], length : usize) -> io::Result<&'a [u8]> {
+    if bytes.len() < length {
+        return Err(Error::new(ErrorKind::UnexpectedEof, "Undo store ended"));
+    }
+
+    let (read, rest) = bytes.split_at(length);
+    *bytes = rest;
+
+    Ok(read)
+}
+
+fn write_action<W: Write>(out : &mut W, action : &Action) -> io::Result<()> {
+    // Instants only mean something while byt is running, so the time
+    // of the action is stored as a wall clock time.
+    let age  = action.timestamp.elapsed();
+    let time = SystemTime::now().checked_sub(age).unwrap_or(UNIX_EPOCH);
+    let time = time.duration_since(UNIX_EPOCH).unwrap_or(Duration::from_secs(0));
+
+    let op = match action.op {
+        Operation::Insert => 0,
+        Operation::Delete => 1,
+    };
+
+    out.write_all(&[op, action.merge_down as u8, action.merge_up as u8])?;
+    write_u64(out, action.offset as u64)?;
+    write_u64(out, action.length as u64)?;
+    write_u64(out, time.as_secs())?;
+    write_u64(out, time.subsec_nanos() as u64)?;
+    write_index(out, action.parent)?;
+    write_u64(out, action.group as u64)?;
+
+    write_u64(out, action.children.len() as u64)?;
+    for &child in action.children.iter() {
+        write_u64(out, child as u64)?;
+    }
+
+    write_u64(out, action.pieces.len() as u64)?;
+    for piece in action.pieces.iter() {
+        // Saving copies all of the text the undo tree needs into the
+        // append buffer.
+        if piece.file != SourceFile::Append {
+            return Err(Error::new(ErrorKind::InvalidData, "Undo tree refers to the original file"));
+        }
+
+        write_u64(out, piece.file_offset as u64)?;
+        write_u64(out, piece.length as u64)?;
+        write_u64(out, piece.logical_offset as u64)?;
+    }
+
+    Ok(())
+}
+
+/// Read an action. Pieces are moved along by `append_offset` bytes, which
+/// is where the stored append buffer starts in the PieceFile's.
+fn read_action(bytes : &mut &[u8], append_offset : usize) -> io::Result<Action> {
+    let flags = read_bytes(bytes, 3)?;
+    let op    = match flags[0] {
+        0 => Operation::Insert,
+        1 => Operation::Delete,
+        _ => return Err(Error::new(ErrorKind::InvalidData, "Unknown operation")),
+    };
+
+    let merge_down = flags[1] != 0;
+    let merge_up   = flags[2] != 0;
+    let offset     = read_u64(bytes)? as usize;
+    let length     = read_u64(bytes)? as usize;
+    let seconds    = read_u64(bytes)?;
+    let nanos      = read_u64(bytes)?;
+    let parent     = read_index(bytes)?;
+    let group      = read_u64(bytes)? as usize;
+
+    let time = UNIX_EPOCH + Duration::new(seconds, nanos as u32);
+    let age  = SystemTime::now().duration_since(time).unwrap_or(Duration::from_secs(0));
+    let now  = Instant::now();
+
+    let mut children = Vec::new();
+    for _ in 0 .. read_u64(bytes)? {
+        children.push(read_u64(bytes)? as usize);
+    }
+
+    let mut pieces = Vec::new();
+    for _ in 0 .. read_u64(bytes)? {
+        pieces.push(Piece {
+            file           : SourceFile::Append,
+            file_offset    : read_u64(bytes)? as usize + append_offset,
+            length         : read_u64(bytes)? as usize,
+            logical_offset : read_u64(bytes)? as usize,
+        });
+    }
+
+    Ok(Action {
+        op,
+        offset,
+        length,
+        pieces,
+        merge_down,
+        merge_up,
+        timestamp : now.checked_sub(age).unwrap_or(now),
+        parent,
+        children,
+        group,
+    })
+}
+
+/// Write the undo tree of a PieceFile that was just saved to its undo
+/// store, given the hash of the text it saved. Nothing is written if the
+/// undo tree is the same as the last time it was stored or loaded.
+pub fn store(file : &mut PieceFile, hash : u64) -> io::Result<()> {
+    let store = match file.path.as_ref().and_then(|path| path_for(path)) {
+        Some(store) => store,
+        None        => return Ok(()),
+    };
+
+    let stored = Some((store.clone(), file.actions.len(), file.current));
+
+    if file.stored_history == stored {
+        return Ok(());
+    }
+
+    // There's no history to keep, but there might be some from before
+    // that no longer fits the file.
+    if file.actions.is_empty() {
+        if store.exists() {
+            fs::remove_file(&store)?;
+        }
+
+        file.stored_history = stored;
+        return Ok(());
+    }
+
+    let size    = file.len();
+    let mut out = BufWriter::new(File::create(&store)?);
+    let name    = file.encoding.name().as_bytes();
+
+    out.write_all(MAGIC)?;
+    write_u64(&mut out, size as u64)?;
+    write_u64(&mut out, hash)?;
+    write_u64(&mut out, name.len() as u64)?;
+    out.write_all(name)?;
+
+    write_u64(&mut out, file.append_file.len() as u64)?;
+    out.write_all(&file.append_file)?;
+
+    write_index(&mut out, file.current)?;
+
+    write_u64(&mut out, file.roots.len() as u64)?;
+    for &root in file.roots.iter() {
+        write_u64(&mut out, root as u64)?;
+    }
+
+    write_u64(&mut out, file.actions.len() as u64)?;
+    for action in file.actions.iter() {
+        write_action(&mut out, action)?;
+    }
+
+    out.flush()?;
+    file.stored_history = stored;
+
+    Ok(())
+}
+
+/// Read the undo tree for a PieceFile that was just opened from its undo
+/// store, if there is one. A store that doesn't match the file is deleted.
+pub fn load(file : &mut PieceFile) -> io::Result<()> {
+    let store = match file.path.as_ref().and_then(|path| path_for(path)) {
+        Some(ref store) if store.exists() => store.clone(),
+        _                                 => return Ok(()),
+    };
+
+    let mut contents = Vec::new();
+    File::open(&store)?.read_to_end(&mut contents)?;
+
+    match read(file, &contents) {
+        Ok(true)  => {
+            file.stored_history = Some((store, file.actions.len(), file.current));
+            Ok(())
+        },
+        Ok(false) => fs::remove_file(&store),
+        Err(err)  => {
+            fs::remove_file(&store).ok();
+            Err(err)
+        },
+    }
+}
+
+/// Read an undo store into a PieceFile. Returns false if it belongs to
+/// different contents.
+fn read(file : &mut PieceFile, contents : &[u8]) -> io::Result<bool> {
+    let invalid   = || Error::new(ErrorKind::InvalidData, "Invalid undo store");
+    let mut bytes = contents;
+
+    if read_bytes(&mut bytes, MAGIC.len())? != MAGIC {
+        return Err(invalid());
+    }
+
+    let size = read_u64(&mut bytes)? as usize;
+    let hash = read_u64(&mut bytes)?;
+
+    let name_length = read_u64(&mut bytes)? as usize;
+    let name        = read_bytes(&mut bytes, name_length)?;
+
+    // The text the undo tree works on depends on the encoding the file
+    // was read in as well as its bytes.
+    if size != file.len() ||
+       name != file.encoding.name().as_bytes() ||
+       hash != self::hash(file)? {
+        return Ok(false);
+    }
+
+    let append_length = read_u64(&mut bytes)? as usize;
+    let append        = read_bytes(&mut bytes, append_length)?;
+    let append_offset = file.append_file.len();
+    let current       = read_index(&mut bytes)?;
+
+    let mut roots = Vec::new();
+    for _ in 0 .. read_u64(&mut bytes)? {
+        roots.push(read_u64(&mut bytes)? as usize);
+    }
+
+    let mut actions = Vec::new();
+    for _ in 0 .. read_u64(&mut bytes)? {
+        actions.push(read_action(&mut bytes, append_offset)?);
+    }
+
+    // Make sure the undo tree can't send us out of bounds. Actions are
+    // always made after their parents, so parents coming first also
+    // makes sure that following them can't go around in circles.
+    let num_actions = actions.len();
+    let in_bounds   = |index : &usize| *index < num_actions;
+    let parent_of   = |index : usize, parent : Option<usize>| actions[index].parent == parent;
+    let valid       = current.iter().all(&in_bounds) &&
+                      roots.iter().all(|&root| in_bounds(&root) && parent_of(root, None)) &&
+                      actions.iter().enumerate().all(|(index, action)| {
+                          action.parent.iter().all(|&parent| parent < index) &&
+                          action.children.iter().all(|&child| {
+                              in_bounds(&child) && parent_of(child, Some(index))
+                          }) &&
+                          action.pieces.iter().all(|piece| {
+                              piece.file_offset + piece.length <= append_offset + append_length
+                          })
+                      });
+
+    if !valid {
+        return Err(invalid());
+    }
+
+    // New groups mustn't be mistaken for stored ones.
+    let next_group = actions.iter().map(|action| action.group + 1).max().unwrap_or(0);
+
+    file.append_file.extend_from_slice(append);
+    file.next_group = cmp::max(file.next_group, next_group);
+    file.actions = actions;
+    file.current = current;
+    file.roots   = roots;
+
+    Ok(true)
+}
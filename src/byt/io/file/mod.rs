@@ -6,30 +6,61 @@
 // EXTERNS
 
 // LIBRARY INCLUDES
+use std::cell::Cell;
+use std::cmp;
+use std::collections::HashSet;
+use std::env;
 use std::fmt;
 use std::fs::{File, OpenOptions};
+use std::fs;
+use std::error;
 use std::io::{
-    BufReader,
-    Error,
+    BufWriter,
     ErrorKind,
     Read,
-    Result,
     Seek,
     SeekFrom,
     Write
 };
 use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::result;
 use std::str;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
 use std::time;
 
 // SUBMODULES
+mod history;
 mod tests;
 
 // LOCAL INCLUDES
+use self::history::Hasher;
+use byt::io::encoding::{Encoding, decode_stream, detect, encode};
+use byt::io::journal::{Header, Journal, Record};
+use byt::io::search::{Match, Pattern};
+use byt::io::source::FileSource;
+use byt::io::tree::{Tree, Weighted};
+
+/// The number of bytes written at a time when saving.
+const SAVE_CHUNK_SIZE : usize = 64 * 1024;
+/// The number of bytes at the start of a file used to detect its encoding.
+const DETECT_SAMPLE_SIZE : usize = 64 * 1024;
+/// The number of bytes read at a time when searching.
+const SEARCH_WINDOW_SIZE : usize = 16 * 1024;
+/// The number of bytes on either side of a search window that anchors like
+/// `^`, `$` and `\b` need to see, which is the longest UTF-8 character.
+const SEARCH_CONTEXT : usize = 4;
+
+/// Tells apart the temporary files that hold decoded text.
+static NEXT_DECODED : AtomicUsize = AtomicUsize::new(0);
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 enum SourceFile {
-    /// The original file on disk.
+    /// The original file on disk, or a decoded copy of it if it isn't
+    /// UTF-8.
     Original,
     /// The append file, held in memory.
     Append
@@ -45,10 +76,22 @@ struct Piece {
     file_offset : usize,
     /// The length of the text in this Piece.
     length : usize,
-    /// The logical offset of the Piece.
+    /// The logical offset of the Piece. The piece table doesn't keep this
+    /// up to date for the pieces it stores; it is filled in whenever a
+    /// Piece is read out of it.
     logical_offset : usize,
 }
 
+impl Weighted for Piece {
+    fn weight(&self) -> usize {
+        self.length
+    }
+
+    fn locate(&mut self, _index : usize, offset : usize) {
+        self.logical_offset = offset;
+    }
+}
+
 impl Piece {
     /// Convert a logical offset, which is piece-table global, to an offset
     /// inside the Piece's file.
@@ -72,6 +115,151 @@ impl fmt::Display for Piece {
     }
 }
 
+/// Describes how the file on disk has changed since the PieceFile
+/// last read or wrote it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Conflict {
+    /// The file was rewritten in place. Text the PieceFile reads from
+    /// it can no longer be trusted.
+    Modified,
+    /// The file was replaced by a different one, like a checkout or a
+    /// formatter might do. The PieceFile still has the old one open.
+    Replaced,
+    /// The file no longer exists.
+    Deleted,
+}
+
+/// A change to the text made by moving through the undo tree or by
+/// replacing matches: `removed` bytes at `offset` were replaced by
+/// `inserted` bytes.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TextChange {
+    pub offset   : usize,
+    pub removed  : usize,
+    pub inserted : usize,
+}
+
+impl TextChange {
+    /// Combine this change with one made after it into a single change
+    /// that covers both.
+    pub fn then(&self, next : &TextChange) -> TextChange {
+        let start = cmp::min(self.offset, next.offset);
+        // Where the combined change ends before and after `next`.
+        let end   = cmp::max(self.offset + self.inserted, next.offset + next.removed);
+
+        TextChange {
+            offset   : start,
+            removed  : end - self.inserted + self.removed - start,
+            inserted : end - next.removed + next.inserted - start,
+        }
+    }
+}
+
+/// Everything that can go wrong when working with a PieceFile.
+#[derive(Debug)]
+pub enum FileError {
+    /// Reading or writing a file failed.
+    Io(io::Error),
+    /// The PieceFile isn't backed by a file on disk.
+    NoPath,
+    /// The file on disk changed since the PieceFile last read or wrote
+    /// it.
+    Conflict(Conflict),
+    /// The file on disk was modified in place while the PieceFile still
+    /// needed text from it.
+    OriginalLost,
+    /// A range of bytes reaches past the end of the file.
+    OutOfRange { offset : usize, length : usize, size : usize },
+    /// A seek would have moved before the start of the file.
+    NegativeSeek(i64),
+    /// Text that was read wasn't valid UTF-8.
+    InvalidUtf8 { offset : usize },
+    /// Two pieces that don't refer to contiguous text were about to be
+    /// merged, which means the piece table is broken.
+    NotContiguous { index : usize },
+    /// There is no recovery file to recover from.
+    NoRecovery,
+    /// The recovery file doesn't apply to the file on disk.
+    RecoveryMismatch,
+    /// The piece table or undo tree is inconsistent. See
+    /// `PieceFile::check_invariants`.
+    BrokenInvariant(String),
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FileError::Io(ref err) => write!(f, "{}", err),
+            FileError::NoPath => write!(f, "File has no path"),
+            FileError::Conflict(Conflict::Modified) => write!(f, "File was modified on disk"),
+            FileError::Conflict(Conflict::Replaced) => write!(f, "File was replaced on disk"),
+            FileError::Conflict(Conflict::Deleted)  => write!(f, "File was deleted from disk"),
+            FileError::OriginalLost => write!(f, "Text from the file on disk was lost"),
+            FileError::OutOfRange { offset, length, size } => {
+                write!(f, "Range {}+{} is past the end of the file ({} bytes)", offset, length, size)
+            },
+            FileError::NegativeSeek(offset) => write!(f, "Seek to negative offset {}", offset),
+            FileError::InvalidUtf8 { offset } => write!(f, "Text at {} isn't valid UTF-8", offset),
+            FileError::NotContiguous { index } => write!(f, "Pieces at {} aren't contiguous", index),
+            FileError::NoRecovery => write!(f, "No recovery file"),
+            FileError::RecoveryMismatch => write!(f, "Recovery file doesn't match the file"),
+            FileError::BrokenInvariant(ref reason) => write!(f, "PieceFile is broken: {}", reason),
+        }
+    }
+}
+
+impl error::Error for FileError {}
+
+impl From<io::Error> for FileError {
+    fn from(err : io::Error) -> FileError {
+        FileError::Io(err)
+    }
+}
+
+impl From<FileError> for io::Error {
+    fn from(err : FileError) -> io::Error {
+        let kind = match err {
+            FileError::Io(err)              => return err,
+            FileError::NoPath               => ErrorKind::InvalidInput,
+            FileError::Conflict(_)          => ErrorKind::Other,
+            FileError::OriginalLost         => ErrorKind::InvalidData,
+            FileError::OutOfRange { .. }    => ErrorKind::InvalidInput,
+            FileError::NegativeSeek(_)      => ErrorKind::InvalidInput,
+            FileError::InvalidUtf8 { .. }   => ErrorKind::InvalidData,
+            FileError::NotContiguous { .. } => ErrorKind::Other,
+            FileError::NoRecovery           => ErrorKind::InvalidInput,
+            FileError::RecoveryMismatch     => ErrorKind::InvalidData,
+            FileError::BrokenInvariant(_)   => ErrorKind::Other,
+        };
+
+        io::Error::new(kind, err)
+    }
+}
+
+/// The result of an operation on a PieceFile.
+pub type Result<T> = result::Result<T, FileError>;
+
+/// The properties of a file on disk that tell us whether it changed.
+#[derive(Debug, Clone, PartialEq)]
+struct DiskState {
+    modified : Option<time::SystemTime>,
+    size     : u64,
+    device   : u64,
+    inode    : u64,
+}
+
+impl DiskState {
+    /// Record the state of a file given its metadata.
+    pub fn new(metadata : &fs::Metadata) -> DiskState {
+        DiskState {
+            modified : metadata.modified().ok(),
+            size     : metadata.len(),
+            device   : metadata.dev(),
+            inode    : metadata.ino(),
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 enum Operation {
     Insert,
@@ -96,21 +284,29 @@ struct Action {
     merge_up   : bool,
     /// The instant this Action was initialized.
     timestamp : time::Instant,
+    /// The index of the action this one was made on top of, or None if it
+    /// was made on the original file.
+    parent : Option<usize>,
+    /// The indices of all actions made on top of this one. The last child is
+    /// the branch that `redo` follows.
+    children : Vec<usize>,
+    /// Actions in the same group are undone and redone together.
+    group : usize,
 }
 
 impl fmt::Display for Action {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "op={:?} {}+{} down={} up={}\n",
-               self.op,
-               self.offset,
-               self.length,
-               self.merge_down,
-               self.merge_up);
-        write!(f, "action pieces\n");
+        writeln!(f, "op={:?} {}+{} down={} up={}",
+                 self.op,
+                 self.offset,
+                 self.length,
+                 self.merge_down,
+                 self.merge_up)?;
+        writeln!(f, "action pieces")?;
         for piece in &self.pieces {
-            write!(f, "{}\n", piece);
+            writeln!(f, "{}", piece)?;
         }
-        write!(f, "end of action pieces\n")
+        writeln!(f, "end of action pieces")
     }
 }
 
@@ -118,33 +314,80 @@ impl fmt::Display for Action {
 /// until asked to.
 pub struct PieceFile {
     /// Stores all mutation operations of the file so that we can
-    /// undo.
+    /// undo. The actions form a tree: making an edit after undoing
+    /// starts a new branch rather than throwing away the undone edits.
     actions : Vec<Action>,
-    /// The in-memory append string. All edits will refer to bytes
+    /// The in-memory append buffer. All edits will refer to bytes
     /// stored here.
-    append_file : String,
-    /// The number of actions currently undone. Needed so
-    /// we can track `redo`.
-    history_offset : usize,
+    append_file : Vec<u8>,
+    /// The group new actions join while one is open.
+    group : Option<usize>,
+    /// How many times `begin_group` was called without `end_group`.
+    group_depth : usize,
+    /// The group the next action or group gets.
+    next_group : usize,
+    /// The index of the most recently applied action, or None if the
+    /// file is in its original state.
+    current : Option<usize>,
+    /// The indices of all actions made on the original file. Like
+    /// `Action::children`, the last one is the branch `redo` follows.
+    roots : Vec<usize>,
     /// The total size (in bytes) of the PieceFile.
     length : usize,
+    /// The state of the file on disk when it was last read or written.
+    disk : Option<DiskState>,
+    /// What `conflict` found the last time it looked at the disk, so that
+    /// reads don't have to. None if it hasn't looked since `disk` changed.
+    checked : Cell<Option<Option<Conflict>>>,
+    /// The encoding of the file on disk. The PieceFile itself always
+    /// holds UTF-8 and converts to this encoding when saving.
+    encoding : Encoding,
+    /// Records edits so they can be recovered if byt dies before they
+    /// are saved. Only PieceFiles with a path have one.
+    journal : Option<Journal>,
     /// The current offset for reads.
     offset : usize,
-    /// Stores all current Pieces.
-    piece_table : Vec<Piece>,
-    /// The seekable file reader.
-    reader : Option<BufReader<File>>,
+    /// The path of the file on disk, if there is one.
+    path : Option<String>,
+    /// Stores all current Pieces, ordered and indexed by their
+    /// logical offsets.
+    piece_table : Tree<Piece>,
+    /// Reads the original file.
+    reader : Option<FileSource>,
+    /// The edit that matches the file on disk.
+    saved_edit : usize,
+    /// Where the undo store was last written or read, along with how many
+    /// actions there were and which was current. Saving doesn't write the
+    /// store again if none of that changed.
+    stored_history : Option<(PathBuf, usize, Option<usize>)>,
+    /// Whether a journal left behind by an earlier session is waiting to
+    /// be recovered or discarded. Edits aren't journaled until it is, so
+    /// that we don't overwrite it.
+    recovery : bool,
+}
+
+impl Drop for PieceFile {
+    fn drop(&mut self) {
+        // A panic is exactly when the journal is needed.
+        if thread::panicking() {
+            return;
+        }
+
+        if let Some(ref mut journal) = self.journal {
+            journal.remove().ok();
+        }
+    }
 }
 
 impl fmt::Display for PieceFile {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "PieceFile\n");
-        write!(f, "history_offset={}\n", self.history_offset);
-        write!(f, "length={}\n", self.length);
-        write!(f, "offset={}\n", self.offset);
-        write!(f, "piece_table len({})\n", self.piece_table.len());
-        for piece in &self.piece_table {
-            write!(f, "{}\n", piece);
+        writeln!(f, "PieceFile")?;
+        writeln!(f, "current={:?}", self.current)?;
+        writeln!(f, "length={}", self.length)?;
+        writeln!(f, "offset={}", self.offset)?;
+        writeln!(f, "piece_table len({})", self.piece_table.len())?;
+        for piece in self.piece_table.iter() {
+            writeln!(f, "{}", piece)?;
         }
         write!(f, "end piece table")
         //write!(f, "actions len({})\n", self.actions.len());
@@ -161,12 +404,19 @@ impl PieceFile {
     // #################################
     /// Delete some text. Returns an action that represents
     /// the deletion.
-    fn _delete(&mut self, offset : usize, length : usize) -> Action {
+    fn _delete(&mut self, offset : usize, length : usize) -> Result<Action> {
+        if offset + length > self.length {
+            return Err(FileError::OutOfRange {
+                offset,
+                length,
+                size : self.length,
+            });
+        }
+
         let start_offset = offset;
         let end_offset   = offset + length;
         let start_index  = self.get_at_offset(start_offset);
         let end_index    = self.get_at_offset(end_offset);
-        let delete_size  = ((end_index - start_index) as usize) + 1;
         let num_pieces   = (end_index - start_index) + 1;
 
         let mut action = Action {
@@ -177,14 +427,18 @@ impl PieceFile {
             merge_down : false,
             merge_up   : false,
             timestamp  : time::Instant::now(),
+            parent     : None,
+            children   : Vec::new(),
+            group      : 0,
         };
 
-        // TODO: ensure we don't overflow
         self.length -= length;
 
+        self.write_journal(Record::Delete { offset, length });
+
         // Edge case : delete is embedded WITHIN a single piece
         if num_pieces == 1 {
-            let piece              = self.piece_table[start_index].clone();
+            let piece              = self.piece_table.get(start_index).unwrap();
             let piece_start_offset = piece.logical_offset;
             let piece_end_offset   = piece_start_offset + piece.length;
             let upper_size         = piece_end_offset - end_offset;
@@ -222,148 +476,205 @@ impl PieceFile {
                 });
             }
 
-            self.update_offsets(start_index);
-
-            return action;
+            return Ok(action);
         }
 
         // Deletes can affect multiple pieces if the user wants
         // to delete across piece boundaries.The code below
         // doesn't result in a net positive number of pieces
         // in the piece table. We do any deletion necessary
-        // in a second pass.
+        // in a second pass. Pieces are only changed once all of them
+        // have been read, because changing a piece moves every piece
+        // after it.
 
         // 1. Handle the piece the delete starts in.
-        {
-            let piece              = &mut self.piece_table[start_index];
+        let start_size = {
+            let piece              = self.piece_table.get(start_index).unwrap();
             let piece_start_offset = piece.logical_offset;
             let piece_end_offset   = piece_start_offset + piece.length;
             let upper_size         = piece_end_offset - start_offset;
             let lower_size         = start_offset - piece_start_offset;
-            piece.length = lower_size;
 
-            if upper_size > 0 {
+            // The piece was only split if part of it is left behind.
+            if lower_size > 0 {
                 action.merge_down = true;
             }
 
-            action.pieces.push(Piece {
-                file           : piece.file,
-                file_offset    : piece.file_offset + lower_size,
-                length         : upper_size,
-                logical_offset : piece_start_offset + lower_size,
-            });
-        }
+            if upper_size > 0 {
+                action.pieces.push(Piece {
+                    file           : piece.file,
+                    file_offset    : piece.file_offset + lower_size,
+                    length         : upper_size,
+                    logical_offset : piece_start_offset + lower_size,
+                });
+            }
+
+            lower_size
+        };
 
         // 2. Handle any pieces in between. They are deleted.
         if num_pieces > 2 {
             for index in start_index + 1 .. end_index {
-                let piece = &mut self.piece_table[index];
-                action.pieces.insert(0, piece.clone());
-                piece.length = 0;
+                let piece = self.piece_table.get(index).unwrap();
+                action.pieces.insert(0, piece);
             }
         }
 
         // 3. Handle the piece the delete ends in.
         {
-            let piece              = &mut self.piece_table[end_index];
+            let piece              = self.piece_table.get(end_index).unwrap();
             let piece_start_offset = piece.logical_offset;
             let piece_end_offset   = piece_start_offset + piece.length;
             let upper_size         = piece_end_offset - end_offset;
             let lower_size         = end_offset - piece_start_offset;
 
-            if lower_size > 0 {
+            if lower_size > 0 && upper_size > 0 {
                 action.merge_up = true;
             }
 
-            action.pieces.insert(0, Piece {
-                file           : piece.file,
-                file_offset    : piece.file_offset,
-                length         : lower_size,
-                logical_offset : piece_start_offset - lower_size,
-            });
+            // The delete may end exactly on this piece's boundary, in
+            // which case none of it was removed.
+            if lower_size > 0 {
+                action.pieces.insert(0, Piece {
+                    file           : piece.file,
+                    file_offset    : piece.file_offset,
+                    length         : lower_size,
+                    logical_offset : piece_start_offset,
+                });
+            }
 
-            piece.file_offset += lower_size;
-            piece.length       = upper_size;
+            self.piece_table.update(end_index, |piece| {
+                piece.file_offset += lower_size;
+                piece.length       = upper_size;
+            });
         }
 
-        // It's possible that we left zero-length pieces above. We should delete them.
-        self.piece_table.retain(|ref v| v.length > 0);
-        self.update_offsets(start_index);
+        self.piece_table.update(start_index, |piece| piece.length = start_size);
 
-        action
-    }
+        // It's possible that we left zero-length pieces above, and all
+        // of the pieces in between have to go. We go backwards so the
+        // indices of the pieces we haven't looked at yet don't change.
+        for index in (start_index .. end_index + 1).rev() {
+            let is_empty = index > start_index && index < end_index ||
+                           self.piece_table.get(index).unwrap().length == 0;
 
-    /// Get a Piece at a particular offset.
-    fn get_at_offset(&self, offset : usize) -> usize {
-        // TODO rewrite to be binary search as we have logical
-        // offsets now
-        for index in 0..self.piece_table.len() {
-            if !self.piece_table[index].contains(offset) {
-                continue;
+            if is_empty {
+                self.piece_table.remove(index);
             }
-
-            return index;
         }
 
-        if self.piece_table.len() == 0 {
-            return 0;
-        }
+        Ok(action)
+    }
 
-        self.piece_table.len() - 1
+    /// Get the index of the Piece at a particular offset. Offsets past
+    /// the end of the file belong to the last Piece.
+    fn get_at_offset(&self, offset : usize) -> usize {
+        match self.piece_table.find(offset) {
+            Some(index) => index,
+            None        => self.piece_table.len().saturating_sub(1),
+        }
     }
 
     /// Insert some text. Returns the action corresponding
     /// to the insert.
-    pub fn _insert(&mut self, text : &str, offset : usize) -> Action {
-        let length = text.len() as usize;
-        let append_offset = self.append_file.len() as usize;
+    fn _insert(&mut self, text : &str, offset : usize) -> Result<Action> {
+        let piece = self.append(text.as_bytes());
+        self.insert_action(piece, offset)
+    }
+
+    /// Add some bytes to the end of the append buffer. Returns a piece
+    /// that refers to them.
+    fn append(&mut self, bytes : &[u8]) -> Piece {
+        let append_offset = self.append_file.len();
 
-        self.append_file += text;
-        self.length      += length;
+        self.append_file.extend_from_slice(bytes);
 
-        let mut piece = Piece {
-            file   : SourceFile::Append,
-            file_offset : append_offset,
+        Piece {
+            file           : SourceFile::Append,
+            file_offset    : append_offset,
             logical_offset : 0, // Unknown right now
-            length,
-        };
+            length         : bytes.len(),
+        }
+    }
+
+    /// Insert a piece as a new edit. Returns the action corresponding
+    /// to the insert.
+    fn insert_action(&mut self, piece : Piece, offset : usize) -> Result<Action> {
+        let length = piece.length;
 
         let mut action = Action {
             op     : Operation::Insert,
             pieces : Vec::new(),
             offset,
             length,
-            // By convention inserts always have
-            // merge_down as true. An insert always
-            // splits a piece unless there isn't a piece
-            // above or below it.
+            // An insert only needs to be merged back
+            // together when it is undone if it split
+            // a piece into two parts.
             merge_down : false,
             merge_up   : false,
             timestamp  : time::Instant::now(),
+            parent     : None,
+            children   : Vec::new(),
+            group      : 0,
         };
 
         action.pieces.push(piece.clone());
+        action.merge_down = self._insert_piece(piece, offset)?;
+
+        Ok(action)
+    }
+
+    /// Insert a piece that refers to text that is already stored in one of
+    /// the source files. Returns true if an existing piece had to be split
+    /// in two to make room for it.
+    fn _insert_piece(&mut self, piece : Piece, offset : usize) -> Result<bool> {
+        let mut piece = piece;
+        let length    = piece.length;
+
+        if offset > self.length {
+            return Err(FileError::OutOfRange {
+                offset,
+                length : 0,
+                size   : self.length,
+            });
+        }
+
+        self.length += length;
+
+        if self.journal.is_some() && !self.recovery {
+            let record = match piece.file {
+                SourceFile::Append => Record::Insert {
+                    offset,
+                    text : self.append_file[piece.file_offset .. piece.file_offset + length].to_vec(),
+                },
+                SourceFile::Original => Record::Copy {
+                    offset,
+                    file_offset : piece.file_offset,
+                    length,
+                },
+            };
+
+            self.write_journal(record);
+        }
 
         // There are edge cases if you do an insert
         // at the beginning or end of the file.
         if offset == 0 {
             self.piece_table.insert(0, piece);
-            self.update_offsets(0);
-            return action;
+            return Ok(false);
         }
         else if offset + length == self.length {
             piece.logical_offset = self.length - length;
             self.piece_table.push(piece);
-
-            // No need to update offsets, this is the last piece
-            return action;
+            return Ok(false);
         }
 
         // The insertion may create as many as three pieces. It can
         // split a piece that already exists into two parts and then
         // goes in between them.
         let split_index = self.get_at_offset(offset);
-        let split_piece = self.piece_table.remove(split_index);
+        let split_piece = self.piece_table.get(split_index).unwrap();
+        self.piece_table.remove(split_index);
 
         let lower_size  = offset - split_piece.logical_offset;
         let upper_size  = (split_piece.logical_offset + split_piece.length) - offset;
@@ -388,439 +699,1529 @@ impl PieceFile {
             });
         }
 
-        if lower_size > 0 && upper_size > 0 {
-            action.merge_down = true;
-        }
-
-        self.update_offsets(0);
-
-        action
+        Ok(lower_size > 0 && upper_size > 0)
     }
 
-    /// Reads characters from a piece into a destination string.
+    /// Reads bytes from a piece into a destination buffer.
     /// The `offset` refers to logical offset in the whole piece
     /// table, not file-specific offset.
-    fn read_piece(&mut self, piece : Piece, offset : usize, num_bytes : usize, dest : &mut String) {
-        let piece_start_offset = piece.logical_offset;
-        let mut buf            = vec![0 as u8; num_bytes];
+    fn read_piece(&mut self, piece : Piece, offset : usize, num_bytes : usize, dest : &mut Vec<u8>) -> Result<()> {
+        let start_offset = piece.logical_to_file(offset);
 
         match piece.file {
             SourceFile::Append => {
-                let append_start_offset = piece.logical_to_file(offset);
-                let append_end_offset   = append_start_offset + num_bytes;
-                let append_bytes        = self.append_file.as_bytes();
-
-                if append_end_offset > append_bytes.len() {
-                    panic!(format!("Attempting to read more bytes than possible: {} > {}",
-                                   append_end_offset,
-                                   append_bytes.len()));
+                let end_offset   = start_offset + num_bytes;
+                let append_bytes = &self.append_file;
+
+                if end_offset > append_bytes.len() {
+                    return Err(FileError::OutOfRange {
+                        offset : start_offset,
+                        length : num_bytes,
+                        size   : append_bytes.len(),
+                    });
                 }
 
-                if append_start_offset == append_end_offset {
-                    let byte = append_bytes.get(append_start_offset).unwrap();
-                    buf[0] = *byte;
-                } else {
-                    let append_slice = append_bytes
-                        .get(append_start_offset ..
-                             append_end_offset)
-                        .unwrap();
-                    buf.copy_from_slice(append_slice);
-                }
+                dest.extend_from_slice(&append_bytes[start_offset .. end_offset]);
             },
             SourceFile::Original => {
-                let reader = self.reader.as_mut().unwrap();
-                reader.seek(SeekFrom::Start(piece.logical_to_file(offset) as u64));
-                reader.read(buf.as_mut_slice()).unwrap();
+                // Reading from a file that changed underneath us would
+                // give back garbage.
+                if self.last_conflict() == Some(Conflict::Modified) {
+                    return Err(FileError::Conflict(Conflict::Modified));
+                }
+
+                match self.reader {
+                    Some(ref mut reader) => reader.read_at(start_offset, num_bytes, dest)?,
+                    None                 => return Err(FileError::NoPath),
+                }
             },
         }
 
-        let mut index = buf.len();
-        let mut converted = str::from_utf8(&buf[0..index]);
+        Ok(())
+    }
 
-        // It's possible we'll stumble upon a boundary. If we do,
-        // reduce the number of bytes until all is well.
-        while converted.is_err() {
-            index -= 1;
-            converted = str::from_utf8(&buf[0..index]);
+    /// Get what `conflict` last found, only looking at the disk if it
+    /// hasn't since the disk state changed.
+    fn last_conflict(&self) -> Option<Conflict> {
+        match self.checked.get() {
+            Some(conflict) => conflict,
+            None           => self.conflict(),
         }
-
-        dest.push_str(converted.unwrap());
     }
 
-    /// Remove any edits newer than the current, historical
-    /// version of the file. E.g if the user undoes a few
-    /// changes, remove their corresponding actions so
-    /// they cannot be redone.
-    fn remove_newer_history(&mut self) {
-        while self.history_offset > 0 {
-            self.actions.pop();
-            self.history_offset -= 1;
+    /// Refuse to edit a file that was modified in place on disk. Whatever
+    /// shows the file reads the text around an edit, which might no longer
+    /// be there.
+    fn check_editable(&self) -> Result<()> {
+        match self.conflict() {
+            Some(Conflict::Modified) => Err(FileError::Conflict(Conflict::Modified)),
+            _                        => Ok(()),
         }
     }
 
-    /// Update the logical offsets starting at a certain index.
-    fn update_offsets(&mut self, start_index : usize) {
-        let length = self.piece_table.len();
-
-        // Don't do anything if this is the last index
-        if length == 0 || start_index >= length {
+    /// Add a record to the journal. If the journal can't be written to
+    /// we give up on it rather than fail the edit.
+    fn write_journal(&mut self, record : Record) {
+        if self.recovery {
             return;
         }
 
-        // Don't trust the logical offset of the first piece if
-        // we're updating from that.
-        let mut offset = if start_index == 0 {
-            0
-        } else {
-            self.piece_table[start_index].logical_offset
+        let failed = match self.journal {
+            Some(ref mut journal) => journal.write(&record).is_err(),
+            None                  => false,
         };
 
-        for index in start_index .. self.piece_table.len() {
-            let piece = &mut self.piece_table[index];
-            piece.logical_offset = offset;
-            offset += piece.length;
+        if failed {
+            self.journal = None;
         }
     }
 
-    // ###############################
-    // P U B L I C  F U N C T I O N S
-    // ###############################
+    /// Make the edit a journal record describes.
+    fn replay(&mut self, record : Record) -> Result<()> {
+        let mismatch = FileError::RecoveryMismatch;
 
-    /// Delete some bytes in the PieceFile.
-    pub fn delete(&mut self, offset : usize, length : usize) {
-        let action = self._delete(offset, length);
-        self.remove_newer_history();
-        self.update_offsets(0);
-        self.actions.push(action);
-    }
+        match record {
+            Record::Insert { offset, text } => {
+                if offset > self.length {
+                    return Err(mismatch);
+                }
 
-    /// Create a new empty PieceFile.
-    pub fn empty() -> io::Result<Box<PieceFile>> {
-        let piece_file = PieceFile {
-            actions        : Vec::new(),
-            append_file    : String::new(),
-            history_offset : 0,
-            length         : 0,
-            offset         : 0,
-            piece_table    : Vec::new(),
-            reader         : None,
-        };
+                let piece  = self.append(&text);
+                let action = self.insert_action(piece, offset)?;
+                self.record(action);
+            },
+            Record::Copy { offset, file_offset, length } => {
+                let original = self.reader.as_ref().map_or(0, |reader| reader.len());
 
-        Ok(Box::new(piece_file))
+                if offset > self.length || file_offset + length > original {
+                    return Err(mismatch);
+                }
+
+                let piece = Piece {
+                    file           : SourceFile::Original,
+                    file_offset,
+                    length,
+                    logical_offset : 0,
+                };
+                let action = self.insert_action(piece, offset)?;
+                self.record(action);
+            },
+            Record::Delete { offset, length } => {
+                if offset + length > self.length {
+                    return Err(mismatch);
+                }
+
+                self.delete(offset, length)?;
+            },
+        }
+
+        Ok(())
     }
 
-    /// Insert some text. Returns the action corresponding
-    /// to the insert.
-    pub fn insert(&mut self, text : &str, offset : usize) {
-        let action = self._insert(text, offset);
-        self.remove_newer_history();
+    /// Add a new action to the undo tree on top of the current one and
+    /// make it the current action.
+    fn record(&mut self, action : Action) {
+        let mut action = action;
+        let index      = self.actions.len();
+
+        action.parent = self.current;
+        action.group  = match self.group {
+            Some(group) => group,
+            None        => {
+                self.next_group += 1;
+                self.next_group - 1
+            },
+        };
+
         self.actions.push(action);
+        self.children_of_mut(self.current).push(index);
+        self.current = Some(index);
     }
 
-    /// Save the PieceFile's contents to disk. Returns
-    /// the number of bytes written.
-    pub fn save(&mut self) -> io::Result<usize> {
-        if self.reader.is_none() {
-            return Err(Error::new(ErrorKind::InvalidInput, "Empty PieceFile"));
+    /// Get the children of a node in the undo tree. None refers to
+    /// the original file.
+    fn children_of(&self, node : Option<usize>) -> &Vec<usize> {
+        match node {
+            Some(index) => &self.actions[index].children,
+            None        => &self.roots,
         }
+    }
 
-        let mut text : Box<String>;
-        {
-            let length = self.len();
-            text = self.read_at(0, length)?;
+    /// Get a mutable reference to the children of a node in the undo tree.
+    fn children_of_mut(&mut self, node : Option<usize>) -> &mut Vec<usize> {
+        match node {
+            Some(index) => &mut self.actions[index].children,
+            None        => &mut self.roots,
         }
+    }
 
-        let mut file = self.reader.as_ref().unwrap().get_ref();
-        file.write_all(text.as_bytes())?;
+    /// Make an action the branch that `redo` follows from its parent.
+    fn prefer_branch(&mut self, index : usize) {
+        let parent   = self.actions[index].parent;
+        let children = self.children_of_mut(parent);
 
-        Ok(self.len())
+        children.retain(|&child| child != index);
+        children.push(index);
     }
 
-    /// Save the PieceFile to disk given a filename. Return the
-    /// number of bytes written.
-    pub fn save_as(&mut self, filename : &str) -> io::Result<usize> {
-        let length = self.len();
-        let text   = self.read_at(0, length)?;
+    /// Perform an action again. The action must be a child of the
+    /// current action.
+    fn apply(&mut self, index : usize) -> Result<TextChange> {
+        let action     = self.actions[index].clone();
+        let mut change = TextChange { offset : action.offset, removed : 0, inserted : 0 };
 
-        let mut file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .open(filename).unwrap();
+        if action.op == Operation::Insert {
+            self._insert_piece(action.pieces[0].clone(), action.offset)?;
+            change.inserted = action.length;
+        } else {
+            self._delete(action.offset, action.length)?;
+            change.removed = action.length;
+        }
 
-        file.write_all(text.as_bytes())?;
+        self.current = Some(index);
+        self.prefer_branch(index);
 
-        Ok(length)
+        Ok(change)
     }
 
-    /// Check if this PieceFile refers to any file.
-    pub fn is_empty(&self) -> bool {
-        self.reader.is_none()
-    }
+    /// Reverse the effects of the current action and move to its parent.
+    fn revert(&mut self, index : usize) -> Result<TextChange> {
+        let action     = self.actions[index].clone();
+        let mut change = TextChange { offset : action.offset, removed : 0, inserted : 0 };
 
-    /// Open a new PieceFile. If the file doesn't exist, it is created.
-    pub fn open(path : &str) -> io::Result<Box<PieceFile>> {
-        let file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .open(path).unwrap();
+        if action.op == Operation::Insert {
+            self._delete(action.offset, action.length)?;
+            change.removed = action.length;
+        } else {
+            change.inserted = action.length;
+
+            // Delete operations have a list of the pieces they removed,
+            // stored in reverse order. Inserting each at the same offset
+            // puts them back in the right order.
+            for piece in action.pieces {
+                self._insert_piece(piece, action.offset)?;
+            }
 
-        // Get the length of the file to initialize the first Piece
-        let size = file.metadata().unwrap().len() as usize;
+            if action.merge_up {
+                self.merge_at(action.offset + action.length)?;
+            }
+        }
 
-        let mut piece_file = PieceFile {
-            actions        : Vec::new(),
-            append_file    : String::new(),
-            history_offset : 0,
-            length         : size,
-            offset         : 0,
-            piece_table    : Vec::new(),
-            reader         : Some(BufReader::new(file)),
-        };
+        // We want the piece table to look EXACTLY like it did
+        // before the operation was performed, so any pieces the
+        // action split apart are glued back together.
+        if action.merge_down {
+            self.merge_at(action.offset)?;
+        }
 
-        piece_file.piece_table.push(Piece {
-            file : SourceFile::Original,
-            file_offset : 0,
-            length : size as usize,
-            logical_offset : 0,
-        });
+        self.current = action.parent;
+        self.prefer_branch(index);
 
-        Ok(Box::new(piece_file))
+        Ok(change)
     }
 
-    pub fn len(&self) -> usize {
-        self.length
-    }
+    /// Merge the pieces on either side of a logical offset if the text
+    /// they refer to is contiguous in the same file.
+    fn merge_at(&mut self, offset : usize) -> Result<()> {
+        if offset == 0 || offset >= self.length {
+            return Ok(());
+        }
 
-    /// Read characters from the buffer. The result is guaranteed to
-    /// contain only whole UTF-8 graphemes and have at MOST `num_bytes`.
-    /// If taking the provided number of bytes would result in a panic
-    /// (i.e if it falls on a UTF8 grapheme boundary) then it takes
-    /// one grapheme fewer.
-    pub fn read(&mut self, num_bytes : usize) -> io::Result<Box<String>> {
-        let mut result    = Box::new(String::new());
-        let mut num_bytes = num_bytes;
+        let index = self.get_at_offset(offset);
 
-        if num_bytes == 0 {
-            return Ok(result);
+        if index == 0 || self.piece_table.get(index).unwrap().logical_offset != offset {
+            return Ok(());
         }
 
-        let start_offset = self.offset;
+        {
+            let lower = self.piece_table.get(index - 1).unwrap();
+            let upper = self.piece_table.get(index).unwrap();
 
-        // Don't allow the user to read past the end of the piece file.
-        if start_offset + num_bytes > self.len() {
-            return Err(Error::new(ErrorKind::InvalidInput, "Requested too many bytes"));
+            if lower.file != upper.file ||
+               lower.file_offset + lower.length != upper.file_offset {
+                return Ok(());
+            }
         }
 
-        let start_index  = self.get_at_offset(start_offset);
-        // Often you'll see the ending offset be exclusive of the rest of
-        // the selection. Here we want it to be end inclusive, so we subtract
-        // one from the offset. Off-by-one errors are hard.
-        let end_offset   = self.offset + num_bytes - 1;
-        let end_index    = self.get_at_offset(end_offset);
+        self.merge_pieces(index - 1)
+    }
 
-        assert!(self.piece_table[end_index].contains(end_offset));
+    /// Check whether any piece, including those in the undo tree, refers
+    /// to the original file.
+    fn references_original(&self) -> bool {
+        let is_original = |piece : &Piece| piece.file == SourceFile::Original;
 
-        let num_pieces   = end_index - start_index + 1;
+        self.piece_table.iter().any(|piece| is_original(&piece)) ||
+        self.actions.iter().any(|action| action.pieces.iter().any(&is_original))
+    }
 
-        // Same as delete. There is an edge case where we read solely inside
-        // of a piece.
-        if num_pieces == 1 {
-            let piece = self.piece_table[start_index].clone();
-            self.read_piece(piece, start_offset, num_bytes, &mut result);
-            return Ok(result);
-        }
+    /// Copy the text an `Original` piece refers to into the append
+    /// buffer and get a piece that refers to the copy.
+    fn copy_original(&mut self, piece : &Piece) -> Result<Piece> {
+        let append_offset = self.append_file.len();
+        let reader        = match self.reader {
+            Some(ref mut reader) => reader,
+            None                 => return Err(FileError::NoPath),
+        };
 
-        // 1. Handle the piece the read starts in.
-        {
-            let piece = self.piece_table[start_index].clone();
-            let piece_end_offset = piece.logical_offset + piece.length;
-            let piece_read_bytes = piece_end_offset - start_offset;
+        reader.read_at(piece.file_offset, piece.length, &mut self.append_file)?;
 
-            self.read_piece(piece, start_offset, piece_read_bytes, &mut result);
-        }
+        let mut piece     = piece.clone();
+        piece.file        = SourceFile::Append;
+        piece.file_offset = append_offset;
 
-        // 2. Handle all of the pieces between the first and the last.
-        if num_pieces > 2 {
-            let mut piece_read_bytes   : usize;
-            let mut piece_start_offset : usize;
+        Ok(piece)
+    }
 
-            for index in start_index + 1 .. end_index {
-                // TODO move this allocation out?
-                let piece = self.piece_table[index].clone();
+    /// Point the PieceFile at a freshly saved copy of its contents.
+    ///
+    /// Every piece in the piece table is still valid, but `Original`
+    /// pieces now have to refer to the new file, in which the text sits
+    /// at its logical offset after the byte order mark. If the file isn't
+    /// UTF-8 it is decoded again, and the text sits at its logical offset
+    /// in the decoded copy. Text from
+    /// the old file that is only referenced by the undo tree is always
+    /// copied so that undo keeps working.
+    fn rebase(&mut self, path : &str) -> Result<()> {
+        let mut file = File::open(path)?;
+        let metadata = file.metadata()?;
+
+        for index in 0 .. self.actions.len() {
+            for piece_index in 0 .. self.actions[index].pieces.len() {
+                let piece = self.actions[index].pieces[piece_index].clone();
+
+                if piece.file != SourceFile::Original {
+                    continue;
+                }
 
-                piece_read_bytes   = piece.length;
-                piece_start_offset = piece.logical_offset;
+                self.actions[index].pieces[piece_index] = self.copy_original(&piece)?;
+            }
+        }
 
-                self.read_piece(
-                    piece,
-                    piece_start_offset,
-                    piece_read_bytes,
-                    &mut result);
+        // The new file holds exactly the text in the piece table, though
+        // it might have to be decoded first.
+        let (source, bom_length) = if self.encoding.is_utf8() {
+            (file, self.encoding.bom().len())
+        } else {
+            (PieceFile::decode_to_temp(&mut file, self.encoding)?, 0)
+        };
+
+        let mut pieces = Vec::with_capacity(self.piece_table.len());
+
+        for mut piece in self.piece_table.to_vec() {
+            if piece.file == SourceFile::Original {
+                piece.file_offset = piece.logical_offset + bom_length;
             }
+
+            pieces.push(piece);
         }
 
-        // 3. Handle the piece the read ends in.
-        {
-            let piece = self.piece_table[end_index].clone();
-            // In this specific case, we need to re-add the one back in.
-            // This is because read_piece does its own subtraction by one
-            // so we don't need it in this specific calculation.
-            // I understand why this might be confusing, but it works.
-            let piece_start_offset = piece.logical_offset;
-            let piece_read_bytes   = end_offset - piece_start_offset + 1;
+        self.piece_table = Tree::from_vec(pieces);
 
-            self.read_piece(
-                piece,
-                piece_start_offset,
-                piece_read_bytes,
-                &mut result);
+        // Everything in the old journal is saved now.
+        if let Some(ref mut journal) = self.journal {
+            journal.remove().ok();
         }
 
-        assert_eq!(result.len(), num_bytes);
+        self.disk    = Some(DiskState::new(&metadata));
+        self.checked.set(None);
+        self.journal = Some(Journal::new(path, Header::new(&metadata)));
+        self.path    = Some(String::from(path));
+        self.reader  = Some(FileSource::new(source)?);
 
-        Ok(result)
+        Ok(())
     }
 
-    /// Read bytes from an offset.
-    pub fn read_at(&mut self, offset : usize, num_bytes : usize) -> io::Result<Box<String>> {
-        self.seek(SeekFrom::Start(offset as u64));
-        self.read(num_bytes)
-    }
+    /// Decode a file that isn't UTF-8 into a temporary file, which is read
+    /// in its place so that the decoded text doesn't have to be kept in
+    /// memory. The temporary file is deleted right away, so it goes away
+    /// for good once it is closed.
+    fn decode_to_temp(file : &mut File, encoding : Encoding) -> Result<File> {
+        let path = env::temp_dir().join(format!("byt-{}-{}.decoded",
+                                                process::id(),
+                                                NEXT_DECODED.fetch_add(1, Ordering::SeqCst)));
 
-    /// Attempt to merge two pieces together given the index of the lower
-    /// piece.
-    fn merge_pieces(&mut self, index : usize) {
-        let upper_index        = index + 1;
-        let upper_piece        = self.piece_table[upper_index].clone();
-        let upper_start_offset = upper_piece.file_offset;
+        let temp = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
 
-        {
-            let lower_piece        = &mut self.piece_table[index];
-            let lower_end_offset   = lower_piece.file_offset + lower_piece.length;
+        fs::remove_file(&path)?;
 
-            // Something is amiss. Don't merge noncontiguous pieces.
-            if lower_end_offset != upper_start_offset {
-                panic!("Attempting to merge noncontiguous pieces!");
-            }
+        let mut writer = BufWriter::new(temp);
+        decode_stream(file, &mut writer, encoding)?;
 
-            lower_piece.length += upper_piece.length;
+        match writer.into_inner() {
+            Ok(temp) => Ok(temp),
+            Err(err) => Err(FileError::Io(err.into_error())),
         }
+    }
 
-        self.piece_table.remove(upper_index);
-        self.update_offsets(index);
-    }
+    /// Write the contents of the PieceFile to a new temporary file and
+    /// sync it to disk. The temporary file takes on the permissions of
+    /// `target` if it exists. The text is hashed with `hasher` on its way
+    /// out.
+    fn write_temp(&mut self, temp : &Path, target : &Path, hasher : &mut Hasher) -> Result<usize> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(temp)?;
 
-    /// Redo an undone action.
-    ///
-    /// Will do nothing if there is nothing to be redone.
-    pub fn redo(&mut self) {
-        if self.actions.len() == 0 || self.history_offset == 0 {
-            return;
+        if let Ok(metadata) = fs::metadata(target) {
+            fs::set_permissions(temp, metadata.permissions())?;
         }
 
-        let action_index = self.actions.len() - self.history_offset;
-        let action       = self.actions[action_index].clone();
-        let index        = self.get_at_offset(action.offset);
-        let last_index   = index + action.pieces.len() - 1;
+        let mut writer  = BufWriter::new(file);
+        let length      = self.len();
+        let seek        = self.offset;
+        let bom         = self.encoding.bom();
+        let mut written = bom.len();
 
-        self.history_offset -= 1;
+        writer.write_all(bom)?;
 
-        if action.op == Operation::Insert {
-            let mut insert_string = String::new();
-            let piece             = action.pieces[0].clone();
-            let piece_offset      = piece.logical_offset;
-            let piece_length      = piece.length;
+        if self.encoding.is_utf8() {
+            // Go through the bytes rather than text so that files that
+            // aren't valid UTF-8 come out exactly as they went in.
+            let mut offset = 0;
 
-            // Unfortunately, the easiest way to do this is to read
-            // from the piece and then insert it like we do below.
-            self.read_piece(
-                piece,
-                piece_offset,
-                piece_length,
-                &mut insert_string);
+            while offset < length {
+                let num_bytes = cmp::min(SAVE_CHUNK_SIZE, length - offset);
+                let bytes     = self.read_bytes_at(offset, num_bytes)?;
 
-            self._insert(insert_string.as_str(), action.offset);
+                hasher.write(&bytes);
+                writer.write_all(&bytes)?;
+                offset += num_bytes;
+            }
+
+            written += length;
         } else {
-            self._delete(action.offset, action.length);
+            // A character split between chunks waits for the rest of it.
+            let mut offset  = 0;
+            let mut pending = Vec::new();
+
+            while offset < length {
+                let num_bytes = cmp::min(SAVE_CHUNK_SIZE, length - offset);
+                let bytes     = self.read_bytes_at(offset, num_bytes)?;
+
+                hasher.write(&bytes);
+                pending.extend(bytes);
+                offset += num_bytes;
+
+                let complete = match str::from_utf8(&pending) {
+                    Err(err) if err.error_len().is_none() && offset < length => err.valid_up_to(),
+                    _                                                        => pending.len(),
+                };
+
+                let bytes = encode(&pending[.. complete], self.encoding)?;
+
+                writer.write_all(&bytes)?;
+                written += bytes.len();
+                pending.drain(.. complete);
+            }
         }
+
+        self.offset = seek;
+
+        let file = match writer.into_inner() {
+            Ok(file) => file,
+            Err(err) => return Err(FileError::Io(err.into_error())),
+        };
+
+        file.sync_all()?;
+
+        Ok(written)
+    }
+
+    // ###############################
+    // P U B L I C  F U N C T I O N S
+    // ###############################
+
+    /// Delete some bytes in the PieceFile.
+    pub fn delete(&mut self, offset : usize, length : usize) -> Result<()> {
+        self.check_editable()?;
+
+        let action = self._delete(offset, length)?;
+        self.record(action);
+        Ok(())
     }
 
-    /// Undo the most recent change to the buffer.
+    /// Create a new empty PieceFile.
+    pub fn empty() -> Result<Box<PieceFile>> {
+        let piece_file = PieceFile {
+            actions        : Vec::new(),
+            append_file    : Vec::new(),
+            current        : None,
+            group          : None,
+            group_depth    : 0,
+            next_group     : 0,
+            roots          : Vec::new(),
+            disk           : None,
+            checked        : Cell::new(None),
+            encoding       : Encoding::Utf8,
+            journal        : None,
+            length         : 0,
+            offset         : 0,
+            path           : None,
+            piece_table    : Tree::new(),
+            reader         : None,
+            recovery       : false,
+            saved_edit     : 0,
+            stored_history : None,
+        };
+
+        Ok(Box::new(piece_file))
+    }
+
+    /// Insert some text. Returns the action corresponding
+    /// to the insert.
+    pub fn insert(&mut self, text : &str, offset : usize) -> Result<()> {
+        self.check_editable()?;
+
+        let action = self._insert(text, offset)?;
+        self.record(action);
+        Ok(())
+    }
+
+    /// Save the PieceFile's contents to disk. Returns
+    /// the number of bytes written.
     ///
-    /// In the future it might be worthwhile to make this into
-    /// a tree like vim does it, but frankly I never use that feature
-    /// and don't find it that useful.
+    /// The file is replaced atomically; see `save_as`.
+    pub fn save(&mut self) -> Result<usize> {
+        let path = match self.path.clone() {
+            Some(path) => path,
+            None       => return Err(FileError::NoPath),
+        };
+
+        // Don't clobber changes someone else made.
+        if let Some(conflict) = self.conflict() {
+            return Err(FileError::Conflict(conflict));
+        }
+
+        self.save_as(path.as_str())
+    }
+
+    /// Save the PieceFile to disk given a filename. Return the
+    /// number of bytes written. The PieceFile refers to the new
+    /// file from then on.
     ///
-    /// Will do nothing if there is nothing to be undone.
-    pub fn undo(&mut self) {
-        if self.actions.len() == 0 ||
-           self.history_offset == self.actions.len() {
-            return;
+    /// The contents are written to a temporary file in the same
+    /// directory which is then renamed over the target, so a crash
+    /// halfway through never leaves a partially written file behind.
+    /// If the target already exists its permissions are kept.
+    pub fn save_as(&mut self, filename : &str) -> Result<usize> {
+        if self.conflict() == Some(Conflict::Modified) {
+            return Err(FileError::Conflict(Conflict::Modified));
         }
 
-        let action_index = self.actions.len() - 1 - self.history_offset;
-        let action       = self.actions[action_index].clone();
-        let index        = self.get_at_offset(action.offset);
-        let last_index   = index + action.pieces.len() - 1;
+        let target    = Path::new(filename);
+        let directory = match target.parent() {
+            Some(parent) if parent != Path::new("") => parent.to_path_buf(),
+            _                                       => PathBuf::from("."),
+        };
+        let name = match target.file_name() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None       => return Err(io::Error::new(ErrorKind::InvalidInput, "Invalid filename").into()),
+        };
+        let temp = directory.join(format!(".{}.{}.byt", name, process::id()));
 
-        self.history_offset += 1;
+        // The undo store checks the text against a hash of it, which is
+        // cheapest to find while the text is passing through anyway.
+        let mut hasher = Hasher::new();
+        let written    = self.write_temp(&temp, target, &mut hasher);
 
-        if action.op == Operation::Insert {
-            // Inserts are simple. Just remove the piece and merge.
-            self.piece_table.remove(index);
-            self.length -= action.length;
-        } else {
-            // Delete operations should have a list of pieces that
-            // they removed.
-
-            // There is an edge case here where the delete completely
-            // removed the last part of a piece. If this happens, we
-            // can't just insert its pieces at the index specified
-            // by the action because that's already the subsequent
-            // piece, not the piece the delete occurred in. As a result
-            // we have to be careful to add the action's Piece _after_
-            // the Piece in `index` and then merge them together.
-            let is_single_end_delete = action.pieces.len() == 1 &&
-                                       !action.merge_up;
-
-            let insert_index = {
-                // Handle edge case where the delete happened at the
-                // end of a single piece
-                if is_single_end_delete {
-                    index + 1
+        if written.is_err() {
+            fs::remove_file(&temp).ok();
+            return written;
+        }
+
+        fs::rename(&temp, target)?;
+
+        // Make sure the rename itself makes it to disk. Not every
+        // platform lets you sync a directory, so this is best effort.
+        if let Ok(directory) = File::open(&directory) {
+            directory.sync_all().ok();
+        }
+
+        self.rebase(filename)?;
+        self.saved_edit = self.current_edit();
+
+        // The file is saved either way, so an undo store we can't write
+        // only costs the history.
+        history::store(self, hasher.finish()).ok();
+
+        written
+    }
+
+    /// Check whether the file on disk changed since the PieceFile last read
+    /// or wrote it. Returns None if it didn't or if there is no file.
+    ///
+    /// This looks at the disk every time. Reads only do so if this hasn't
+    /// been called since the disk state last changed, so it should be
+    /// called whenever the file might have changed, like before drawing it.
+    pub fn conflict(&self) -> Option<Conflict> {
+        let (path, disk) = match (self.path.as_ref(), self.disk.as_ref()) {
+            (Some(path), Some(disk)) => (path, disk),
+            _                        => return None,
+        };
+
+        let conflict = match fs::metadata(path) {
+            Ok(metadata) => {
+                let current = DiskState::new(&metadata);
+
+                if current == *disk {
+                    None
+                } else if current.device != disk.device || current.inode != disk.inode {
+                    Some(Conflict::Replaced)
                 } else {
-                    index
+                    Some(Conflict::Modified)
                 }
+            },
+            Err(_) => Some(Conflict::Deleted),
+        };
+
+        self.checked.set(Some(conflict));
+        conflict
+    }
+
+    /// Resolve a conflict by keeping the PieceFile's contents. The next
+    /// save overwrites whatever is on disk.
+    ///
+    /// Fails if the file was modified in place and the PieceFile still
+    /// needs text from it, as that text is gone.
+    pub fn keep_buffer(&mut self) -> Result<()> {
+        match self.conflict() {
+            None => {},
+            Some(Conflict::Deleted) => {
+                self.disk = None;
+                self.checked.set(None);
+            },
+            Some(conflict) => {
+                if conflict == Conflict::Modified && self.references_original() {
+                    return Err(FileError::OriginalLost);
+                }
+
+                let path  = self.path.clone().unwrap();
+                self.disk = Some(DiskState::new(&fs::metadata(path)?));
+                self.checked.set(None);
+            },
+        }
+
+        Ok(())
+    }
+
+    /// Discard all changes, including the undo history, and read the file
+    /// from disk again.
+    pub fn reload(&mut self) -> Result<()> {
+        let path = match self.path.clone() {
+            Some(path) => path,
+            None       => return Err(FileError::NoPath),
+        };
+
+        // Our own journal would look like one left behind by a crash.
+        if let Some(ref mut journal) = self.journal {
+            journal.remove().ok();
+        }
+
+        *self = *PieceFile::open(path.as_str())?;
+
+        Ok(())
+    }
+
+    /// Check if this PieceFile refers to any file.
+    pub fn is_empty(&self) -> bool {
+        self.reader.is_none()
+    }
+
+    /// Check whether an earlier session left a journal of unsaved edits
+    /// behind for this file.
+    pub fn has_recovery(&self) -> bool {
+        self.recovery
+    }
+
+    /// Replay the edits in a journal left behind by an earlier session
+    /// onto the file as it was opened. Any edits made since opening the
+    /// file are kept in the undo tree. The journal is deleted, and the edits are
+    /// journaled again as they are replayed.
+    ///
+    /// Fails with `FileError::RecoveryMismatch` if the file changed since
+    /// the journal was written.
+    pub fn recover(&mut self) -> Result<()> {
+        let path = match self.path.clone() {
+            Some(path) => path,
+            None       => return Err(FileError::NoPath),
+        };
+
+        if !self.recovery {
+            return Err(FileError::NoRecovery);
+        }
+
+        let (header, records) = Journal::read(path.as_str())?;
+
+        if header != Header::new(&fs::metadata(path.as_str())?) || self.conflict().is_some() {
+            return Err(FileError::RecoveryMismatch);
+        }
+
+        // Edits made since opening the file were never journaled, so
+        // undo them before the journal starts recording again.
+        let saved_edit = self.saved_edit;
+        self.goto_edit(saved_edit)?;
+
+        Journal::remove_file(path.as_str())?;
+        self.recovery = false;
+
+        // The recovered edits are undone as one.
+        self.begin_group();
+
+        let result = records.into_iter().try_for_each(|record| self.replay(record));
+
+        self.end_group();
+
+        result
+    }
+
+    /// Delete a journal left behind by an earlier session without
+    /// replaying it.
+    pub fn discard_recovery(&mut self) -> Result<()> {
+        if let Some(ref path) = self.path {
+            if self.recovery {
+                Journal::remove_file(path.as_str())?;
+            }
+        }
+
+        self.recovery = false;
+
+        Ok(())
+    }
+
+    /// Find every match of a pattern that starts between `start` and
+    /// `end`. Matches don't overlap.
+    pub fn find_all(&mut self, pattern : &Pattern, start : usize, end : usize) -> Result<Vec<Match>> {
+        let mut matches = Vec::new();
+        let mut offset  = start;
+
+        while offset < end {
+            match self.find_next(pattern, offset)? {
+                Some(found) if found.start < end => {
+                    // Step over empty matches so we don't find them again.
+                    offset = if found.is_empty() { found.end + 1 } else { found.end };
+                    matches.push(found);
+                },
+                _ => break,
+            }
+        }
+
+        Ok(matches)
+    }
+
+    /// Find the first match of a pattern that starts at or after `from`.
+    pub fn find_next(&mut self, pattern : &Pattern, from : usize) -> Result<Option<Match>> {
+        let found = self.search_forward(pattern, from, None)?;
+        Ok(found.map(|(found, _)| found))
+    }
+
+    /// Find the last match of a pattern that starts before `before`.
+    pub fn find_prev(&mut self, pattern : &Pattern, before : usize) -> Result<Option<Match>> {
+        let length     = self.len();
+        let size       = cmp::max(SEARCH_WINDOW_SIZE, 2 * (pattern.overlap() + SEARCH_CONTEXT));
+        let mut reach  = pattern.overlap() + SEARCH_CONTEXT;
+        let mut before = cmp::min(before, length + 1);
+
+        while before > 0 {
+            // Look at the matches that start between `first` and `before`.
+            // The window reaches past `before` so that they fit in it.
+            let first  = before.saturating_sub(size);
+            let start  = first.saturating_sub(SEARCH_CONTEXT);
+            let end    = cmp::min(length, before + reach);
+            let at_end = end == length;
+            let window = self.read_bytes_at(start, end - start)?;
+
+            let mut last = None;
+            let mut at   = first - start;
+
+            while let Some(found) = pattern.find_at(&window, at) {
+                if start + found.start >= before {
+                    break;
+                }
+
+                last = Some(found.shift(start));
+                at   = found.start + 1;
+
+                if at > window.len() {
+                    break;
+                }
+            }
+
+            match last {
+                Some(found) if at_end || found.end + SEARCH_CONTEXT <= end => return Ok(Some(found)),
+                // The match might go on past the window.
+                Some(_) => reach *= 2,
+                None    => before = first,
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Replace every match of a pattern that lies between `start` and `end`
+    /// with `replacement`, which can refer to the pattern's capture groups
+    /// as `Pattern::expand` describes. Every match is found before anything
+    /// is replaced, and the replacements are undone as one. Returns the
+    /// number of matches that were replaced.
+    pub fn replace_all(&mut self, pattern : &Pattern, replacement : &str, start : usize, end : usize) -> Result<usize> {
+        self.check_editable()?;
+
+        let mut replacements = Vec::new();
+        let mut offset       = start;
+
+        while offset < end {
+            let (found, text) = match self.search_forward(pattern, offset, Some(replacement))? {
+                Some((found, text)) if found.end <= end => (found, text),
+                _                                       => break,
             };
 
-            for piece in action.pieces {
-                self.piece_table.insert(insert_index, piece);
+            // Step over empty matches so we don't find them again.
+            offset = if found.is_empty() { found.end + 1 } else { found.end };
+            replacements.push((found, PieceFile::replacement_text(found, text)?));
+        }
+
+        self.replace_matches(&replacements)?;
+
+        Ok(replacements.len())
+    }
+
+    /// Replace the matches of a pattern that start in each of `ranges`
+    /// with `replacement`, as in `replace_all`, or only the first one in
+    /// each unless `global` is set. The ranges are in order and don't
+    /// overlap. Everything is replaced as one edit. Returns the number of
+    /// matches replaced in each range along with the changes that were
+    /// made, last first.
+    pub fn replace_in_ranges(&mut self, pattern : &Pattern, replacement : &str, ranges : &[(usize, usize)], global : bool) -> Result<(Vec<usize>, Vec<TextChange>)> {
+        self.check_editable()?;
+
+        let mut replacements = Vec::new();
+        let mut counts       = Vec::with_capacity(ranges.len());
+        // Where the last match ended, so that one running on into the next
+        // range isn't replaced over again.
+        let mut after        = 0;
+
+        for &(start, end) in ranges {
+            let mut offset = cmp::max(start, after);
+            let mut count  = 0;
+
+            // A match can start right at the end of a range, so that `$`
+            // matches, and `^` does on an empty range.
+            while offset <= end {
+                let (found, text) = match self.search_forward(pattern, offset, Some(replacement))? {
+                    Some((found, text)) if found.start <= end => (found, text),
+                    _                                         => break,
+                };
+
+                offset = if found.is_empty() { found.end + 1 } else { found.end };
+                after  = found.end;
+                count += 1;
+                replacements.push((found, PieceFile::replacement_text(found, text)?));
+
+                if !global {
+                    break;
+                }
+            }
+
+            counts.push(count);
+        }
+
+        let changes = self.replace_matches(&replacements)?;
+
+        Ok((counts, changes))
+    }
+
+    /// Replace the first match of a pattern that starts at or after `from`
+    /// with `replacement`, as in `replace_all`. Returns the match that was
+    /// replaced and the length of the text that replaced it.
+    pub fn replace_next(&mut self, pattern : &Pattern, replacement : &str, from : usize) -> Result<Option<(Match, usize)>> {
+        self.check_editable()?;
+
+        let (found, text) = match self.search_forward(pattern, from, Some(replacement))? {
+            Some(result) => result,
+            None         => return Ok(None),
+        };
+
+        let text = PieceFile::replacement_text(found, text)?;
+
+        self.begin_group();
+        let result = self.replace_range(found.start, found.len(), text.as_str());
+        self.end_group();
+
+        result.map(|_| Some((found, text.len())))
+    }
+
+    /// Open a new PieceFile, detecting the file's encoding. Files that
+    /// look like UTF-16 but aren't valid UTF-16 are read as Latin-1.
+    pub fn open(path : &str) -> Result<Box<PieceFile>> {
+        let mut sample = Vec::new();
+        File::open(path)?.take(DETECT_SAMPLE_SIZE as u64).read_to_end(&mut sample)?;
+
+        let complete = sample.len() < DETECT_SAMPLE_SIZE;
+        let encoding = detect(&sample, complete);
+
+        match PieceFile::open_with_encoding(path, encoding) {
+            Err(FileError::Io(ref err)) if err.kind() == ErrorKind::InvalidData => {
+                PieceFile::open_with_encoding(path, Encoding::Latin1)
+            },
+            result => result,
+        }
+    }
+
+    /// Open a new PieceFile that reads the file in a particular encoding.
+    /// Fails with an `ErrorKind::InvalidData` I/O error if the file isn't
+    /// valid in that encoding.
+    ///
+    /// UTF-8 files are read from disk as they are needed. Files in any
+    /// other encoding are converted to UTF-8 up front, into a temporary
+    /// file that is read in their place.
+    pub fn open_with_encoding(path : &str, encoding : Encoding) -> Result<Box<PieceFile>> {
+        // We never write through this handle. Saving replaces the file
+        // on disk instead.
+        let mut file = OpenOptions::new()
+            .read(true)
+            .open(path)?;
+
+        let metadata = file.metadata()?;
+        let size     = metadata.len() as usize;
+
+        let mut piece_file = PieceFile {
+            actions        : Vec::new(),
+            append_file    : Vec::new(),
+            current        : None,
+            group          : None,
+            group_depth    : 0,
+            next_group     : 0,
+            roots          : Vec::new(),
+            disk           : Some(DiskState::new(&metadata)),
+            checked        : Cell::new(None),
+            encoding,
+            journal        : Some(Journal::new(path, Header::new(&metadata))),
+            length         : 0,
+            offset         : 0,
+            path           : Some(String::from(path)),
+            piece_table    : Tree::new(),
+            reader         : None,
+            recovery       : Journal::exists(path),
+            saved_edit     : 0,
+            stored_history : None,
+        };
+
+        let (piece, source) = if encoding.is_utf8() {
+            // Skip the byte order mark if there is one.
+            let bom       = encoding.bom();
+            let mut start = vec![0u8; cmp::min(bom.len(), size)];
+            file.read_exact(&mut start)?;
+
+            let bom_length = if start == bom { bom.len() } else { 0 };
+
+            (Piece {
+                file           : SourceFile::Original,
+                file_offset    : bom_length,
+                length         : size - bom_length,
+                logical_offset : 0,
+            }, file)
+        } else {
+            let decoded = PieceFile::decode_to_temp(&mut file, encoding)?;
+
+            (Piece {
+                file           : SourceFile::Original,
+                file_offset    : 0,
+                length         : decoded.metadata()?.len() as usize,
+                logical_offset : 0,
+            }, decoded)
+        };
+
+        piece_file.length = piece.length;
+        piece_file.reader = Some(FileSource::new(source)?);
+
+        if piece.length > 0 {
+            piece_file.piece_table.push(piece);
+        }
+
+        // Losing the undo history isn't worth failing to open the file
+        // over.
+        history::load(&mut piece_file).ok();
+        piece_file.saved_edit = piece_file.current_edit();
+
+        Ok(Box::new(piece_file))
+    }
+
+    /// Finish the group of edits started by `begin_group`.
+    pub fn end_group(&mut self) {
+        self.group_depth = self.group_depth.saturating_sub(1);
+
+        if self.group_depth == 0 {
+            self.group = None;
+        }
+    }
+
+    /// Get the encoding the PieceFile is saved in.
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// Change the encoding the PieceFile is saved in. Saving fails if the
+    /// text can't be represented in the new encoding.
+    pub fn set_encoding(&mut self, encoding : Encoding) {
+        self.encoding = encoding;
+    }
+
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    /// Read text from the buffer. Fails with `FileError::InvalidUtf8` if
+    /// the bytes aren't valid UTF-8, including when the read starts or
+    /// ends in the middle of a character. Use `read_bytes` to get at
+    /// anything else.
+    pub fn read(&mut self, num_bytes : usize) -> Result<String> {
+        let offset = self.offset;
+        let bytes  = self.read_bytes(num_bytes)?;
+
+        match String::from_utf8(bytes) {
+            Ok(text) => Ok(text),
+            Err(err) => Err(FileError::InvalidUtf8 { offset : offset + err.utf8_error().valid_up_to() }),
+        }
+    }
+
+    /// Read text from an offset.
+    pub fn read_at(&mut self, offset : usize, num_bytes : usize) -> Result<String> {
+        self.seek(SeekFrom::Start(offset as u64))?;
+        self.read(num_bytes)
+    }
+
+    /// Read exactly `num_bytes` bytes from the buffer, whatever they are.
+    pub fn read_bytes(&mut self, num_bytes : usize) -> Result<Vec<u8>> {
+        let mut result = Vec::with_capacity(num_bytes);
+
+        if num_bytes == 0 {
+            return Ok(result);
+        }
+
+        let start_offset = self.offset;
+
+        // Don't allow the user to read past the end of the piece file.
+        if start_offset + num_bytes > self.len() {
+            return Err(FileError::OutOfRange {
+                offset : start_offset,
+                length : num_bytes,
+                size   : self.len(),
+            });
+        }
+
+        let start_index  = self.get_at_offset(start_offset);
+        // Often you'll see the ending offset be exclusive of the rest of
+        // the selection. Here we want it to be end inclusive, so we subtract
+        // one from the offset. Off-by-one errors are hard.
+        let end_offset   = self.offset + num_bytes - 1;
+        let end_index    = self.get_at_offset(end_offset);
+
+        assert!(self.piece_table.get(end_index).unwrap().contains(end_offset));
+
+        let num_pieces   = end_index - start_index + 1;
+
+        // Same as delete. There is an edge case where we read solely inside
+        // of a piece.
+        if num_pieces == 1 {
+            let piece = self.piece_table.get(start_index).unwrap();
+            self.read_piece(piece, start_offset, num_bytes, &mut result)?;
+            return Ok(result);
+        }
+
+        // 1. Handle the piece the read starts in.
+        {
+            let piece = self.piece_table.get(start_index).unwrap();
+            let piece_end_offset = piece.logical_offset + piece.length;
+            let piece_read_bytes = piece_end_offset - start_offset;
+
+            self.read_piece(piece, start_offset, piece_read_bytes, &mut result)?;
+        }
+
+        // 2. Handle all of the pieces between the first and the last.
+        if num_pieces > 2 {
+            let mut piece_read_bytes   : usize;
+            let mut piece_start_offset : usize;
+
+            for index in start_index + 1 .. end_index {
+                // TODO move this allocation out?
+                let piece = self.piece_table.get(index).unwrap();
+
+                piece_read_bytes   = piece.length;
+                piece_start_offset = piece.logical_offset;
+
+                self.read_piece(
+                    piece,
+                    piece_start_offset,
+                    piece_read_bytes,
+                    &mut result)?;
             }
+        }
 
-            if is_single_end_delete {
-                self.merge_pieces(index);
+        // 3. Handle the piece the read ends in.
+        {
+            let piece = self.piece_table.get(end_index).unwrap();
+            // In this specific case, we need to re-add the one back in.
+            // This is because read_piece does its own subtraction by one
+            // so we don't need it in this specific calculation.
+            // I understand why this might be confusing, but it works.
+            let piece_start_offset = piece.logical_offset;
+            let piece_read_bytes   = end_offset - piece_start_offset + 1;
+
+            self.read_piece(
+                piece,
+                piece_start_offset,
+                piece_read_bytes,
+                &mut result)?;
+        }
+
+        assert_eq!(result.len(), num_bytes);
+
+        Ok(result)
+    }
+
+    /// Read exactly `num_bytes` bytes from an offset.
+    pub fn read_bytes_at(&mut self, offset : usize, num_bytes : usize) -> Result<Vec<u8>> {
+        self.seek(SeekFrom::Start(offset as u64))?;
+        self.read_bytes(num_bytes)
+    }
+
+    /// Find the first match of a pattern that starts at or after `from`.
+    /// If there is a `replacement`, it is also expanded for the match
+    /// while the text around it is at hand.
+    fn search_forward(&mut self, pattern : &Pattern, from : usize, replacement : Option<&str>) -> Result<Option<(Match, Vec<u8>)>> {
+        let length     = self.len();
+        let overlap    = pattern.overlap();
+        let mut offset = from;
+        let mut size   = cmp::max(SEARCH_WINDOW_SIZE, 2 * (overlap + SEARCH_CONTEXT));
+
+        while offset <= length {
+            // The window starts a little early so that anchors can see
+            // the text before it.
+            let start  = offset.saturating_sub(SEARCH_CONTEXT);
+            let end    = cmp::min(length, offset + size);
+            let at_end = end == length;
+            let window = self.read_bytes_at(start, end - start)?;
+
+            // Any match that starts before `safe` fits in the window. One
+            // that starts after it might have been cut off, which could
+            // also have let a later match win.
+            let safe = if at_end { length + 1 } else { end - overlap - SEARCH_CONTEXT };
+
+            match pattern.find_at(&window, offset - start).map(|found| found.shift(start)) {
+                Some(found) if at_end || (found.start < safe && found.end + SEARCH_CONTEXT <= end) => {
+                    let text = match replacement {
+                        Some(replacement) => pattern.expand(&window, found.start - start, replacement),
+                        None              => Vec::new(),
+                    };
+
+                    return Ok(Some((found, text)));
+                },
+                // This match runs to the end of the window and might go
+                // on past it, so look again with more room.
+                Some(found) if found.start < safe => {
+                    offset = found.start;
+                    size  *= 2;
+                },
+                _ => offset = safe,
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Check that the expanded replacement for a match is valid UTF-8,
+    /// which it might not be if it uses captured text. It's checked before
+    /// changing anything so that replacing doesn't stop partway.
+    fn replacement_text(found : Match, text : Vec<u8>) -> Result<String> {
+        String::from_utf8(text).map_err(|_| FileError::InvalidUtf8 { offset : found.start })
+    }
+
+    /// Replace each of `replacements`, which are in order and don't
+    /// overlap, as one edit. They're replaced last first so that the ones
+    /// still to go stay where they were found, and the changes are returned
+    /// in that order.
+    fn replace_matches(&mut self, replacements : &[(Match, String)]) -> Result<Vec<TextChange>> {
+        let mut changes = Vec::with_capacity(replacements.len());
+        let mut result  = Ok(());
+
+        self.begin_group();
+
+        for &(found, ref text) in replacements.iter().rev() {
+            result = self.replace_range(found.start, found.len(), text.as_str());
+
+            if result.is_err() {
+                break;
             }
 
-            self.length += action.length;
+            changes.push(TextChange { offset : found.start, removed : found.len(), inserted : text.len() });
         }
 
-        // We want the piece table to look EXACTLY like it did
-        // before the operation was performed.
-        //
-        // There aren't any real advantages to this other than
-        // making me feel good for the time being.
+        self.end_group();
+
+        result.map(|_| changes)
+    }
+
+    /// Replace `length` bytes at `offset` with `text`. The caller checks
+    /// that the file can be edited.
+    fn replace_range(&mut self, offset : usize, length : usize, text : &str) -> Result<()> {
+        if length > 0 {
+            let action = self._delete(offset, length)?;
+            self.record(action);
+        }
+
+        if !text.is_empty() {
+            let action = self._insert(text, offset)?;
+            self.record(action);
+        }
+
+        Ok(())
+    }
+
+    /// Attempt to merge two pieces together given the index of the lower
+    /// piece.
+    fn merge_pieces(&mut self, index : usize) -> Result<()> {
+        let upper_index        = index + 1;
+        let upper_piece        = self.piece_table.get(upper_index).unwrap();
+        let upper_start_offset = upper_piece.file_offset;
+        let lower_piece        = self.piece_table.get(index).unwrap();
+        let lower_end_offset   = lower_piece.file_offset + lower_piece.length;
+
+        // Something is amiss. Don't merge noncontiguous pieces.
+        if lower_end_offset != upper_start_offset {
+            return Err(FileError::NotContiguous { index });
+        }
+
+        self.piece_table.update(index, |piece| piece.length += upper_piece.length);
+        self.piece_table.remove(upper_index);
+
+        Ok(())
+    }
 
-        if action.merge_up && last_index + 1 < self.piece_table.len() {
-            self.merge_pieces(last_index);
+    /// Start a group of edits that `undo` and `redo` treat as one. Groups
+    /// can be nested, in which case the outermost one counts.
+    pub fn begin_group(&mut self) {
+        if self.group_depth == 0 {
+            self.group       = Some(self.next_group);
+            self.next_group += 1;
         }
 
-        if action.merge_down && index > 0 {
-            self.merge_pieces(index - 1);
+        self.group_depth += 1;
+    }
+
+    /// Get the edits that are the tips of branches in the undo tree, in the
+    /// order they were made. Edits are numbered starting at 1.
+    pub fn branches(&self) -> Vec<usize> {
+        (0 .. self.actions.len())
+            .filter(|&index| self.actions[index].children.is_empty())
+            .map(|index| index + 1)
+            .collect()
+    }
+
+    /// Check that the piece table and undo tree are consistent, which
+    /// they always should be. This walks every piece and action, so it's
+    /// meant for tests and debugging rather than to be run on every edit.
+    ///
+    /// The piece table has to cover the file without gaps or empty
+    /// pieces, every piece has to refer to text that exists, and the undo
+    /// tree's links have to agree with each other.
+    pub fn check_invariants(&self) -> Result<()> {
+        let broken = |reason : String| Err(FileError::BrokenInvariant(reason));
+
+        let append_length   = self.append_file.len();
+        let original_length = self.reader.as_ref().map_or(0, |reader| reader.len());
+        let mut offset      = 0;
+
+        for (index, piece) in self.piece_table.iter().enumerate() {
+            if piece.length == 0 {
+                return broken(format!("piece {} is empty", index));
+            }
+
+            if piece.logical_offset != offset {
+                return broken(format!("piece {} starts at {} instead of {}", index, piece.logical_offset, offset));
+            }
+
+            let source_length = match piece.file {
+                SourceFile::Append   => append_length,
+                SourceFile::Original => original_length,
+            };
+
+            if piece.file_offset + piece.length > source_length {
+                return broken(format!("piece {} reaches past the end of its file", index));
+            }
+
+            offset += piece.length;
+        }
+
+        if offset != self.length || self.piece_table.weight() != self.length {
+            return broken(format!("pieces add up to {} bytes instead of {}", offset, self.length));
+        }
+
+        let num_actions = self.actions.len();
+
+        if self.current.is_some_and(|current| current >= num_actions) {
+            return broken(String::from("the current action doesn't exist"));
+        }
+
+        for (index, action) in self.actions.iter().enumerate() {
+            let siblings = match action.parent {
+                Some(parent) if parent >= num_actions => {
+                    return broken(format!("action {} has a parent that doesn't exist", index));
+                },
+                Some(parent) => &self.actions[parent].children,
+                None         => &self.roots,
+            };
+
+            if !siblings.contains(&index) {
+                return broken(format!("action {} isn't a child of its parent", index));
+            }
+
+            for &child in action.children.iter() {
+                if child >= num_actions || self.actions[child].parent != Some(index) {
+                    return broken(format!("action {} has a child that isn't its own", index));
+                }
+            }
+        }
+
+        if self.group.is_some() != (self.group_depth > 0) {
+            return broken(String::from("the open group doesn't match its depth"));
+        }
+
+        Ok(())
+    }
+
+    /// Get the number of the edit the file is currently at. Edits are
+    /// numbered in the order they were made starting at 1. 0 refers to
+    /// the original state of the file.
+    pub fn current_edit(&self) -> usize {
+        match self.current {
+            Some(index) => index + 1,
+            None        => 0,
+        }
+    }
+
+    /// Check whether the file has changed since it was opened or last
+    /// saved.
+    pub fn is_modified(&self) -> bool {
+        self.current_edit() != self.saved_edit
+    }
+
+    /// Move back in time from the moment the current edit was made. The
+    /// file ends up as it was at that point, even if the state it was in
+    /// is on another branch of the undo tree.
+    pub fn earlier(&mut self, duration : time::Duration) -> Result<Vec<TextChange>> {
+        let now = match self.edit_time(self.current_edit()) {
+            Some(now) => now,
+            None      => return Ok(Vec::new()),
+        };
+
+        match now.checked_sub(duration) {
+            Some(target) => self.goto_time(target),
+            None         => self.goto_edit(0),
+        }
+    }
+
+    /// Move back a number of edits in the order they were made, regardless
+    /// of which branch of the undo tree they are on.
+    pub fn earlier_edits(&mut self, count : usize) -> Result<Vec<TextChange>> {
+        let edit = self.current_edit().saturating_sub(count);
+        self.goto_edit(edit)
+    }
+
+    /// Get the time an edit was made. Returns None for edit 0, the
+    /// original file, or for edits that do not exist.
+    pub fn edit_time(&self, edit : usize) -> Option<time::Instant> {
+        if edit == 0 || edit > self.actions.len() {
+            return None;
+        }
+
+        Some(self.actions[edit - 1].timestamp)
+    }
+
+    /// Restore the file to how it looked at a particular time: the state
+    /// right after the last edit made at or before `instant`.
+    pub fn goto_time(&mut self, instant : time::Instant) -> Result<Vec<TextChange>> {
+        // Actions are stored in the order they were made, so their
+        // timestamps are sorted.
+        let edit = self.actions
+            .iter()
+            .take_while(|action| action.timestamp <= instant)
+            .count();
+
+        self.goto_edit(edit)
+    }
+
+    /// Jump to the state of the file right after an edit was made, undoing
+    /// and redoing along the undo tree as necessary. 0 restores the
+    /// original state of the file. Returns the changes made to the text,
+    /// in the order they were made, as do the other functions that move
+    /// through the undo tree.
+    ///
+    /// Will do nothing if the edit does not exist.
+    pub fn goto_edit(&mut self, edit : usize) -> Result<Vec<TextChange>> {
+        if edit > self.actions.len() {
+            return Ok(Vec::new());
+        }
+
+        self.check_editable()?;
+
+        // All of the actions between the target and the root.
+        let mut path   = Vec::new();
+        let mut node   = if edit == 0 { None } else { Some(edit - 1) };
+        while let Some(index) = node {
+            path.push(index);
+            node = self.actions[index].parent;
+        }
+
+        let ancestors : HashSet<usize> = path.iter().cloned().collect();
+        let mut changes = Vec::new();
+
+        // Undo until we're somewhere on the path to the target.
+        while let Some(index) = self.current {
+            if ancestors.contains(&index) {
+                break;
+            }
+
+            changes.push(self.revert(index)?);
+        }
+
+        // Then redo down to it.
+        let remaining = match self.current {
+            Some(current) => path.iter().position(|&index| index == current).unwrap(),
+            None          => path.len(),
+        };
+
+        for &index in path[.. remaining].iter().rev() {
+            changes.push(self.apply(index)?);
+        }
+
+        Ok(changes)
+    }
+
+    /// Move forward in time from the moment the current edit was made.
+    /// From the original file, time is measured from the first edit.
+    pub fn later(&mut self, duration : time::Duration) -> Result<Vec<TextChange>> {
+        let edit = cmp::max(self.current_edit(), 1);
+        let now  = match self.edit_time(edit) {
+            Some(now) => now,
+            None      => return Ok(Vec::new()),
+        };
+
+        self.goto_time(now + duration)
+    }
+
+    /// Move forward a number of edits in the order they were made,
+    /// regardless of which branch of the undo tree they are on.
+    pub fn later_edits(&mut self, count : usize) -> Result<Vec<TextChange>> {
+        let edit = cmp::min(self.current_edit() + count, self.num_edits());
+        self.goto_edit(edit)
+    }
+
+    /// Get the number of edits ever made to the file, including those
+    /// that have been undone.
+    pub fn num_edits(&self) -> usize {
+        self.actions.len()
+    }
+
+    /// Move to the branch made right after the current one on top of the
+    /// same state. Branches are only siblings if they share a parent.
+    ///
+    /// Will do nothing if there is no such branch.
+    pub fn next_branch(&mut self) -> Result<Vec<TextChange>> {
+        self.switch_branch(1)
+    }
+
+    /// Move to the branch made right before the current one on top of the
+    /// same state.
+    ///
+    /// Will do nothing if there is no such branch.
+    pub fn prev_branch(&mut self) -> Result<Vec<TextChange>> {
+        self.switch_branch(-1)
+    }
+
+    /// Move `delta` siblings over from the current action.
+    fn switch_branch(&mut self, delta : i64) -> Result<Vec<TextChange>> {
+        let index = match self.current {
+            Some(index) => index,
+            None        => return Ok(Vec::new()),
+        };
+
+        self.check_editable()?;
+
+        // Siblings are stored in the order they were last visited, so
+        // we have to sort them to get the order they were made in.
+        let mut siblings = self.children_of(self.actions[index].parent).clone();
+        siblings.sort();
+
+        let position = siblings.iter().position(|&sibling| sibling == index).unwrap();
+        let target   = (position as i64) + delta;
+
+        if target < 0 || target >= siblings.len() as i64 {
+            return Ok(Vec::new());
+        }
+
+        let reverted = self.revert(index)?;
+        let applied  = self.apply(siblings[target as usize])?;
+
+        Ok(vec![reverted, applied])
+    }
+
+    /// Redo an undone action, along with the rest of its group. If there
+    /// are several branches on top of the current state, this follows the
+    /// one visited most recently.
+    ///
+    /// Will do nothing if there is nothing to be redone.
+    pub fn redo(&mut self) -> Result<Vec<TextChange>> {
+        let index = match self.children_of(self.current).last() {
+            Some(&index) => index,
+            None         => return Ok(Vec::new()),
+        };
+
+        self.check_editable()?;
+
+        let group       = self.actions[index].group;
+        let mut changes = vec![self.apply(index)?];
+
+        while let Some(&index) = self.children_of(self.current).last() {
+            if self.actions[index].group != group {
+                break;
+            }
+
+            changes.push(self.apply(index)?);
+        }
+
+        Ok(changes)
+    }
+
+    /// Undo the most recent change to the buffer, along with the rest of
+    /// its group. Undone changes are kept in the undo tree even after new
+    /// edits are made, so they can always be recovered with `goto_edit` or
+    /// the branch functions.
+    ///
+    /// Will do nothing if there is nothing to be undone.
+    pub fn undo(&mut self) -> Result<Vec<TextChange>> {
+        let group = match self.current {
+            Some(index) => self.actions[index].group,
+            None        => return Ok(Vec::new()),
+        };
+
+        self.check_editable()?;
+
+        let mut changes = Vec::new();
+
+        while let Some(index) = self.current {
+            if self.actions[index].group != group {
+                break;
+            }
+
+            changes.push(self.revert(index)?);
         }
 
-        // TODO make this smarter. We really don't have to start
-        // from zero.
-        self.update_offsets(0);
+        Ok(changes)
     }
 }
 
@@ -833,7 +2234,7 @@ impl Seek for PieceFile {
         };
 
         if new_offset < 0 {
-            panic!("Seek offset less than 0");
+            return Err(FileError::NegativeSeek(new_offset).into());
         }
 
         self.offset = new_offset as usize;
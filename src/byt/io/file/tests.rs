@@ -13,12 +13,12 @@ fn it_inserts() {
     let mut file = PieceFile::empty().unwrap();
     assert_eq!(file.piece_table.len(), 0);
 
-    file.insert("foo", 0);
-    file.insert("bar", 0);
+    file.insert("foo", 0).unwrap();
+    file.insert("bar", 0).unwrap();
 
     assert_eq!(file.length, 6);
 
-    let piece_table = &file.piece_table;
+    let piece_table = file.piece_table.to_vec();
     assert_eq!(piece_table.len(), 2);
 
     let first_element  = &piece_table[0];
@@ -43,12 +43,12 @@ fn it_inserts_inside_piece() {
     let mut file = PieceFile::empty().unwrap();
     assert_eq!(file.piece_table.len(), 0);
 
-    file.insert("aa", 0);
-    file.insert("b", 1);
+    file.insert("aa", 0).unwrap();
+    file.insert("b", 1).unwrap();
 
     assert_eq!(file.length, 3);
 
-    let piece_table = &file.piece_table;
+    let piece_table = file.piece_table.to_vec();
     assert_eq!(piece_table.len(), 3);
 
     let first_piece  = &piece_table[0];
@@ -78,10 +78,10 @@ fn it_deletes_inside_piece() {
     let mut file = PieceFile::empty().unwrap();
     assert_eq!(file.piece_table.len(), 0);
 
-    file.insert("foo", 0);
-    file.delete(1, 1);
+    file.insert("foo", 0).unwrap();
+    file.delete(1, 1).unwrap();
 
-    let piece_table = &file.piece_table;
+    let piece_table = file.piece_table.to_vec();
     assert_eq!(piece_table.len(), 2);
     assert_eq!(piece_table[0].length, 1);
     assert_eq!(piece_table[1].length, 1);
@@ -102,17 +102,17 @@ fn it_deletes_inside_piece() {
 fn it_inserts_little_pieces() {
     let mut file = PieceFile::empty().unwrap();
 
-    file.insert("a", 0);
-    file.insert("a", 0);
-    file.insert("a", 0);
-    file.insert("a", 0);
-    file.insert("b", 1);
+    file.insert("a", 0).unwrap();
+    file.insert("a", 0).unwrap();
+    file.insert("a", 0).unwrap();
+    file.insert("a", 0).unwrap();
+    file.insert("b", 1).unwrap();
 
     assert_eq!(file.piece_table.len(), 5);
 
     // This should be the piece with an 'a' in it
     {
-        let piece = &file.piece_table[0];
+        let piece = file.piece_table.get(0).unwrap();
         assert_eq!(piece.file, SourceFile::Append);
         assert_eq!(piece.length, 1);
         assert_eq!(piece.file_offset, 3);
@@ -121,7 +121,7 @@ fn it_inserts_little_pieces() {
 
     // This should be the piece with the 'b' in it
     {
-        let piece = &file.piece_table[1];
+        let piece = file.piece_table.get(1).unwrap();
         assert_eq!(piece.file, SourceFile::Append);
         assert_eq!(piece.length, 1);
         assert_eq!(piece.file_offset, 4);
@@ -132,20 +132,68 @@ fn it_inserts_little_pieces() {
     assert_eq!(read.as_str(), "abaaa");
 }
 
+#[test]
+fn it_rejects_edits_past_the_end() {
+    let mut file = PieceFile::empty().unwrap();
+    file.insert("foo", 0).unwrap();
+
+    match file.insert("bar", 4).unwrap_err() {
+        FileError::OutOfRange { offset, size, .. } => assert_eq!((offset, size), (4, 3)),
+        err                                        => panic!("Unexpected error {}", err),
+    }
+
+    match file.delete(1, 3).unwrap_err() {
+        FileError::OutOfRange { offset, length, .. } => assert_eq!((offset, length), (1, 3)),
+        err                                          => panic!("Unexpected error {}", err),
+    }
+
+    // Nothing happened.
+    assert_eq!(file.len(), 3);
+    assert_eq!(file.num_edits(), 1);
+    assert!(file.read_at(2, 2).is_err());
+    assert!(file.seek(SeekFrom::Current(-10)).is_err());
+}
+
+#[test]
+fn it_handles_many_pieces() {
+    let mut file = PieceFile::empty().unwrap();
+
+    // Every other insert lands at the start so nothing can be merged.
+    for i in 0 .. 1000 {
+        if i % 2 == 0 {
+            file.insert("a", 0).unwrap();
+        } else {
+            file.insert("b", file.len()).unwrap();
+        }
+    }
+
+    assert_eq!(file.piece_table.len(), 1000);
+
+    let piece = file.piece_table.get(700).unwrap();
+    assert_eq!(piece.logical_offset, 700);
+
+    file.delete(499, 2).unwrap();
+    assert_eq!(file.read_at(497, 4).unwrap().as_str(), "aabb");
+
+    file.undo().unwrap();
+    assert_eq!(file.read_at(498, 4).unwrap().as_str(), "aabb");
+    assert_eq!(file.piece_table.len(), 1000);
+}
+
 #[test]
 fn it_deletes_little_pieces() {
     let mut file = PieceFile::empty().unwrap();
 
-    file.insert("a", 0);
-    file.insert("\n", 1);
-    file.insert("a", 2);
-    file.insert("\n", 3);
-    file.insert("a", 4);
-    file.insert("\n", 5);
+    file.insert("a", 0).unwrap();
+    file.insert("\n", 1).unwrap();
+    file.insert("a", 2).unwrap();
+    file.insert("\n", 3).unwrap();
+    file.insert("a", 4).unwrap();
+    file.insert("\n", 5).unwrap();
 
     println!("{}", file);
 
-    file.delete(2, 2);
+    file.delete(2, 2).unwrap();
 
     let read = file.read(4).unwrap();
     assert_eq!(read.as_str(), "a\na\n");
@@ -155,10 +203,10 @@ fn it_deletes_little_pieces() {
 fn it_deletes_backwards() {
     let mut file = PieceFile::empty().unwrap();
 
-    file.insert("hhhh", 0);
-    file.delete(2, 1);
-    file.delete(1, 1);
-    file.delete(0, 1);
+    file.insert("hhhh", 0).unwrap();
+    file.delete(2, 1).unwrap();
+    file.delete(1, 1).unwrap();
+    file.delete(0, 1).unwrap();
 
     let read = file.read(1).unwrap();
     assert_eq!(read.as_str(), "h");
@@ -169,13 +217,13 @@ fn it_deletes_across_two_pieces() {
     let mut file = PieceFile::empty().unwrap();
     assert_eq!(file.piece_table.len(), 0);
 
-    file.insert("bar", 0);
-    file.insert("foo", 0);
-    file.delete(2,2);
+    file.insert("bar", 0).unwrap();
+    file.insert("foo", 0).unwrap();
+    file.delete(2,2).unwrap();
 
     assert_eq!(file.length, 4);
 
-    let piece_table = &file.piece_table;
+    let piece_table = file.piece_table.to_vec();
     assert_eq!(piece_table.len(), 2);
     assert_eq!(piece_table[0].length, 2);
     assert_eq!(piece_table[1].length, 2);
@@ -189,7 +237,7 @@ fn it_deletes_across_two_pieces() {
     assert_eq!(first_piece.file, SourceFile::Append);
     assert_eq!(first_piece.length, 1);
     assert_eq!(first_piece.file_offset, 0);
-    assert_eq!(first_piece.logical_offset, 2);
+    assert_eq!(first_piece.logical_offset, 3);
 
     let second_piece = &action.pieces[1];
     assert_eq!(second_piece.file, SourceFile::Append);
@@ -203,14 +251,14 @@ fn it_deletes_across_three_pieces() {
     let mut file = PieceFile::empty().unwrap();
     assert_eq!(file.piece_table.len(), 0);
 
-    file.insert("cc", 0);
-    file.insert("bb", 0);
-    file.insert("aa", 0);
-    file.delete(1, 4);
+    file.insert("cc", 0).unwrap();
+    file.insert("bb", 0).unwrap();
+    file.insert("aa", 0).unwrap();
+    file.delete(1, 4).unwrap();
 
     assert_eq!(file.length, 2);
 
-    let piece_table = &file.piece_table;
+    let piece_table = file.piece_table.to_vec();
     assert_eq!(piece_table.len(), 2);
 
     let first_piece  = &piece_table[0];
@@ -232,7 +280,7 @@ fn it_reads_inside_piece() {
     let mut file = PieceFile::empty().unwrap();
     assert_eq!(file.piece_table.len(), 0);
 
-    file.insert("foobar", 0);
+    file.insert("foobar", 0).unwrap();
     let read = file.read(6).unwrap();
     assert_eq!(read.as_str(), "foobar");
 }
@@ -242,8 +290,8 @@ fn it_reads_across_two_pieces() {
     let mut file = PieceFile::empty().unwrap();
     assert_eq!(file.piece_table.len(), 0);
 
-    file.insert("bar", 0);
-    file.insert("foo", 0);
+    file.insert("bar", 0).unwrap();
+    file.insert("foo", 0).unwrap();
     let read = file.read(6).unwrap();
     assert_eq!(read.as_str(), "foobar");
 }
@@ -253,9 +301,9 @@ fn it_reads_across_three_pieces() {
     let mut file = PieceFile::empty().unwrap();
     assert_eq!(file.piece_table.len(), 0);
 
-    file.insert("bar", 0);
-    file.insert("foo", 0);
-    file.insert("car", 0);
+    file.insert("bar", 0).unwrap();
+    file.insert("foo", 0).unwrap();
+    file.insert("car", 0).unwrap();
 
     let read = file.read(9).unwrap();
     assert_eq!(read.as_str(), "carfoobar");
@@ -266,8 +314,8 @@ fn it_reads_at_offset() {
     let mut file = PieceFile::empty().unwrap();
     assert_eq!(file.piece_table.len(), 0);
 
-    file.insert("bar", 0);
-    file.insert("bar", 0);
+    file.insert("bar", 0).unwrap();
+    file.insert("bar", 0).unwrap();
 
     let read = file.read_at(1, 3).unwrap();
     assert_eq!(read.as_str(), "arb");
@@ -278,8 +326,8 @@ fn it_undoes_an_insert() {
     let mut file = PieceFile::empty().unwrap();
     assert_eq!(file.piece_table.len(), 0);
 
-    file.insert("bar", 0);
-    file.undo();
+    file.insert("bar", 0).unwrap();
+    file.undo().unwrap();
 
     assert_eq!(file.piece_table.len(), 0);
 }
@@ -289,9 +337,9 @@ fn it_undoes_a_delete_in_a_single_piece() {
     let mut file = PieceFile::empty().unwrap();
     assert_eq!(file.piece_table.len(), 0);
 
-    file.insert("bar", 0);
-    file.delete(1, 1);
-    file.undo();
+    file.insert("bar", 0).unwrap();
+    file.delete(1, 1).unwrap();
+    file.undo().unwrap();
 
     assert_eq!(file.piece_table.len(), 1);
 
@@ -311,12 +359,12 @@ fn it_undoes_a_delete_at_the_end_of_a_single_piece() {
     let mut file = PieceFile::empty().unwrap();
     assert_eq!(file.piece_table.len(), 0);
 
-    file.insert("bar", 0);
-    file.delete(1, 2);
-    file.undo();
+    file.insert("bar", 0).unwrap();
+    file.delete(1, 2).unwrap();
+    file.undo().unwrap();
 
     assert_eq!(file.piece_table.len(), 1);
-    assert_eq!(file.piece_table[0].length, 3);
+    assert_eq!(file.piece_table.get(0).unwrap().length, 3);
     assert_eq!(file.length, 3);
 
     let read = file.read(3).unwrap();
@@ -328,12 +376,12 @@ fn it_undoes_a_delete_at_the_start_of_a_single_piece() {
     let mut file = PieceFile::empty().unwrap();
     assert_eq!(file.piece_table.len(), 0);
 
-    file.insert("bar", 0);
-    file.delete(0, 2);
-    file.undo();
+    file.insert("bar", 0).unwrap();
+    file.delete(0, 2).unwrap();
+    file.undo().unwrap();
 
     assert_eq!(file.piece_table.len(), 1);
-    assert_eq!(file.piece_table[0].length, 3);
+    assert_eq!(file.piece_table.get(0).unwrap().length, 3);
     assert_eq!(file.length, 3);
 
     let read = file.read(3).unwrap();
@@ -345,11 +393,11 @@ fn it_undoes_a_delete_across_two_pieces() {
     let mut file = PieceFile::empty().unwrap();
     assert_eq!(file.piece_table.len(), 0);
 
-    file.insert("bar", 0);
-    file.insert("foo", 0);
-    file.delete(0, 6);
+    file.insert("bar", 0).unwrap();
+    file.insert("foo", 0).unwrap();
+    file.delete(0, 6).unwrap();
     assert_eq!(file.piece_table.len(), 0);
-    file.undo();
+    file.undo().unwrap();
     assert_eq!(file.piece_table.len(), 2);
 
     let read = file.read(6).unwrap();
@@ -361,11 +409,11 @@ fn it_undoes_a_delete_across_three_pieces() {
     let mut file = PieceFile::empty().unwrap();
     assert_eq!(file.piece_table.len(), 0);
 
-    file.insert("bar", 0);
-    file.insert("foo", 0);
-    file.insert("car", 0);
-    file.delete(0, 9);
-    file.undo();
+    file.insert("bar", 0).unwrap();
+    file.insert("foo", 0).unwrap();
+    file.insert("car", 0).unwrap();
+    file.delete(0, 9).unwrap();
+    file.undo().unwrap();
 
     assert_eq!(file.piece_table.len(), 3);
 
@@ -378,32 +426,59 @@ fn it_redoes_an_insert() {
     let mut file = PieceFile::empty().unwrap();
     assert_eq!(file.piece_table.len(), 0);
 
-    file.insert("bar", 0);
-    file.undo();
+    file.insert("bar", 0).unwrap();
+    file.undo().unwrap();
     assert_eq!(file.piece_table.len(), 0);
-    file.redo();
+    file.redo().unwrap();
     assert_eq!(file.piece_table.len(), 1);
     let read = file.read(3).unwrap();
     assert_eq!(read.as_str(), "bar");
 }
 
+#[test]
+fn it_undoes_and_redoes_a_group() {
+    let mut file = PieceFile::empty().unwrap();
+    file.insert("foo", 0).unwrap();
+
+    file.begin_group();
+    file.insert("bar", 3).unwrap();
+    file.begin_group();
+    file.delete(0, 1).unwrap();
+    file.end_group();
+    file.insert("!", 5).unwrap();
+    file.end_group();
+
+    file.insert("?", 6).unwrap();
+
+    file.undo().unwrap();
+    assert_eq!(file.len(), 6);
+    file.undo().unwrap();
+    assert_eq!(file.len(), 3);
+    assert_eq!(file.current_edit(), 1);
+
+    file.redo().unwrap();
+    assert_eq!(file.len(), 6);
+    assert_eq!(file.read_at(0, 6).unwrap().as_str(), "oobar!");
+    assert_eq!(file.current_edit(), 4);
+}
+
 #[test]
 fn it_redoes_a_delete() {
     let mut file = PieceFile::empty().unwrap();
     assert_eq!(file.piece_table.len(), 0);
 
-    file.insert("foobar", 0);
+    file.insert("foobar", 0).unwrap();
     assert_eq!(file.piece_table.len(), 1);
 
-    file.delete(2, 4);
+    file.delete(2, 4).unwrap();
     assert_eq!(file.piece_table.len(), 1);
     assert_eq!(file.length, 2);
 
-    file.undo();
+    file.undo().unwrap();
     assert_eq!(file.piece_table.len(), 1);
     assert_eq!(file.length, 6);
 
-    file.redo();
+    file.redo().unwrap();
     assert_eq!(file.length, 2);
     assert_eq!(file.piece_table.len(), 1);
 
@@ -416,14 +491,14 @@ fn it_does_not_panic_after_many_undos() {
     let mut file = PieceFile::empty().unwrap();
     assert_eq!(file.piece_table.len(), 0);
 
-    file.insert("foobar", 0);
+    file.insert("foobar", 0).unwrap();
     assert_eq!(file.piece_table.len(), 1);
 
-    file.undo();
-    file.undo();
-    file.undo();
-    file.undo();
-    file.undo();
+    file.undo().unwrap();
+    file.undo().unwrap();
+    file.undo().unwrap();
+    file.undo().unwrap();
+    file.undo().unwrap();
 }
 
 #[test]
@@ -431,28 +506,1025 @@ fn it_does_not_panic_after_many_redos() {
     let mut file = PieceFile::empty().unwrap();
     assert_eq!(file.piece_table.len(), 0);
 
-    file.insert("foobar", 0);
+    file.insert("foobar", 0).unwrap();
     assert_eq!(file.piece_table.len(), 1);
 
-    file.undo();
-    file.redo();
-    file.redo();
-    file.redo();
-    file.redo();
+    file.undo().unwrap();
+    file.redo().unwrap();
+    file.redo().unwrap();
+    file.redo().unwrap();
+    file.redo().unwrap();
 }
 
 #[test]
-fn it_removes_newer_actions() {
+fn it_keeps_undone_actions() {
     let mut file = PieceFile::empty().unwrap();
     assert_eq!(file.piece_table.len(), 0);
 
-    file.insert("foobar", 0);
-    file.insert("bar", 0);
-    file.undo();
-    file.insert("bar", 0);
-    file.redo();
+    file.insert("foobar", 0).unwrap();
+    file.insert("bar", 0).unwrap();
+    file.undo().unwrap();
+    file.insert("bar", 0).unwrap();
+    file.redo().unwrap();
 
     let read = file.read(9).unwrap();
     assert_eq!(read.as_str(), "barfoobar");
-    assert_eq!(file.actions.len(), 2);
+    assert_eq!(file.actions.len(), 3);
+    assert_eq!(file.branches(), vec![2, 3]);
+}
+
+#[test]
+fn it_goes_to_an_edit_on_another_branch() {
+    let mut file = PieceFile::empty().unwrap();
+
+    file.insert("foo", 0).unwrap();
+    file.insert("bar", 3).unwrap();
+    file.undo().unwrap();
+    file.insert("car", 0).unwrap();
+    assert_eq!(file.current_edit(), 3);
+
+    file.goto_edit(2).unwrap();
+    assert_eq!(file.current_edit(), 2);
+    let read = file.read_at(0, 6).unwrap();
+    assert_eq!(read.as_str(), "foobar");
+
+    file.goto_edit(3).unwrap();
+    let read = file.read_at(0, 6).unwrap();
+    assert_eq!(read.as_str(), "carfoo");
+
+    file.goto_edit(0).unwrap();
+    assert_eq!(file.len(), 0);
+    assert_eq!(file.piece_table.len(), 0);
+}
+
+#[test]
+fn it_redoes_the_most_recent_branch() {
+    let mut file = PieceFile::empty().unwrap();
+
+    file.insert("foo", 0).unwrap();
+    file.insert("bar", 3).unwrap();
+    file.undo().unwrap();
+    file.insert("car", 3).unwrap();
+    file.goto_edit(2).unwrap();
+    file.undo().unwrap();
+    file.redo().unwrap();
+
+    let read = file.read_at(0, 6).unwrap();
+    assert_eq!(read.as_str(), "foobar");
+}
+
+#[test]
+fn it_moves_between_sibling_branches() {
+    let mut file = PieceFile::empty().unwrap();
+
+    file.insert("foobar", 0).unwrap();
+    file.delete(0, 3).unwrap();
+    file.undo().unwrap();
+    file.delete(3, 3).unwrap();
+    file.undo().unwrap();
+    file.insert("car", 6).unwrap();
+
+    file.prev_branch().unwrap();
+    assert_eq!(file.current_edit(), 3);
+    let read = file.read_at(0, 3).unwrap();
+    assert_eq!(read.as_str(), "foo");
+
+    file.prev_branch().unwrap();
+    let read = file.read_at(0, 3).unwrap();
+    assert_eq!(read.as_str(), "bar");
+
+    // There is no branch before the first one.
+    file.prev_branch().unwrap();
+    assert_eq!(file.current_edit(), 2);
+
+    file.next_branch().unwrap();
+    file.next_branch().unwrap();
+    assert_eq!(file.current_edit(), 4);
+    assert_eq!(file.piece_table.len(), 2);
+    assert_eq!(file.piece_table.get(0).unwrap().length, 6);
+}
+
+#[test]
+fn it_goes_back_in_time() {
+    let mut file = PieceFile::empty().unwrap();
+
+    file.insert("foo", 0).unwrap();
+    file.insert("bar", 3).unwrap();
+    file.insert("car", 6).unwrap();
+
+    let now = time::Instant::now();
+    file.actions[0].timestamp = now - time::Duration::from_secs(600);
+    file.actions[1].timestamp = now - time::Duration::from_secs(300);
+    file.actions[2].timestamp = now;
+
+    file.earlier(time::Duration::from_secs(200)).unwrap();
+    assert_eq!(file.current_edit(), 2);
+
+    file.earlier(time::Duration::from_secs(200)).unwrap();
+    assert_eq!(file.current_edit(), 1);
+    let read = file.read_at(0, 3).unwrap();
+    assert_eq!(read.as_str(), "foo");
+
+    file.earlier(time::Duration::from_secs(200)).unwrap();
+    assert_eq!(file.current_edit(), 0);
+    assert_eq!(file.len(), 0);
+
+    file.later(time::Duration::from_secs(600)).unwrap();
+    assert_eq!(file.current_edit(), 3);
+}
+
+#[test]
+fn it_moves_through_edits_across_branches() {
+    let mut file = PieceFile::empty().unwrap();
+
+    file.insert("foo", 0).unwrap();
+    file.insert("bar", 3).unwrap();
+    file.undo().unwrap();
+    file.insert("car", 3).unwrap();
+
+    file.earlier_edits(1).unwrap();
+    assert_eq!(file.current_edit(), 2);
+    let read = file.read_at(0, 6).unwrap();
+    assert_eq!(read.as_str(), "foobar");
+
+    file.later_edits(5).unwrap();
+    assert_eq!(file.current_edit(), 3);
+    let read = file.read_at(0, 6).unwrap();
+    assert_eq!(read.as_str(), "foocar");
+}
+
+/// Make a file in the temporary directory with some contents and
+/// return its path.
+#[cfg(test)]
+fn make_temp_file<C: AsRef<[u8]>>(name : &str, contents : C) -> String {
+    use std::env;
+
+    let path = env::temp_dir().join(format!("byt-{}-{}", process::id(), name));
+    let path = String::from(path.to_str().unwrap());
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+#[test]
+fn it_saves_a_shorter_file() {
+    let path     = make_temp_file("shorter", "foobar\n");
+    let mut file = PieceFile::open(path.as_str()).unwrap();
+
+    file.delete(0, 3).unwrap();
+    assert_eq!(file.save().unwrap(), 4);
+    assert_eq!(fs::read_to_string(&path).unwrap(), "bar\n");
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn it_reads_bytes_that_arent_utf8() {
+    let path     = make_temp_file("latin1", b"caf\xe9\n");
+    let mut file = PieceFile::open_with_encoding(path.as_str(), Encoding::Utf8).unwrap();
+
+    assert_eq!(file.read_bytes_at(0, 5).unwrap(), b"caf\xe9\n".to_vec());
+    match file.read_at(0, 5).unwrap_err() {
+        FileError::InvalidUtf8 { offset } => assert_eq!(offset, 3),
+        err                               => panic!("Unexpected error {}", err),
+    }
+    assert_eq!(file.read_at(0, 3).unwrap().as_str(), "caf");
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn it_saves_bytes_that_arent_utf8() {
+    let path     = make_temp_file("latin1_save", b"\xff\xfeabc\xe9");
+    let mut file = PieceFile::open_with_encoding(path.as_str(), Encoding::Utf8).unwrap();
+
+    file.insert("\u{e9}", 2).unwrap();
+    file.delete(5, 1).unwrap();
+    assert_eq!(file.save().unwrap(), 7);
+    assert_eq!(fs::read(&path).unwrap(), b"\xff\xfe\xc3\xa9ac\xe9".to_vec());
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn it_converts_a_utf16_file() {
+    let path     = make_temp_file("utf16", b"\xff\xfeh\x00i\x00\n\x00");
+    let mut file = PieceFile::open(path.as_str()).unwrap();
+
+    assert_eq!(file.encoding(), Encoding::Utf16Le);
+    assert_eq!(file.len(), 3);
+
+    file.insert("\u{e9}", 2).unwrap();
+    assert_eq!(file.save().unwrap(), 10);
+    assert_eq!(fs::read(&path).unwrap(), b"\xff\xfeh\x00i\x00\xe9\x00\n\x00".to_vec());
+
+    // Undo needs text from before the save.
+    file.undo().unwrap();
+    assert_eq!(file.read_at(0, 3).unwrap().as_str(), "hi\n");
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn it_doesnt_keep_decoded_text_in_memory() {
+    // Big enough that characters are split between chunks when saving.
+    let mut contents = vec![b'a'];
+    contents.extend(vec![0xe9u8; 100 * 1024]);
+
+    let path     = make_temp_file("latin1_big", &contents);
+    let mut file = PieceFile::open(path.as_str()).unwrap();
+
+    assert_eq!(file.encoding(), Encoding::Latin1);
+    assert_eq!(file.len(), 1 + 200 * 1024);
+    assert!(file.append_file.is_empty());
+
+    file.insert("b", 1).unwrap();
+    file.save().unwrap();
+    contents.insert(1, b'b');
+    assert_eq!(fs::read(&path).unwrap(), contents);
+    assert_eq!(file.read_at(0, 4).unwrap().as_str(), "ab\u{e9}");
+    assert_eq!(file.append_file.len(), 1);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn it_skips_the_utf8_byte_order_mark() {
+    let path     = make_temp_file("utf8_bom", b"\xef\xbb\xbffoo");
+    let mut file = PieceFile::open(path.as_str()).unwrap();
+
+    assert_eq!(file.encoding(), Encoding::Utf8Bom);
+    assert_eq!(file.read_at(0, 3).unwrap().as_str(), "foo");
+
+    file.insert("x", 3).unwrap();
+    file.save().unwrap();
+    assert_eq!(fs::read(&path).unwrap(), b"\xef\xbb\xbffoox".to_vec());
+    assert_eq!(file.read_at(0, 4).unwrap().as_str(), "foox");
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn it_changes_the_encoding() {
+    let path     = make_temp_file("to_latin1", "caf\u{e9}");
+    let mut file = PieceFile::open(path.as_str()).unwrap();
+
+    assert_eq!(file.encoding(), Encoding::Utf8);
+    file.set_encoding(Encoding::Latin1);
+    assert_eq!(file.save().unwrap(), 4);
+    assert_eq!(fs::read(&path).unwrap(), b"caf\xe9".to_vec());
+    assert_eq!(file.read_at(0, 5).unwrap().as_str(), "caf\u{e9}");
+
+    // Not everything fits in Latin-1.
+    file.insert("\u{20ac}", 0).unwrap();
+    assert_eq!(io::Error::from(file.save().unwrap_err()).kind(), ErrorKind::InvalidData);
+    assert_eq!(fs::read(&path).unwrap(), b"caf\xe9".to_vec());
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn it_undoes_after_saving() {
+    let path     = make_temp_file("undo_save", "foobar");
+    let mut file = PieceFile::open(path.as_str()).unwrap();
+
+    file.delete(1, 2).unwrap();
+    file.insert("xy", 0).unwrap();
+    file.save().unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "xyfbar");
+
+    file.undo().unwrap();
+    file.undo().unwrap();
+    let read = file.read_at(0, 6).unwrap();
+    assert_eq!(read.as_str(), "foobar");
+
+    file.save().unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "foobar");
+
+    file.redo().unwrap();
+    let read = file.read_at(0, 4).unwrap();
+    assert_eq!(read.as_str(), "fbar");
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn it_keeps_permissions_when_saving() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = make_temp_file("permissions", "foo");
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+
+    let mut file = PieceFile::open(path.as_str()).unwrap();
+    file.insert("bar", 3).unwrap();
+    file.save().unwrap();
+
+    let mode = fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o640);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn it_saves_an_empty_file_as() {
+    let path     = make_temp_file("save_as", "");
+    let mut file = PieceFile::empty().unwrap();
+    assert!(file.save().is_err());
+
+    file.insert("foo", 0).unwrap();
+    file.save_as(path.as_str()).unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "foo");
+
+    file.insert("bar", 3).unwrap();
+    file.save().unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "foobar");
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn it_detects_a_modified_file() {
+    let path     = make_temp_file("modified", "foo");
+    let mut file = PieceFile::open(path.as_str()).unwrap();
+    assert_eq!(file.conflict(), None);
+
+    fs::write(&path, "foobar!").unwrap();
+    assert_eq!(file.conflict(), Some(Conflict::Modified));
+    assert!(file.read_at(0, 3).is_err());
+    assert!(file.insert("x", 0).is_err());
+    assert!(file.save().is_err());
+    assert!(file.keep_buffer().is_err());
+
+    file.reload().unwrap();
+    assert_eq!(file.conflict(), None);
+    let read = file.read_at(0, 7).unwrap();
+    assert_eq!(read.as_str(), "foobar!");
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn it_keeps_the_buffer_of_a_replaced_file() {
+    let path     = make_temp_file("replaced", "foo");
+    let other    = make_temp_file("replacement", "barbar");
+    let mut file = PieceFile::open(path.as_str()).unwrap();
+
+    fs::rename(&other, &path).unwrap();
+    assert_eq!(file.conflict(), Some(Conflict::Replaced));
+    assert!(file.save().is_err());
+
+    // We still have the old file open, so nothing is lost.
+    let read = file.read_at(0, 3).unwrap();
+    assert_eq!(read.as_str(), "foo");
+
+    file.keep_buffer().unwrap();
+    assert_eq!(file.conflict(), None);
+    file.save().unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "foo");
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn it_detects_a_deleted_file() {
+    let path     = make_temp_file("deleted", "foo");
+    let mut file = PieceFile::open(path.as_str()).unwrap();
+
+    fs::remove_file(&path).unwrap();
+    assert_eq!(file.conflict(), Some(Conflict::Deleted));
+
+    file.keep_buffer().unwrap();
+    file.save().unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "foo");
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn it_recovers_unsaved_edits() {
+    use std::mem;
+
+    let path = make_temp_file("recover", "foo bar baz");
+
+    {
+        let mut file = PieceFile::open(path.as_str()).unwrap();
+        file.insert("x", 0).unwrap();
+        file.delete(4, 4).unwrap();
+        // Bringing back text from the original file.
+        file.undo().unwrap();
+        file.insert("!", 12).unwrap();
+        assert!(Journal::exists(path.as_str()));
+
+        // Pretend byt died.
+        mem::forget(file);
+    }
+
+    let mut file = PieceFile::open(path.as_str()).unwrap();
+    assert!(file.has_recovery());
+
+    // Edits made before recovering are undone first.
+    file.insert("y", 0).unwrap();
+    file.recover().unwrap();
+    assert!(!file.has_recovery());
+    assert_eq!(file.read_at(0, 13).unwrap().as_str(), "xfoo bar baz!");
+
+    drop(file);
+    assert!(!Journal::exists(path.as_str()));
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn it_discards_unsaved_edits() {
+    use std::mem;
+
+    let path     = make_temp_file("discard", "foo");
+    let mut file = PieceFile::open(path.as_str()).unwrap();
+    file.insert("bar", 3).unwrap();
+    mem::forget(file);
+
+    let mut file = PieceFile::open(path.as_str()).unwrap();
+    assert!(file.has_recovery());
+
+    // The journal isn't touched until it is dealt with.
+    file.insert("baz", 0).unwrap();
+    assert_eq!(Journal::read(path.as_str()).unwrap().1.len(), 1);
+
+    file.discard_recovery().unwrap();
+    assert!(!file.has_recovery());
+    assert!(!Journal::exists(path.as_str()));
+    assert!(file.recover().is_err());
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn it_doesnt_recover_onto_a_changed_file() {
+    use std::mem;
+
+    let path     = make_temp_file("recover-changed", "foo");
+    let mut file = PieceFile::open(path.as_str()).unwrap();
+    file.delete(0, 1).unwrap();
+    mem::forget(file);
+
+    fs::write(&path, "foobar").unwrap();
+
+    let mut file = PieceFile::open(path.as_str()).unwrap();
+    match file.recover().unwrap_err() {
+        FileError::RecoveryMismatch => {},
+        err                         => panic!("Unexpected error {}", err),
+    }
+    assert!(file.has_recovery());
+
+    file.discard_recovery().unwrap();
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn it_removes_the_journal_when_saving() {
+    let path     = make_temp_file("journal-save", "foo");
+    let mut file = PieceFile::open(path.as_str()).unwrap();
+
+    file.insert("bar", 3).unwrap();
+    assert!(Journal::exists(path.as_str()));
+
+    file.save().unwrap();
+    assert!(!Journal::exists(path.as_str()));
+
+    fs::remove_file(&path).unwrap();
+}
+
+/// Keep undo stores in a directory of the test's own for the rest of the
+/// test and return it.
+#[cfg(test)]
+fn make_undo_dir(name : &str) -> PathBuf {
+    use std::env;
+
+    let directory = env::temp_dir().join(format!("byt-{}-{}-undo", process::id(), name));
+    fs::create_dir_all(&directory).unwrap();
+    history::TEST_UNDO_DIR.with(|undo_dir| *undo_dir.borrow_mut() = Some(directory.clone()));
+    directory
+}
+
+#[test]
+fn it_keeps_undo_history_across_sessions() {
+    let path      = make_temp_file("history", "foo");
+    let directory = make_undo_dir("history");
+
+    {
+        let mut file = PieceFile::open(path.as_str()).unwrap();
+        file.insert("bar", 3).unwrap();
+        file.delete(0, 1).unwrap();
+        file.save().unwrap();
+    }
+
+    let mut file = PieceFile::open(path.as_str()).unwrap();
+    assert_eq!(file.num_edits(), 2);
+    assert_eq!(file.current_edit(), 2);
+
+    file.undo().unwrap();
+    assert_eq!(file.read_at(0, 6).unwrap().as_str(), "foobar");
+    file.undo().unwrap();
+    assert_eq!(file.read_at(0, 3).unwrap().as_str(), "foo");
+    file.goto_edit(2).unwrap();
+    assert_eq!(file.read_at(0, 5).unwrap().as_str(), "oobar");
+
+    fs::remove_file(history::path_for(path.as_str()).unwrap()).unwrap();
+    fs::remove_file(&path).unwrap();
+    fs::remove_dir(&directory).unwrap();
+}
+
+#[test]
+fn it_keeps_undo_history_for_a_converted_file() {
+    let path      = make_temp_file("history-latin1", b"caf\xe9");
+    let directory = make_undo_dir("history-latin1");
+
+    {
+        let mut file = PieceFile::open(path.as_str()).unwrap();
+        file.insert("!", 5).unwrap();
+        file.save().unwrap();
+    }
+
+    // The stored history has to make room for the converted text.
+    let mut file = PieceFile::open(path.as_str()).unwrap();
+    assert_eq!(file.read_at(0, 6).unwrap().as_str(), "caf\u{e9}!");
+    file.undo().unwrap();
+    assert_eq!(file.read_at(0, 5).unwrap().as_str(), "caf\u{e9}");
+
+    fs::remove_file(history::path_for(path.as_str()).unwrap()).unwrap();
+    fs::remove_file(&path).unwrap();
+    fs::remove_dir(&directory).unwrap();
+}
+
+#[test]
+fn it_throws_away_history_for_a_changed_file() {
+    let path      = make_temp_file("history-changed", "foo");
+    let directory = make_undo_dir("history-changed");
+
+    {
+        let mut file = PieceFile::open(path.as_str()).unwrap();
+        file.insert("bar", 3).unwrap();
+        file.save().unwrap();
+    }
+
+    // Same length, different contents.
+    fs::write(&path, "bazbar").unwrap();
+
+    let file = PieceFile::open(path.as_str()).unwrap();
+    assert_eq!(file.num_edits(), 0);
+    assert!(!history::path_for(path.as_str()).unwrap().exists());
+
+    fs::remove_file(&path).unwrap();
+    fs::remove_dir(&directory).unwrap();
+}
+
+#[test]
+fn it_throws_away_history_in_an_old_format() {
+    let path      = make_temp_file("history-old", "foo");
+    let directory = make_undo_dir("history-old");
+
+    {
+        let mut file = PieceFile::open(path.as_str()).unwrap();
+        file.insert("bar", 3).unwrap();
+        file.save().unwrap();
+    }
+
+    // Stores written before undo groups were kept look the same otherwise.
+    let store        = history::path_for(path.as_str()).unwrap();
+    let mut contents = fs::read(&store).unwrap();
+    contents[7]      = b'1';
+    fs::write(&store, contents).unwrap();
+
+    let file = PieceFile::open(path.as_str()).unwrap();
+    assert_eq!(file.num_edits(), 0);
+    assert!(!store.exists());
+
+    fs::remove_file(&path).unwrap();
+    fs::remove_dir(&directory).unwrap();
+}
+
+#[test]
+fn it_throws_away_history_that_isnt_a_tree() {
+    use byt::io::journal::read_u64;
+
+    let path      = make_temp_file("history-cycle", "foo");
+    let directory = make_undo_dir("history-cycle");
+
+    {
+        let mut file = PieceFile::open(path.as_str()).unwrap();
+        file.insert("bar", 3).unwrap();
+        file.insert("baz", 6).unwrap();
+        file.save().unwrap();
+    }
+
+    // Find the first action, which follows the header, the append buffer,
+    // the current action and the roots.
+    let store        = history::path_for(path.as_str()).unwrap();
+    let mut contents = fs::read(&store).unwrap();
+    let read_at      = |at : usize| read_u64(&mut &contents[at ..]).unwrap() as usize;
+
+    let mut at = history::MAGIC.len() + 16;
+    at        += 8 + read_at(at);
+    at        += 8 + read_at(at) + 8;
+    at        += 8 + 8 * read_at(at) + 8;
+    // Its flags, offset, length and time come before its parent.
+    at        += 3 + 8 * 4;
+
+    // Make the first action the child of the second as well as its
+    // parent, which would send undo around in circles.
+    assert_eq!(read_at(at), usize::MAX);
+    contents[at .. at + 8].copy_from_slice(&1u64.to_le_bytes());
+    fs::write(&store, contents).unwrap();
+
+    let file = PieceFile::open(path.as_str()).unwrap();
+    assert_eq!(file.num_edits(), 0);
+    assert!(!store.exists());
+
+    fs::remove_file(&path).unwrap();
+    fs::remove_dir(&directory).unwrap();
+}
+
+#[test]
+fn it_only_keeps_history_in_the_undo_dir() {
+    use std::env;
+
+    let path = make_temp_file("history-off", "foo");
+
+    {
+        let mut file = PieceFile::open(path.as_str()).unwrap();
+        file.insert("bar", 3).unwrap();
+        file.save().unwrap();
+    }
+
+    // Without an undo directory nothing is left next to the file.
+    let prefix = format!(".byt-{}-history-off", process::id());
+    let litter = fs::read_dir(env::temp_dir()).unwrap()
+        .filter_map(|entry| entry.ok())
+        .any(|entry| entry.file_name().to_string_lossy().starts_with(prefix.as_str()));
+    assert!(!litter);
+    assert_eq!(PieceFile::open(path.as_str()).unwrap().num_edits(), 0);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn it_doesnt_store_history_that_didnt_change() {
+    let path      = make_temp_file("history-same", "foo");
+    let directory = make_undo_dir("history-same");
+    let store     = history::path_for(path.as_str()).unwrap();
+
+    let mut file = PieceFile::open(path.as_str()).unwrap();
+    file.save().unwrap();
+    assert!(!store.exists());
+
+    file.insert("bar", 3).unwrap();
+    file.save().unwrap();
+    assert!(store.exists());
+
+    // Saving again with nothing new leaves the store alone.
+    fs::remove_file(&store).unwrap();
+    file.save().unwrap();
+    assert!(!store.exists());
+
+    file.undo().unwrap();
+    file.save().unwrap();
+    assert!(store.exists());
+
+    fs::remove_file(&store).unwrap();
+    fs::remove_file(&path).unwrap();
+    fs::remove_dir(&directory).unwrap();
+}
+
+/// A small xorshift generator so the randomized tests below are
+/// reproducible from their seed.
+#[cfg(test)]
+struct Rng(u64);
+
+#[cfg(test)]
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Get a number in `0 .. bound`.
+    fn below(&mut self, bound : usize) -> usize {
+        (self.next() % (bound as u64)) as usize
+    }
+
+    fn text(&mut self) -> String {
+        let length = 1 + self.below(8);
+        (0 .. length).map(|_| (b'a' + self.below(26) as u8) as char).collect()
+    }
+}
+
+/// Make a random edit to both a PieceFile and a String that models it.
+#[cfg(test)]
+fn random_edit(rng : &mut Rng, file : &mut PieceFile, model : &mut String) {
+    let length = model.len();
+
+    if length == 0 || rng.below(3) > 0 {
+        let offset = rng.below(length + 1);
+        let text   = rng.text();
+
+        file.insert(text.as_str(), offset).unwrap();
+        model.insert_str(offset, text.as_str());
+    } else {
+        let offset    = rng.below(length);
+        let num_bytes = 1 + rng.below(cmp::min(8, length - offset));
+
+        file.delete(offset, num_bytes).unwrap();
+        model.replace_range(offset .. offset + num_bytes, "");
+    }
+}
+
+/// Check that a PieceFile is consistent and holds the same text as its
+/// model.
+#[cfg(test)]
+fn check_model(file : &mut PieceFile, model : &str, seed : u64, step : usize) {
+    if let Err(err) = file.check_invariants() {
+        panic!("seed {} step {}: {}", seed, step, err);
+    }
+
+    let length = file.len();
+    let text   = file.read_at(0, length).unwrap();
+    assert_eq!(text.as_str(), model, "seed {} step {}", seed, step);
+}
+
+#[test]
+fn it_checks_invariants() {
+    let mut file = PieceFile::empty().unwrap();
+    file.insert("foobar", 0).unwrap();
+    file.delete(2, 2).unwrap();
+    assert!(file.check_invariants().is_ok());
+
+    file.piece_table.update(1, |piece| piece.length += 1);
+
+    match file.check_invariants().unwrap_err() {
+        FileError::BrokenInvariant(_) => {},
+        err                           => panic!("Unexpected error {}", err),
+    }
+}
+
+#[test]
+fn it_matches_a_string_model() {
+    for seed in 1 .. 201 {
+        let path     = make_temp_file(format!("model-{}", seed).as_str(), "the original file");
+        let mut file = PieceFile::open(path.as_str()).unwrap();
+        let mut rng  = Rng(seed);
+
+        // The states `undo` and `redo` move between, in order.
+        let mut history  = vec![String::from("the original file")];
+        let mut position = 0;
+
+        for step in 0 .. 100 {
+            match rng.below(6) {
+                0 if position > 0 => {
+                    file.undo().unwrap();
+                    position -= 1;
+                },
+                1 if position + 1 < history.len() => {
+                    file.redo().unwrap();
+                    position += 1;
+                },
+                _ => {
+                    let mut model = history[position].clone();
+                    random_edit(&mut rng, &mut file, &mut model);
+
+                    // A new edit is what `redo` follows from now on.
+                    history.truncate(position + 1);
+                    history.push(model);
+                    position += 1;
+                },
+            }
+
+            check_model(&mut file, history[position].as_str(), seed, step);
+        }
+
+        fs::remove_file(&path).unwrap();
+    }
+}
+
+#[test]
+fn it_goes_to_any_edit() {
+    for seed in 1 .. 101 {
+        let mut file = PieceFile::empty().unwrap();
+        let mut rng  = Rng(seed);
+
+        // The text after each edit, by edit number.
+        let mut states = vec![String::new()];
+
+        for step in 0 .. 60 {
+            if rng.below(4) == 0 {
+                let edit = rng.below(states.len());
+                file.goto_edit(edit).unwrap();
+                assert_eq!(file.current_edit(), edit);
+            } else {
+                let mut model = states[file.current_edit()].clone();
+                random_edit(&mut rng, &mut file, &mut model);
+                states.push(model);
+            }
+
+            let current = file.current_edit();
+            check_model(&mut file, states[current].as_str(), seed, step);
+        }
+    }
+}
+
+#[test]
+fn it_finds_text_across_pieces() {
+    let mut file = PieceFile::empty().unwrap();
+    file.insert("hello world", 0).unwrap();
+    file.insert("XY", 5).unwrap();
+
+    let pattern = Pattern::literal("oXY w");
+
+    assert_eq!(file.find_next(&pattern, 0).unwrap(), Some(Match { start : 4, end : 9 }));
+    assert_eq!(file.find_next(&pattern, 5).unwrap(), None);
+    assert_eq!(file.find_prev(&pattern, 13).unwrap(), Some(Match { start : 4, end : 9 }));
+    assert_eq!(file.find_prev(&pattern, 4).unwrap(), None);
+}
+
+#[test]
+fn it_finds_all_matches_in_a_range() {
+    let mut file = PieceFile::empty().unwrap();
+    file.insert("foo bar foo baz foo", 0).unwrap();
+
+    let pattern = Pattern::regex("fo+").unwrap();
+
+    assert_eq!(file.find_all(&pattern, 1, 17).unwrap(),
+               vec![Match { start : 8, end : 11 }, Match { start : 16, end : 19 }]);
+}
+
+/// Get the matches of a regular expression that start at every offset it
+/// matches at, as found in the whole text at once.
+#[cfg(test)]
+fn every_match(regex : &::regex::bytes::Regex, text : &[u8]) -> Vec<Match> {
+    let mut matches = Vec::new();
+    let mut at      = 0;
+
+    while let Some(found) = regex.find_at(text, at) {
+        matches.push(Match { start : found.start(), end : found.end() });
+        at = found.start() + 1;
+
+        if at > text.len() {
+            break;
+        }
+    }
+
+    matches
+}
+
+#[test]
+fn it_searches_like_the_whole_text_would() {
+    let sources = ["ab", r"(?m)^b+$", r"\bab", "a(b|c)a", r"a\nb"];
+
+    for seed in 1 .. 4 {
+        let mut rng  = Rng(seed);
+        let mut file = PieceFile::empty().unwrap();
+        let mut text = String::new();
+
+        // Build the file out of lots of pieces.
+        while text.len() < 50 * 1024 {
+            let chunk : String = (0 .. 1 + rng.below(200))
+                .map(|_| ['a', 'b', 'c', ' ', '\n'][rng.below(5)])
+                .collect();
+            let offset = rng.below(text.len() + 1);
+
+            file.insert(chunk.as_str(), offset).unwrap();
+            text.insert_str(offset, chunk.as_str());
+        }
+
+        for source in sources.iter() {
+            let pattern = Pattern::regex(source).unwrap();
+            let regex   = ::regex::bytes::Regex::new(source).unwrap();
+            let matches = every_match(&regex, text.as_bytes());
+
+            for _ in 0 .. 50 {
+                let offset = rng.below(text.len() + 1);
+
+                let next = regex.find_at(text.as_bytes(), offset)
+                    .map(|found| Match { start : found.start(), end : found.end() });
+                let prev = matches.iter().rfind(|found| found.start < offset).cloned();
+
+                assert_eq!(file.find_next(&pattern, offset).unwrap(), next, "{} from {}", source, offset);
+                assert_eq!(file.find_prev(&pattern, offset).unwrap(), prev, "{} before {}", source, offset);
+            }
+        }
+    }
+}
+
+#[test]
+fn it_replaces_all_matches_as_one_edit() {
+    let mut file = PieceFile::empty().unwrap();
+    file.insert("a=1, b=2, c=3", 0).unwrap();
+
+    let pattern = Pattern::regex(r"(\w)=(\d)").unwrap();
+
+    assert_eq!(file.replace_all(&pattern, "$2:$1", 0, 9).unwrap(), 2);
+    assert_eq!(file.read_at(0, file.len()).unwrap().as_str(), "1:a, 2:b, c=3");
+    file.check_invariants().unwrap();
+
+    file.undo().unwrap();
+    assert_eq!(file.read_at(0, file.len()).unwrap().as_str(), "a=1, b=2, c=3");
+
+    file.redo().unwrap();
+    assert_eq!(file.read_at(0, file.len()).unwrap().as_str(), "1:a, 2:b, c=3");
+}
+
+#[test]
+fn it_replaces_matches_in_ranges_as_one_edit() {
+    let mut file = PieceFile::empty().unwrap();
+    file.insert("a a\nb b\na a", 0).unwrap();
+
+    let pattern = Pattern::regex("a").unwrap();
+    let ranges  = vec![(0, 3), (4, 7), (8, 11)];
+
+    let (counts, changes) = file.replace_in_ranges(&pattern, "cc", &ranges, false).unwrap();
+    assert_eq!(counts, vec![1, 0, 1]);
+    assert_eq!(changes, vec![
+        TextChange { offset : 8, removed : 1, inserted : 2 },
+        TextChange { offset : 0, removed : 1, inserted : 2 },
+    ]);
+    assert_eq!(file.read_at(0, file.len()).unwrap().as_str(), "cc a\nb b\ncc a");
+
+    file.undo().unwrap();
+    assert_eq!(file.read_at(0, file.len()).unwrap().as_str(), "a a\nb b\na a");
+
+    let (counts, _) = file.replace_in_ranges(&pattern, "c", &ranges, true).unwrap();
+    assert_eq!(counts, vec![2, 0, 2]);
+    assert_eq!(file.read_at(0, file.len()).unwrap().as_str(), "c c\nb b\nc c");
+    file.check_invariants().unwrap();
+
+    file.undo().unwrap();
+    assert_eq!(file.read_at(0, file.len()).unwrap().as_str(), "a a\nb b\na a");
+}
+
+#[test]
+fn it_doesnt_replace_a_match_running_into_the_next_range_twice() {
+    let mut file = PieceFile::empty().unwrap();
+    file.insert("ab\ncd", 0).unwrap();
+
+    let pattern = Pattern::regex(r"b\nc|c").unwrap();
+
+    let (counts, _) = file.replace_in_ranges(&pattern, "-", &[(0, 2), (3, 5)], true).unwrap();
+    assert_eq!(counts, vec![1, 0]);
+    assert_eq!(file.read_at(0, file.len()).unwrap().as_str(), "a-d");
+}
+
+#[test]
+fn it_replaces_at_the_ends_of_ranges_and_on_empty_ones() {
+    let mut file = PieceFile::empty().unwrap();
+    file.insert("a\n\nb", 0).unwrap();
+
+    let ranges = vec![(0, 1), (2, 2), (3, 4)];
+
+    let (counts, _) = file.replace_in_ranges(&Pattern::regex("(?m)^").unwrap(), "# ", &ranges, false).unwrap();
+    assert_eq!(counts, vec![1, 1, 1]);
+    assert_eq!(file.read_at(0, file.len()).unwrap().as_str(), "# a\n# \n# b");
+
+    let ranges = vec![(0, 3), (4, 6), (7, 10)];
+
+    let (counts, _) = file.replace_in_ranges(&Pattern::regex("(?m)$").unwrap(), ";", &ranges, true).unwrap();
+    assert_eq!(counts, vec![1, 1, 1]);
+    assert_eq!(file.read_at(0, file.len()).unwrap().as_str(), "# a;\n# ;\n# b;");
+    file.check_invariants().unwrap();
+}
+
+#[test]
+fn it_replaces_matches_against_the_original_text() {
+    let mut file = PieceFile::empty().unwrap();
+    file.insert("aab", 0).unwrap();
+
+    // Deleting the first `a` would make the second one match if we looked
+    // again after replacing it.
+    let pattern = Pattern::regex("^a").unwrap();
+
+    assert_eq!(file.replace_all(&pattern, "", 0, 3).unwrap(), 1);
+    assert_eq!(file.read_at(0, file.len()).unwrap().as_str(), "ab");
+}
+
+#[test]
+fn it_replaces_the_next_match() {
+    let mut file = PieceFile::empty().unwrap();
+    file.insert("foo foo", 0).unwrap();
+
+    let pattern = Pattern::literal("foo");
+
+    assert_eq!(file.replace_next(&pattern, "ba", 1).unwrap(), Some((Match { start : 4, end : 7 }, 2)));
+    assert_eq!(file.read_at(0, file.len()).unwrap().as_str(), "foo ba");
+    assert_eq!(file.replace_next(&pattern, "ba", 1).unwrap(), None);
+}
+
+#[test]
+fn it_combines_text_changes() {
+    let change = |offset, removed, inserted| TextChange { offset, removed, inserted };
+
+    // Typing two characters.
+    assert_eq!(change(3, 0, 1).then(&change(4, 0, 1)), change(3, 0, 2));
+    // Deleting backwards.
+    assert_eq!(change(5, 2, 0).then(&change(2, 3, 0)), change(2, 5, 0));
+    // Changes that don't touch cover everything between them.
+    assert_eq!(change(8, 1, 3).then(&change(2, 2, 0)), change(2, 7, 7));
 }
@@ -0,0 +1,282 @@
+//! byt - io::journal
+//!
+//! Recovery files for unsaved edits. As a PieceFile is edited, every change
+//! to its text is appended to a journal that sits next to the file. If byt
+//! dies before the file is saved the journal is left behind, and replaying
+//! it onto the original file gets the edits back.
+//!
+//! A journal starts with a header describing the original file so that we
+//! don't replay edits onto a file that has changed since. Each record after
+//! that is a one byte tag followed by little-endian u64 fields:
+//!
+//! * `i` offset length bytes: text was inserted.
+//! * `c` offset file_offset length: text from the original file was
+//!   inserted, which happens when a delete is undone.
+//! * `d` offset length: text was deleted.
+
+// EXTERNS
+
+// LIBRARY INCLUDES
+use std::fs::{File, OpenOptions};
+use std::fs;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// SUBMODULES
+mod tests;
+
+// LOCAL INCLUDES
+
+/// Identifies journal files and the version of their format.
+const MAGIC : &[u8] = b"BYTJOURNAL1\n";
+
+/// Describes the original file a journal's edits apply to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Header {
+    /// The size of the original file in bytes.
+    pub size     : u64,
+    /// When the original file was last modified, if that is known.
+    pub modified : Option<SystemTime>,
+}
+
+impl Header {
+    /// Describe a file given its metadata.
+    pub fn new(metadata : &fs::Metadata) -> Header {
+        Header {
+            size     : metadata.len(),
+            modified : metadata.modified().ok(),
+        }
+    }
+
+    fn write<W: Write>(&self, out : &mut W) -> io::Result<()> {
+        // The modification time is stored as seconds and nanoseconds since
+        // the epoch, or all ones if we don't know it.
+        let (seconds, nanos) = match self.modified.map(|time| time.duration_since(UNIX_EPOCH)) {
+            Some(Ok(since)) => (since.as_secs(), since.subsec_nanos() as u64),
+            _               => (u64::MAX, u64::MAX),
+        };
+
+        out.write_all(MAGIC)?;
+        write_u64(out, self.size)?;
+        write_u64(out, seconds)?;
+        write_u64(out, nanos)
+    }
+
+    fn read(bytes : &mut &[u8]) -> io::Result<Header> {
+        if !bytes.starts_with(MAGIC) {
+            return Err(Error::new(ErrorKind::InvalidData, "Not a byt journal"));
+        }
+
+        *bytes = &bytes[MAGIC.len() ..];
+
+        let size    = read_u64(bytes)?;
+        let seconds = read_u64(bytes)?;
+        let nanos   = read_u64(bytes)?;
+
+        let modified = if seconds == u64::MAX {
+            None
+        } else {
+            Some(UNIX_EPOCH + Duration::new(seconds, nanos as u32))
+        };
+
+        Ok(Header {
+            size,
+            modified,
+        })
+    }
+}
+
+/// A change to the text of a PieceFile.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Record {
+    /// `text` was inserted at `offset`.
+    Insert { offset : usize, text : Vec<u8> },
+    /// `length` bytes starting at `file_offset` in the original file were
+    /// inserted at `offset`.
+    Copy { offset : usize, file_offset : usize, length : usize },
+    /// `length` bytes were deleted at `offset`.
+    Delete { offset : usize, length : usize },
+}
+
+impl Record {
+    fn write<W: Write>(&self, out : &mut W) -> io::Result<()> {
+        match *self {
+            Record::Insert { offset, ref text } => {
+                out.write_all(b"i")?;
+                write_u64(out, offset as u64)?;
+                write_u64(out, text.len() as u64)?;
+                out.write_all(text)
+            },
+            Record::Copy { offset, file_offset, length } => {
+                out.write_all(b"c")?;
+                write_u64(out, offset as u64)?;
+                write_u64(out, file_offset as u64)?;
+                write_u64(out, length as u64)
+            },
+            Record::Delete { offset, length } => {
+                out.write_all(b"d")?;
+                write_u64(out, offset as u64)?;
+                write_u64(out, length as u64)
+            },
+        }
+    }
+
+    fn read(bytes : &mut &[u8]) -> io::Result<Record> {
+        let tag = match bytes.first() {
+            Some(&tag) => tag,
+            None       => return Err(Error::new(ErrorKind::UnexpectedEof, "Journal ended")),
+        };
+
+        *bytes = &bytes[1 ..];
+
+        match tag {
+            b'i' => {
+                let offset = read_u64(bytes)? as usize;
+                let length = read_u64(bytes)? as usize;
+
+                if bytes.len() < length {
+                    return Err(Error::new(ErrorKind::UnexpectedEof, "Journal ended"));
+                }
+
+                let text = bytes[.. length].to_vec();
+                *bytes   = &bytes[length ..];
+
+                Ok(Record::Insert { offset, text })
+            },
+            b'c' => {
+                let offset      = read_u64(bytes)? as usize;
+                let file_offset = read_u64(bytes)? as usize;
+                let length      = read_u64(bytes)? as usize;
+
+                Ok(Record::Copy { offset, file_offset, length })
+            },
+            b'd' => {
+                let offset = read_u64(bytes)? as usize;
+                let length = read_u64(bytes)? as usize;
+
+                Ok(Record::Delete { offset, length })
+            },
+            _ => Err(Error::new(ErrorKind::InvalidData, "Unknown journal record")),
+        }
+    }
+}
+
+/// Write a little-endian u64.
+pub fn write_u64<W: Write>(out : &mut W, value : u64) -> io::Result<()> {
+    out.write_all(&value.to_le_bytes())
+}
+
+/// Read a little-endian u64 off the front of `bytes`.
+pub fn read_u64(bytes : &mut &[u8]) -> io::Result<u64> {
+    if bytes.len() < 8 {
+        return Err(Error::new(ErrorKind::UnexpectedEof, "Journal ended"));
+    }
+
+    let mut value = [0u8; 8];
+    value.copy_from_slice(&bytes[.. 8]);
+    *bytes = &bytes[8 ..];
+
+    Ok(u64::from_le_bytes(value))
+}
+
+/// The journal for one file. Nothing is written to disk until the first
+/// record is, so files that are only looked at don't get a journal.
+pub struct Journal {
+    path   : PathBuf,
+    header : Header,
+    file   : Option<File>,
+}
+
+impl Journal {
+    // ###############################
+    // P U B L I C  F U N C T I O N S
+    // ###############################
+
+    /// Check whether there is a journal on disk for a file.
+    pub fn exists(path : &str) -> bool {
+        Journal::path_for(path).exists()
+    }
+
+    /// Make a journal for the file at `path`, which is described by
+    /// `header`.
+    pub fn new(path : &str, header : Header) -> Journal {
+        Journal {
+            path   : Journal::path_for(path),
+            header,
+            file   : None,
+        }
+    }
+
+    /// Get the path of the journal for a file. Journals live next to the
+    /// file they belong to and are hidden.
+    pub fn path_for(path : &str) -> PathBuf {
+        let path      = Path::new(path);
+        let directory = match path.parent() {
+            Some(parent) => parent.to_path_buf(),
+            None         => PathBuf::new(),
+        };
+        let name = match path.file_name() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None       => String::new(),
+        };
+
+        directory.join(format!(".{}.byt-journal", name))
+    }
+
+    /// Read the journal for a file. A record that was only partly written
+    /// when byt died is ignored.
+    pub fn read(path : &str) -> io::Result<(Header, Vec<Record>)> {
+        let mut contents = Vec::new();
+        File::open(Journal::path_for(path))?.read_to_end(&mut contents)?;
+
+        let mut bytes   = contents.as_slice();
+        let header      = Header::read(&mut bytes)?;
+        let mut records = Vec::new();
+
+        while !bytes.is_empty() {
+            match Record::read(&mut bytes) {
+                Ok(record) => records.push(record),
+                Err(ref err) if err.kind() == ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok((header, records))
+    }
+
+    /// Delete the journal for a file, whoever wrote it.
+    pub fn remove_file(path : &str) -> io::Result<()> {
+        fs::remove_file(Journal::path_for(path))
+    }
+
+    /// Delete this journal from disk if it was ever written.
+    pub fn remove(&mut self) -> io::Result<()> {
+        if self.file.take().is_some() {
+            fs::remove_file(&self.path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Add a record to the journal, creating it if need be. Records are
+    /// written straight to the file but not synced, which is enough to
+    /// survive byt crashing but not the machine going down.
+    pub fn write(&mut self, record : &Record) -> io::Result<()> {
+        if self.file.is_none() {
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&self.path)?;
+
+            self.header.write(&mut file)?;
+            self.file = Some(file);
+        }
+
+        let mut bytes = Vec::new();
+        record.write(&mut bytes)?;
+        self.file.as_mut().unwrap().write_all(&bytes)
+    }
+}
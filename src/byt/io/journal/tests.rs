@@ -0,0 +1,63 @@
+/// Tests for recovery journals.
+#[cfg(test)]
+use super::*;
+
+/// Get a path in the temporary directory.
+#[cfg(test)]
+fn temp_path(name : &str) -> String {
+    use std::env;
+    use std::process;
+
+    let path = env::temp_dir().join(format!("byt-{}-journal-{}", process::id(), name));
+    String::from(path.to_str().unwrap())
+}
+
+#[test]
+fn it_puts_journals_next_to_files() {
+    assert_eq!(Journal::path_for("foo/bar.txt"), PathBuf::from("foo/.bar.txt.byt-journal"));
+    assert_eq!(Journal::path_for("bar.txt"), PathBuf::from(".bar.txt.byt-journal"));
+}
+
+#[test]
+fn it_writes_and_reads_records() {
+    let path   = temp_path("records");
+    let header = Header { size : 3, modified : Some(UNIX_EPOCH + Duration::new(5, 6)) };
+
+    let mut journal = Journal::new(path.as_str(), header.clone());
+    assert!(!Journal::exists(path.as_str()));
+
+    let records = vec![
+        Record::Insert { offset : 1, text : b"foo".to_vec() },
+        Record::Copy { offset : 0, file_offset : 2, length : 1 },
+        Record::Delete { offset : 3, length : 2 },
+    ];
+
+    for record in records.iter() {
+        journal.write(record).unwrap();
+    }
+
+    assert!(Journal::exists(path.as_str()));
+    assert_eq!(Journal::read(path.as_str()).unwrap(), (header, records));
+
+    journal.remove().unwrap();
+    assert!(!Journal::exists(path.as_str()));
+}
+
+#[test]
+fn it_ignores_a_partly_written_record() {
+    let path        = temp_path("partial");
+    let mut journal = Journal::new(path.as_str(), Header { size : 0, modified : None });
+
+    journal.write(&Record::Delete { offset : 0, length : 1 }).unwrap();
+
+    {
+        let mut file = OpenOptions::new().append(true).open(Journal::path_for(path.as_str())).unwrap();
+        file.write_all(b"i\x01\x00").unwrap();
+    }
+
+    let (header, records) = Journal::read(path.as_str()).unwrap();
+    assert_eq!(header.modified, None);
+    assert_eq!(records, vec![Record::Delete { offset : 0, length : 1 }]);
+
+    journal.remove().unwrap();
+}
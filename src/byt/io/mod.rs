@@ -9,6 +9,12 @@
 
 // SUBMODULES
 pub mod binds;
+pub mod diff;
+pub mod encoding;
 pub mod file;
+pub mod journal;
+pub mod search;
+pub mod source;
+pub mod tree;
 
 // LOCAL INCLUDES
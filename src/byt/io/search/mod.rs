@@ -0,0 +1,185 @@
+//! byt - io::search
+//!
+//! Patterns for finding text in a PieceFile. Searches never read the whole
+//! file at once; PieceFile reads it a window at a time, across as many
+//! pieces as the window happens to cover, and runs the pattern over each
+//! window. Literal text is matched by escaping it into a regular expression
+//! so that both kinds of pattern are searched the same way.
+
+// EXTERNS
+
+// LIBRARY INCLUDES
+use regex::bytes::Regex;
+use regex;
+
+// SUBMODULES
+mod tests;
+
+// LOCAL INCLUDES
+
+/// How much further than the longest possible match a window reaches when
+/// the length of a regular expression's matches isn't known. Matches longer
+/// than this can be missed if they cross the edge of a window.
+const REGEX_OVERLAP : usize = 4 * 1024;
+
+/// A range of bytes in a file that a Pattern matched. `end` is exclusive.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Match {
+    pub start : usize,
+    pub end   : usize,
+}
+
+impl Match {
+    /// Move the match along by `offset` bytes, which turns an offset into
+    /// a window into an offset into the file.
+    pub fn shift(&self, offset : usize) -> Match {
+        Match {
+            start : self.start + offset,
+            end   : self.end + offset,
+        }
+    }
+
+    /// Check whether a match is empty, which some regular expressions
+    /// allow.
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// Get the length of the match in bytes.
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+}
+
+/// Compile a regular expression for a Pattern.
+///
+/// Given a starting offset, regex 0.2 finds anchored literals like `^foo`
+/// at that offset whether or not they are at the start of the text, except
+/// when it is also asked for capture groups. A group on the end that only
+/// ever matches nothing makes sure there are always some to ask for, and
+/// doesn't change what the expression matches or how its groups are
+/// numbered.
+fn compile(source : &str) -> Result<Regex, regex::Error> {
+    Regex::new(format!("(?:{})(x{{0}})", source).as_str())
+}
+
+/// Something to search for.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    /// The text or regular expression the pattern was made from.
+    source  : String,
+    regex   : Regex,
+    /// The length of the longest match, which is only known for literal
+    /// patterns.
+    longest : Option<usize>,
+}
+
+impl Pattern {
+    /// Make a pattern that matches `text` exactly.
+    pub fn literal(text : &str) -> Pattern {
+        Pattern {
+            source  : String::from(text),
+            regex   : compile(regex::escape(text).as_str()).unwrap(),
+            longest : Some(text.len()),
+        }
+    }
+
+    /// Make a pattern from a regular expression, in the syntax of the
+    /// `regex` crate.
+    pub fn regex(source : &str) -> Result<Pattern, regex::Error> {
+        Ok(Pattern {
+            source  : String::from(source),
+            regex   : compile(source)?,
+            longest : None,
+        })
+    }
+
+    /// Get the text or regular expression the pattern was made from.
+    pub fn as_str(&self) -> &str {
+        self.source.as_str()
+    }
+
+    /// Make the text that replaces the match starting at `start` in `text`.
+    /// For regular expressions `$1`, `$name` and `${name}` are replaced by
+    /// what that group captured, or nothing if it didn't, and `$$` is a
+    /// dollar sign. Replacements for literal patterns are used as they are.
+    pub fn expand(&self, text : &[u8], start : usize, replacement : &str) -> Vec<u8> {
+        if self.longest.is_some() {
+            return replacement.as_bytes().to_vec();
+        }
+
+        let mut locations = self.regex.locations();
+        self.regex.read_captures_at(&mut locations, text, start);
+
+        let names : Vec<Option<&str>> = self.regex.capture_names().collect();
+        let group = |name : &str| -> Option<(usize, usize)> {
+            let index = match name.parse::<usize>() {
+                Ok(index) => Some(index),
+                Err(_)    => names.iter().position(|other| *other == Some(name)),
+            };
+
+            index.and_then(|index| locations.pos(index))
+        };
+
+        let mut result = Vec::with_capacity(replacement.len());
+        let mut rest   = replacement;
+
+        while let Some(dollar) = rest.find('$') {
+            result.extend_from_slice(&rest.as_bytes()[.. dollar]);
+            rest = &rest[dollar + 1 ..];
+
+            if rest.starts_with('$') {
+                result.push(b'$');
+                rest = &rest[1 ..];
+                continue;
+            }
+
+            // Names go on for as long as they can unless they're in braces.
+            let (name, after) = if rest.starts_with('{') {
+                match rest.find('}') {
+                    Some(close) => (&rest[1 .. close], &rest[close + 1 ..]),
+                    None        => ("", rest),
+                }
+            } else {
+                let length = rest
+                    .find(|c : char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(rest.len());
+
+                (&rest[.. length], &rest[length ..])
+            };
+
+            // A dollar sign that isn't followed by a name is just a dollar
+            // sign.
+            if name.is_empty() {
+                result.push(b'$');
+                continue;
+            }
+
+            if let Some((group_start, group_end)) = group(name) {
+                result.extend_from_slice(&text[group_start .. group_end]);
+            }
+
+            rest = after;
+        }
+
+        result.extend_from_slice(rest.as_bytes());
+        result
+    }
+
+    /// Find the first match in `text` that starts at or after `start`.
+    /// Text before `start` is only looked at for anchors like `^` and `\b`.
+    pub fn find_at(&self, text : &[u8], start : usize) -> Option<Match> {
+        let mut locations = self.regex.locations();
+
+        self.regex.read_captures_at(&mut locations, text, start).map(|found| Match {
+            start : found.start(),
+            end   : found.end(),
+        })
+    }
+
+    /// Get the number of bytes a window needs to reach past the last
+    /// offset a match could start at for the match to fit inside of it.
+    pub fn overlap(&self) -> usize {
+        self.longest.unwrap_or(REGEX_OVERLAP)
+    }
+}
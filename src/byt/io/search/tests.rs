@@ -0,0 +1,58 @@
+/// Tests for search patterns.
+#[cfg(test)]
+use super::*;
+
+#[test]
+fn it_escapes_literal_patterns() {
+    let pattern = Pattern::literal("a.b*");
+
+    assert_eq!(pattern.find_at(b"aXbb a.b*", 0), Some(Match { start : 5, end : 9 }));
+    assert_eq!(pattern.overlap(), 4);
+}
+
+#[test]
+fn it_keeps_the_source_of_a_pattern() {
+    assert_eq!(Pattern::literal("a.b").as_str(), "a.b");
+    assert_eq!(Pattern::regex(r"a\.b").unwrap().as_str(), r"a\.b");
+}
+
+#[test]
+fn it_uses_context_before_the_start() {
+    let pattern = Pattern::regex(r"\bfoo").unwrap();
+
+    assert_eq!(pattern.find_at(b"xfoo foo", 1), Some(Match { start : 5, end : 8 }));
+    assert_eq!(pattern.find_at(b"xfoo foo", 0), Some(Match { start : 5, end : 8 }));
+    assert_eq!(pattern.find_at(b" foo", 1), Some(Match { start : 1, end : 4 }));
+}
+
+#[test]
+fn it_rejects_invalid_regexes() {
+    assert!(Pattern::regex("(foo").is_err());
+}
+
+#[test]
+fn it_expands_capture_groups() {
+    let pattern = Pattern::regex(r"(?P<key>\w+)=(\w+)").unwrap();
+    let text    = b"a=1 foo=bar";
+
+    assert_eq!(pattern.expand(text, 4, "$2=${key}"), b"bar=foo".to_vec());
+    assert_eq!(pattern.expand(text, 4, "$0 costs $$5 and $"), b"foo=bar costs $5 and $".to_vec());
+    assert_eq!(pattern.expand(text, 4, "$3$nope."), b".".to_vec());
+}
+
+#[test]
+fn it_does_not_expand_literal_replacements() {
+    let pattern = Pattern::literal("(a)");
+
+    assert_eq!(pattern.expand(b"(a)", 0, "$1"), b"$1".to_vec());
+}
+
+#[test]
+fn it_only_matches_anchors_at_the_start() {
+    for source in ["^a", r"\Aa", "^(a|b)"].iter() {
+        let pattern = Pattern::regex(source).unwrap();
+
+        assert_eq!(pattern.find_at(b"aab", 0), Some(Match { start : 0, end : 1 }));
+        assert_eq!(pattern.find_at(b"aab", 1), None);
+    }
+}
@@ -0,0 +1,215 @@
+//! byt - io::source
+//!
+//! Random access to the original file behind a PieceFile. Where possible
+//! the file is memory-mapped, which leaves it up to the operating system
+//! to page in the parts we look at and drop them again under memory
+//! pressure. If the file can't be mapped we fall back to reading it in
+//! fixed-size chunks and keeping only the most recently used ones around,
+//! so memory use stays bounded either way.
+
+// EXTERNS
+
+// LIBRARY INCLUDES
+use libc;
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{Error, ErrorKind, Read, Seek, SeekFrom};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::ptr;
+use std::slice;
+
+// SUBMODULES
+mod tests;
+
+// LOCAL INCLUDES
+
+/// The number of bytes the chunk cache reads from disk at a time.
+const CHUNK_SIZE : usize = 64 * 1024;
+/// The most chunks the chunk cache keeps in memory at once.
+const MAX_CHUNKS : usize = 64;
+
+/// A read-only memory mapping of a whole file.
+struct Mapping {
+    pointer : *mut libc::c_void,
+    length  : usize,
+}
+
+// The mapping is never written to, so it's safe to hand to another thread.
+unsafe impl Send for Mapping {}
+
+impl Mapping {
+    /// Map a file into memory. Returns None if the operating system won't,
+    /// which is always the case for empty files.
+    fn new(file : &File, length : usize) -> Option<Mapping> {
+        if length == 0 {
+            return None;
+        }
+
+        let pointer = unsafe {
+            libc::mmap(ptr::null_mut(),
+                       length as libc::size_t,
+                       libc::PROT_READ,
+                       libc::MAP_PRIVATE,
+                       file.as_raw_fd(),
+                       0)
+        };
+
+        if pointer == libc::MAP_FAILED {
+            return None;
+        }
+
+        Some(Mapping {
+            pointer,
+            length,
+        })
+    }
+
+    fn bytes(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.pointer as *const u8, self.length) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.pointer, self.length as libc::size_t);
+        }
+    }
+}
+
+/// Keeps the most recently used chunks of a file in memory.
+struct ChunkCache {
+    file       : File,
+    chunk_size : usize,
+    max_chunks : usize,
+    chunks     : HashMap<usize, Vec<u8>>,
+    /// The indices of the cached chunks, least recently used first.
+    recent     : VecDeque<usize>,
+}
+
+impl ChunkCache {
+    /// Get a chunk by its index, reading it from disk if necessary.
+    fn chunk(&mut self, index : usize) -> io::Result<&Vec<u8>> {
+        if self.chunks.contains_key(&index) {
+            self.recent.retain(|&chunk| chunk != index);
+        } else {
+            let mut chunk = Vec::with_capacity(self.chunk_size);
+            self.file.seek(SeekFrom::Start((index * self.chunk_size) as u64))?;
+            (&mut self.file).take(self.chunk_size as u64).read_to_end(&mut chunk)?;
+
+            if self.recent.len() == self.max_chunks {
+                let oldest = self.recent.pop_front().unwrap();
+                self.chunks.remove(&oldest);
+            }
+
+            self.chunks.insert(index, chunk);
+        }
+
+        self.recent.push_back(index);
+        Ok(&self.chunks[&index])
+    }
+}
+
+enum Backing {
+    Mapped(Mapping),
+    Cached(ChunkCache),
+}
+
+/// Reads ranges of bytes out of a file that isn't expected to change.
+///
+/// Memory-mapped files see changes other programs make to the file, and
+/// reading past the end of a mapped file that was truncated kills the
+/// process. Check that the file hasn't been modified before reading.
+pub struct FileSource {
+    backing : Backing,
+    length  : usize,
+}
+
+impl FileSource {
+    // ###############################
+    // P U B L I C  F U N C T I O N S
+    // ###############################
+
+    /// Memory-map a file, or fall back to the chunk cache if that isn't
+    /// possible.
+    pub fn new(file : File) -> io::Result<FileSource> {
+        let length = file.metadata()?.len() as usize;
+
+        match Mapping::new(&file, length) {
+            Some(mapping) => Ok(FileSource {
+                backing : Backing::Mapped(mapping),
+                length,
+            }),
+            None => FileSource::with_cache(file, CHUNK_SIZE, MAX_CHUNKS),
+        }
+    }
+
+    /// Read a file through a cache of at most `max_chunks` chunks of
+    /// `chunk_size` bytes each without trying to memory-map it.
+    pub fn with_cache(file : File, chunk_size : usize, max_chunks : usize) -> io::Result<FileSource> {
+        let length = file.metadata()?.len() as usize;
+
+        Ok(FileSource {
+            backing : Backing::Cached(ChunkCache {
+                file,
+                chunk_size,
+                max_chunks,
+                chunks : HashMap::new(),
+                recent : VecDeque::new(),
+            }),
+            length,
+        })
+    }
+
+    /// Check whether the file is memory-mapped.
+    pub fn is_mapped(&self) -> bool {
+        match self.backing {
+            Backing::Mapped(_) => true,
+            Backing::Cached(_) => false,
+        }
+    }
+
+    /// Get the length of the file when it was opened.
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    /// Append `num_bytes` bytes starting at `offset` to `dest`. Fails
+    /// with `ErrorKind::UnexpectedEof` if the file isn't that long.
+    pub fn read_at(&mut self, offset : usize, num_bytes : usize, dest : &mut Vec<u8>) -> io::Result<()> {
+        let truncated = Error::new(ErrorKind::UnexpectedEof, "Original file was truncated");
+
+        if offset + num_bytes > self.length {
+            return Err(truncated);
+        }
+
+        match self.backing {
+            Backing::Mapped(ref mapping) => {
+                dest.extend_from_slice(&mapping.bytes()[offset .. offset + num_bytes]);
+            },
+            Backing::Cached(ref mut cache) => {
+                let chunk_size = cache.chunk_size;
+                let mut offset = offset;
+                let end        = offset + num_bytes;
+
+                while offset < end {
+                    let chunk = cache.chunk(offset / chunk_size)?;
+                    let start = offset % chunk_size;
+                    let stop  = start + cmp::min(end - offset, chunk_size - start);
+
+                    // The file got shorter since we opened it.
+                    if stop > chunk.len() {
+                        return Err(truncated);
+                    }
+
+                    dest.extend_from_slice(&chunk[start .. stop]);
+                    offset += stop - start;
+                }
+            },
+        }
+
+        Ok(())
+    }
+}
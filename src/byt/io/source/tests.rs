@@ -0,0 +1,88 @@
+/// Tests for reading original files.
+#[cfg(test)]
+use super::*;
+
+/// Write a file to the temporary directory and open it.
+#[cfg(test)]
+fn make_file(name : &str, contents : &[u8]) -> (String, File) {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    let path = env::temp_dir().join(format!("byt-{}-source-{}", process::id(), name));
+    let path = String::from(path.to_str().unwrap());
+    fs::write(&path, contents).unwrap();
+
+    let file = File::open(&path).unwrap();
+    (path, file)
+}
+
+#[test]
+fn it_maps_a_file() {
+    use std::fs;
+
+    let (path, file) = make_file("mapped", b"foobar");
+    let mut source   = FileSource::new(file).unwrap();
+
+    assert!(source.is_mapped());
+    assert_eq!(source.len(), 6);
+
+    let mut bytes = Vec::new();
+    source.read_at(2, 3, &mut bytes).unwrap();
+    assert_eq!(bytes, b"oba".to_vec());
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn it_falls_back_for_empty_files() {
+    use std::fs;
+
+    let (path, file) = make_file("empty", b"");
+    let mut source   = FileSource::new(file).unwrap();
+
+    assert!(!source.is_mapped());
+
+    let mut bytes = Vec::new();
+    source.read_at(0, 0, &mut bytes).unwrap();
+    assert_eq!(source.read_at(0, 1, &mut bytes).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn it_reads_across_cached_chunks() {
+    use std::fs;
+
+    let contents : Vec<u8> = (0 .. 100).collect();
+    let (path, file)       = make_file("chunks", &contents);
+    let mut source         = FileSource::with_cache(file, 8, 2).unwrap();
+
+    // Every read touches more chunks than the cache can hold.
+    for offset in 0 .. 70 {
+        let mut bytes = Vec::new();
+        source.read_at(offset, 30, &mut bytes).unwrap();
+        assert_eq!(bytes, contents[offset .. offset + 30].to_vec());
+    }
+
+    if let Backing::Cached(ref cache) = source.backing {
+        assert_eq!(cache.chunks.len(), 2);
+    }
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn it_notices_a_truncated_file() {
+    use std::fs;
+
+    let (path, file) = make_file("truncated", b"foobar");
+    let mut source   = FileSource::with_cache(file, 4, 2).unwrap();
+
+    fs::write(&path, b"foo").unwrap();
+
+    let mut bytes = Vec::new();
+    assert_eq!(source.read_at(2, 4, &mut bytes).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+
+    fs::remove_file(&path).unwrap();
+}
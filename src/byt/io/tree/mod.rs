@@ -0,0 +1,429 @@
+//! byt - io::tree
+//!
+//! A balanced (AVL) tree that stores a sequence of items, like a Vec, but
+//! inserts and removes them anywhere in logarithmic time. Every item has a
+//! weight, which for byt is usually a number of bytes, and the tree can
+//! find the item that covers a particular offset in logarithmic time too.
+//!
+//! Items don't store their own positions. Instead they are told where they
+//! are by `Weighted::locate` whenever they are read out of the tree, which
+//! is what lets us avoid rewriting the offsets of every item after an edit.
+
+// EXTERNS
+
+// LIBRARY INCLUDES
+use std::cmp;
+
+// SUBMODULES
+mod tests;
+
+// LOCAL INCLUDES
+
+/// Something that can be stored in a Tree.
+pub trait Weighted: Clone {
+    /// The number of units (usually bytes) this item covers.
+    fn weight(&self) -> usize;
+
+    /// Called on a copy of the item when it is read out of the tree with
+    /// its index and the total weight of all of the items before it.
+    fn locate(&mut self, index : usize, offset : usize);
+}
+
+struct Node<T: Weighted> {
+    item   : T,
+    left   : Option<Box<Node<T>>>,
+    right  : Option<Box<Node<T>>>,
+    /// The height of the subtree rooted at this node.
+    height : usize,
+    /// The number of items in the subtree rooted at this node.
+    count  : usize,
+    /// The total weight of the subtree rooted at this node.
+    weight : usize,
+}
+
+impl<T: Weighted> Node<T> {
+    fn new(item : T) -> Box<Node<T>> {
+        let weight = item.weight();
+
+        Box::new(Node {
+            item,
+            left   : None,
+            right  : None,
+            height : 1,
+            count  : 1,
+            weight,
+        })
+    }
+
+    /// Recalculate this node's summary from its children.
+    fn update(&mut self) {
+        self.height = 1 + cmp::max(height(&self.left), height(&self.right));
+        self.count  = 1 + count(&self.left) + count(&self.right);
+        self.weight = self.item.weight() + weight(&self.left) + weight(&self.right);
+    }
+}
+
+fn height<T: Weighted>(node : &Option<Box<Node<T>>>) -> usize {
+    node.as_ref().map_or(0, |node| node.height)
+}
+
+fn count<T: Weighted>(node : &Option<Box<Node<T>>>) -> usize {
+    node.as_ref().map_or(0, |node| node.count)
+}
+
+fn weight<T: Weighted>(node : &Option<Box<Node<T>>>) -> usize {
+    node.as_ref().map_or(0, |node| node.weight)
+}
+
+fn rotate_left<T: Weighted>(mut node : Box<Node<T>>) -> Box<Node<T>> {
+    let mut right = node.right.take().unwrap();
+    node.right    = right.left.take();
+    node.update();
+    right.left    = Some(node);
+    right.update();
+    right
+}
+
+fn rotate_right<T: Weighted>(mut node : Box<Node<T>>) -> Box<Node<T>> {
+    let mut left = node.left.take().unwrap();
+    node.left    = left.right.take();
+    node.update();
+    left.right   = Some(node);
+    left.update();
+    left
+}
+
+/// Restore the AVL property at a node whose children differ in height by
+/// at most two.
+fn balance<T: Weighted>(mut node : Box<Node<T>>) -> Box<Node<T>> {
+    node.update();
+
+    let left  = height(&node.left);
+    let right = height(&node.right);
+
+    if left > right + 1 {
+        {
+            let child = node.left.as_ref().unwrap();
+            if height(&child.left) < height(&child.right) {
+                let child = node.left.take().unwrap();
+                node.left = Some(rotate_left(child));
+            }
+        }
+        return rotate_right(node);
+    }
+
+    if right > left + 1 {
+        {
+            let child = node.right.as_ref().unwrap();
+            if height(&child.right) < height(&child.left) {
+                let child  = node.right.take().unwrap();
+                node.right = Some(rotate_right(child));
+            }
+        }
+        return rotate_left(node);
+    }
+
+    node
+}
+
+fn insert<T: Weighted>(node : Option<Box<Node<T>>>, index : usize, item : T) -> Box<Node<T>> {
+    let mut node = match node {
+        Some(node) => node,
+        None       => return Node::new(item),
+    };
+
+    let left_count = count(&node.left);
+
+    if index <= left_count {
+        node.left = Some(insert(node.left.take(), index, item));
+    } else {
+        node.right = Some(insert(node.right.take(), index - left_count - 1, item));
+    }
+
+    balance(node)
+}
+
+/// Remove the leftmost item of a subtree.
+fn remove_first<T: Weighted>(mut node : Box<Node<T>>) -> (Option<Box<Node<T>>>, T) {
+    match node.left.take() {
+        Some(left) => {
+            let (left, item) = remove_first(left);
+            node.left = left;
+            (Some(balance(node)), item)
+        },
+        None => {
+            let node = *node;
+            (node.right, node.item)
+        },
+    }
+}
+
+fn remove<T: Weighted>(mut node : Box<Node<T>>, index : usize) -> (Option<Box<Node<T>>>, T) {
+    let left_count = count(&node.left);
+
+    if index < left_count {
+        let (left, item) = remove(node.left.take().unwrap(), index);
+        node.left = left;
+        return (Some(balance(node)), item);
+    }
+
+    if index > left_count {
+        let (right, item) = remove(node.right.take().unwrap(), index - left_count - 1);
+        node.right = right;
+        return (Some(balance(node)), item);
+    }
+
+    let node = *node;
+
+    match (node.left, node.right) {
+        (None, right) => (right, node.item),
+        (left, None)  => (left, node.item),
+        (left, Some(right)) => {
+            // Replace this node with the first item after it.
+            let (right, next) = remove_first(right);
+            let mut next      = Node::new(next);
+            next.left         = left;
+            next.right        = right;
+            (Some(balance(next)), node.item)
+        },
+    }
+}
+
+fn update<T: Weighted, F: FnOnce(&mut T)>(node : &mut Box<Node<T>>, index : usize, change : F) {
+    let left_count = count(&node.left);
+
+    if index < left_count {
+        update(node.left.as_mut().unwrap(), index, change);
+    } else if index > left_count {
+        update(node.right.as_mut().unwrap(), index - left_count - 1, change);
+    } else {
+        change(&mut node.item);
+    }
+
+    node.update();
+}
+
+/// Build a perfectly balanced subtree out of a run of items.
+fn build<T: Weighted>(items : &mut Vec<Option<T>>, start : usize, end : usize) -> Option<Box<Node<T>>> {
+    if start >= end {
+        return None;
+    }
+
+    let middle   = start + (end - start) / 2;
+    let mut node = Node::new(items[middle].take().unwrap());
+    node.left    = build(items, start, middle);
+    node.right   = build(items, middle + 1, end);
+    node.update();
+
+    Some(node)
+}
+
+/// A sequence of Weighted items with logarithmic lookups, inserts, and
+/// removals.
+pub struct Tree<T: Weighted> {
+    root : Option<Box<Node<T>>>,
+}
+
+impl<T: Weighted> Tree<T> {
+    /// Make a new, empty Tree.
+    pub fn new() -> Tree<T> {
+        Tree {
+            root : None,
+        }
+    }
+
+    /// Make a Tree containing the items in a Vec, in order.
+    pub fn from_vec(items : Vec<T>) -> Tree<T> {
+        let length    = items.len();
+        let mut items = items.into_iter().map(Some).collect();
+
+        Tree {
+            root : build(&mut items, 0, length),
+        }
+    }
+
+    /// Remove all of the items.
+    pub fn clear(&mut self) {
+        self.root = None;
+    }
+
+    /// Find the index of the item that covers an offset. Items with a
+    /// weight of zero never cover anything.
+    pub fn find(&self, offset : usize) -> Option<usize> {
+        let mut node   = self.root.as_ref();
+        let mut offset = offset;
+        let mut index  = 0;
+
+        while let Some(current) = node {
+            let left_weight = weight(&current.left);
+            let item_weight = current.item.weight();
+
+            if offset < left_weight {
+                node = current.left.as_ref();
+            } else if offset < left_weight + item_weight {
+                return Some(index + count(&current.left));
+            } else {
+                offset -= left_weight + item_weight;
+                index  += count(&current.left) + 1;
+                node    = current.right.as_ref();
+            }
+        }
+
+        None
+    }
+
+    /// Get a copy of the item at an index that knows where it is.
+    pub fn get(&self, index : usize) -> Option<T> {
+        let mut node   = self.root.as_ref();
+        let mut index  = index;
+        let mut before = 0;
+        let mut offset = 0;
+
+        while let Some(current) = node {
+            let left_count = count(&current.left);
+
+            if index < left_count {
+                node = current.left.as_ref();
+            } else if index > left_count {
+                index  -= left_count + 1;
+                before += left_count + 1;
+                offset += weight(&current.left) + current.item.weight();
+                node    = current.right.as_ref();
+            } else {
+                let mut item = current.item.clone();
+                item.locate(before + left_count, offset + weight(&current.left));
+                return Some(item);
+            }
+        }
+
+        None
+    }
+
+    /// Insert an item so that it ends up at `index`, shifting everything
+    /// after it over by one.
+    ///
+    /// Panics if `index` is greater than the number of items.
+    pub fn insert(&mut self, index : usize, item : T) {
+        assert!(index <= self.len(), "Tree index out of bounds");
+        self.root = Some(insert(self.root.take(), index, item));
+    }
+
+    /// Iterate over copies of all of the items in order.
+    pub fn iter(&self) -> Iter<'_, T> {
+        self.iter_from(0)
+    }
+
+    /// Iterate over copies of the items in order, starting at `index`.
+    pub fn iter_from(&self, index : usize) -> Iter<'_, T> {
+        let mut iter = Iter {
+            stack  : Vec::new(),
+            index  : 0,
+            offset : 0,
+        };
+
+        // Walk down to the item at `index`, remembering every node we
+        // pass on its right since those come after it.
+        let mut node  = self.root.as_ref();
+        let mut index = index;
+
+        while let Some(current) = node {
+            let left_count = count(&current.left);
+
+            if index < left_count {
+                iter.stack.push(current);
+                node = current.left.as_ref();
+            } else if index > left_count {
+                index       -= left_count + 1;
+                iter.index  += left_count + 1;
+                iter.offset += weight(&current.left) + current.item.weight();
+                node         = current.right.as_ref();
+            } else {
+                iter.index  += left_count;
+                iter.offset += weight(&current.left);
+                iter.stack.push(current);
+                break;
+            }
+        }
+
+        iter
+    }
+
+    /// Get the number of items in the tree.
+    pub fn len(&self) -> usize {
+        count(&self.root)
+    }
+
+    /// Add an item to the end.
+    pub fn push(&mut self, item : T) {
+        let length = self.len();
+        self.insert(length, item);
+    }
+
+    /// Remove the item at an index and return it.
+    ///
+    /// Panics if there is no such item.
+    pub fn remove(&mut self, index : usize) -> T {
+        assert!(index < self.len(), "Tree index out of bounds");
+
+        let (root, item) = remove(self.root.take().unwrap(), index);
+        self.root = root;
+        item
+    }
+
+    /// Get all of the items in a Vec.
+    pub fn to_vec(&self) -> Vec<T> {
+        self.iter().collect()
+    }
+
+    /// Change the item at an index in place.
+    ///
+    /// Panics if there is no such item.
+    pub fn update<F: FnOnce(&mut T)>(&mut self, index : usize, change : F) {
+        assert!(index < self.len(), "Tree index out of bounds");
+        update(self.root.as_mut().unwrap(), index, change);
+    }
+
+    /// Get the total weight of every item in the tree.
+    pub fn weight(&self) -> usize {
+        weight(&self.root)
+    }
+}
+
+/// An in-order iterator over a Tree.
+pub struct Iter<'a, T: 'a + Weighted> {
+    /// Nodes whose items haven't been visited yet. A node's right subtree
+    /// is only pushed once its item is visited.
+    stack  : Vec<&'a Node<T>>,
+    index  : usize,
+    offset : usize,
+}
+
+impl<'a, T: Weighted> Iter<'a, T> {
+    /// Push a node and all of its left descendants onto the stack.
+    fn descend(&mut self, node : Option<&'a Node<T>>) {
+        let mut node = node;
+
+        while let Some(current) = node {
+            self.stack.push(current);
+            node = current.left.as_deref();
+        }
+    }
+}
+
+impl<'a, T: Weighted> Iterator for Iter<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        let node = self.stack.pop()?;
+
+        self.descend(node.right.as_deref());
+
+        let mut item = node.item.clone();
+        item.locate(self.index, self.offset);
+
+        self.index  += 1;
+        self.offset += node.item.weight();
+
+        Some(item)
+    }
+}
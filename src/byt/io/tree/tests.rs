@@ -0,0 +1,121 @@
+/// Tests for the balanced tree.
+#[cfg(test)]
+use super::*;
+
+/// A test item that just records where it was found.
+#[cfg(test)]
+#[derive(Clone, Debug, PartialEq)]
+struct Item {
+    weight : usize,
+    index  : usize,
+    offset : usize,
+}
+
+#[cfg(test)]
+impl Weighted for Item {
+    fn weight(&self) -> usize {
+        self.weight
+    }
+
+    fn locate(&mut self, index : usize, offset : usize) {
+        self.index  = index;
+        self.offset = offset;
+    }
+}
+
+#[cfg(test)]
+fn item(weight : usize) -> Item {
+    Item { weight, index : 0, offset : 0 }
+}
+
+#[test]
+fn it_inserts_in_order() {
+    let mut tree = Tree::new();
+    tree.push(item(1));
+    tree.push(item(2));
+    tree.insert(0, item(3));
+
+    assert_eq!(tree.len(), 3);
+    assert_eq!(tree.weight(), 6);
+
+    let weights : Vec<usize> = tree.iter().map(|item| item.weight).collect();
+    assert_eq!(weights, vec![3, 1, 2]);
+}
+
+#[test]
+fn it_locates_items() {
+    let tree = Tree::from_vec(vec![item(3), item(1), item(2)]);
+
+    let found = tree.get(2).unwrap();
+    assert_eq!(found.index, 2);
+    assert_eq!(found.offset, 4);
+    assert!(tree.get(3).is_none());
+}
+
+#[test]
+fn it_finds_offsets() {
+    let tree = Tree::from_vec(vec![item(3), item(0), item(2)]);
+
+    assert_eq!(tree.find(0), Some(0));
+    assert_eq!(tree.find(2), Some(0));
+    assert_eq!(tree.find(3), Some(2));
+    assert_eq!(tree.find(4), Some(2));
+    assert_eq!(tree.find(5), None);
+}
+
+#[test]
+fn it_iterates_from_an_index() {
+    let tree = Tree::from_vec((1 .. 8).map(item).collect());
+
+    let found : Vec<(usize, usize)> = tree.iter_from(4).map(|item| (item.index, item.offset)).collect();
+    assert_eq!(found, vec![(4, 10), (5, 15), (6, 21)]);
+    assert_eq!(tree.iter_from(7).count(), 0);
+}
+
+#[test]
+fn it_removes_and_updates() {
+    let mut tree = Tree::from_vec(vec![item(1), item(2), item(3)]);
+
+    assert_eq!(tree.remove(1).weight, 2);
+    tree.update(1, |item| item.weight = 5);
+
+    assert_eq!(tree.weight(), 6);
+    assert_eq!(tree.find(5), Some(1));
+}
+
+#[test]
+fn it_clears_every_item() {
+    let mut tree = Tree::from_vec(vec![item(1), item(2), item(3)]);
+    tree.clear();
+
+    assert_eq!(tree.len(), 0);
+    assert_eq!(tree.weight(), 0);
+    assert_eq!(tree.find(0), None);
+}
+
+#[test]
+fn it_stays_balanced() {
+    let mut tree  : Tree<Item> = Tree::new();
+    let mut model = Vec::new();
+    let mut seed  = 7usize;
+
+    for step in 0 .. 2000 {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345) % 2147483648;
+
+        if step % 3 == 2 && !model.is_empty() {
+            let index = seed % model.len();
+            assert_eq!(tree.remove(index).weight, model.remove(index));
+        } else {
+            let index = seed % (model.len() + 1);
+            tree.insert(index, item(step % 5));
+            model.insert(index, step % 5);
+        }
+    }
+
+    let weights : Vec<usize> = tree.iter().map(|item| item.weight).collect();
+    assert_eq!(weights, model);
+
+    // An AVL tree is never more than about 1.44 log2(n) high.
+    let height = tree.root.as_ref().unwrap().height as f64;
+    assert!(height <= 1.45 * (model.len() as f64).log2() + 2.0);
+}
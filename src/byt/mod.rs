@@ -14,7 +14,6 @@ use std::io::{Write, stdout, stdin};
 use std::sync::mpsc::channel;
 use std::thread;
 use termion::cursor::Goto;
-use termion::event::Key;
 use termion::input::TermRead;
 use termion::raw::IntoRawMode;
 use termion::screen::AlternateScreen;
@@ -58,18 +57,14 @@ pub fn init() {
 
     let mut editor = MutatePair::new(editor::Editor::new());
 
+    editor.target_mut().add_file_mutator(|| Box::new(Vym::new()));
+
     if arguments.len() > 1 {
         editor.target_mut().open(arguments.nth(1).unwrap().as_str());
     } else {
         editor.target_mut().open_empty();
     }
 
-    editor
-        .target_mut()
-        .current_file()
-        .unwrap()
-        .register_mutator(Box::new(Vym::new()));
-
     render(&mut screen, &mut editor);
 
     // One thread just reads from user input and makes
@@ -86,21 +81,21 @@ pub fn init() {
 
     loop {
         let event = receiver.recv().unwrap();
-        let sender = sender.clone();
 
         if let Event::KeyPress(key) = event {
-            // Just for now while I mess with other things
-            if key == Key::Char('q') {
-                break;
-            }
-
             let result = editor.consume(key);
 
             if result.is_none() {
                 continue;
             }
 
-            for action in editor.actions() {
+            let actions = editor.actions();
+
+            if actions.contains(&Action::Quit) {
+                break;
+            }
+
+            for action in actions {
                 if let Action::Mutator(name) = action {
                     editor.call_action(name.as_str(), key);
                 }
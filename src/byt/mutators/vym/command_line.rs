@@ -0,0 +1,242 @@
+//! byt - vym::command_line
+//!
+//! The one line prompt that `:` opens at the bottom of the screen. It keeps
+//! the text being typed and where the cursor is in it, along with the
+//! lines that were run before so that they can be brought back with the
+//! arrow keys.
+//!
+//! Tab completion is driven from outside, since what a word can complete
+//! to depends on where it is in the line. The prompt just cycles through
+//! whatever it is given.
+
+// EXTERNS
+
+// LIBRARY INCLUDES
+
+// SUBMODULES
+
+// LOCAL INCLUDES
+
+/// The words the end of the line can complete to.
+struct Completion {
+    /// Where the word being completed starts.
+    start      : usize,
+    candidates : Vec<String>,
+    /// The candidate that is in the line.
+    index      : usize,
+}
+
+pub struct CommandLine {
+    text       : String,
+    /// Where the cursor is in the text, in bytes.
+    cursor     : usize,
+    /// Every line that was run, oldest first.
+    history    : Vec<String>,
+    /// The line in the history being shown, if the arrow keys were used.
+    browsing   : Option<usize>,
+    /// What was being typed before the history was browsed.
+    draft      : String,
+    completion : Option<Completion>,
+}
+
+impl CommandLine {
+    // #################################
+    // P R I V A T E  F U N C T I O N S
+    // #################################
+
+    /// Replace all of the text, leaving the cursor at the end.
+    fn set_text(&mut self, text : &str) {
+        self.text       = String::from(text);
+        self.cursor     = self.text.len();
+        self.completion = None;
+    }
+
+    /// Get the offset of the character before the cursor.
+    fn prev_char(&self) -> usize {
+        self.text[.. self.cursor]
+            .char_indices()
+            .last()
+            .map_or(0, |(offset, _)| offset)
+    }
+
+    // ###############################
+    // P U B L I C  F U N C T I O N S
+    // ###############################
+
+    /// Delete the character before the cursor. Returns false if there
+    /// wasn't anything to delete, which vim takes as a sign to close the
+    /// prompt.
+    pub fn backspace(&mut self) -> bool {
+        if self.text.is_empty() {
+            return false;
+        }
+
+        if self.cursor > 0 {
+            let start = self.prev_char();
+            self.text.drain(start .. self.cursor);
+            self.cursor = start;
+        }
+
+        self.completion = None;
+        true
+    }
+
+    /// Start completing the word at the end of the line, which starts at
+    /// `start`, with the first of `candidates`. Typing Tab again goes on to
+    /// the next one with `next_completion`.
+    pub fn complete(&mut self, start : usize, candidates : Vec<String>) {
+        let first = match candidates.first() {
+            Some(first) => first.clone(),
+            None        => return,
+        };
+
+        self.text.truncate(start);
+        self.text.push_str(first.as_str());
+        self.cursor     = self.text.len();
+        self.completion = Some(Completion { start, candidates, index : 0 });
+    }
+
+    /// Get where the cursor is in the text, in characters.
+    pub fn cursor(&self) -> usize {
+        self.text[.. self.cursor].chars().count()
+    }
+
+    /// Delete the word before the cursor, along with any whitespace after
+    /// it. Like vim, a run of punctuation counts as a word.
+    pub fn delete_word(&mut self) {
+        let word   = |c : char| c.is_alphanumeric() || c == '_';
+        let before = self.text[.. self.cursor].trim_end();
+
+        let start = match before.chars().last() {
+            Some(last) => before
+                .char_indices()
+                .rev()
+                .take_while(|&(_, c)| !c.is_whitespace() && word(c) == word(last))
+                .last()
+                .map_or(before.len(), |(offset, _)| offset),
+            None => 0,
+        };
+
+        self.text.drain(start .. self.cursor);
+        self.cursor     = start;
+        self.completion = None;
+    }
+
+    /// Move the cursor to the end of the text.
+    pub fn end(&mut self) {
+        self.cursor = self.text.len();
+    }
+
+    /// Add the line to the history and close it. Returns the line.
+    pub fn finish(&mut self) -> String {
+        let text = self.text.clone();
+
+        if !text.is_empty() && self.history.last() != Some(&text) {
+            self.history.push(text.clone());
+        }
+
+        self.open("");
+        text
+    }
+
+    /// Move the cursor to the start of the text.
+    pub fn home(&mut self) {
+        self.cursor = 0;
+    }
+
+    /// Type a character at the cursor.
+    pub fn insert(&mut self, c : char) {
+        self.text.insert(self.cursor, c);
+        self.cursor     += c.len_utf8();
+        self.completion = None;
+    }
+
+    /// Move the cursor a character left.
+    pub fn left(&mut self) {
+        self.cursor = self.prev_char();
+    }
+
+    pub fn new() -> CommandLine {
+        CommandLine {
+            text       : String::new(),
+            cursor     : 0,
+            history    : Vec::new(),
+            browsing   : None,
+            draft      : String::new(),
+            completion : None,
+        }
+    }
+
+    /// Go on to the next candidate when completing. Returns false if
+    /// nothing is being completed.
+    pub fn next_completion(&mut self) -> bool {
+        let completion = match self.completion {
+            Some(ref mut completion) => completion,
+            None                     => return false,
+        };
+
+        completion.index = (completion.index + 1) % completion.candidates.len();
+
+        self.text.truncate(completion.start);
+        self.text.push_str(completion.candidates[completion.index].as_str());
+        self.cursor = self.text.len();
+        true
+    }
+
+    /// Start a new line with some text already typed.
+    pub fn open(&mut self, text : &str) {
+        self.set_text(text);
+        self.browsing = None;
+    }
+
+    /// Show the line that was run after the one being shown, or what was
+    /// being typed before the history was browsed.
+    pub fn next_history(&mut self) {
+        let index = match self.browsing {
+            Some(index) => index + 1,
+            None        => return,
+        };
+
+        if index < self.history.len() {
+            let text = self.history[index].clone();
+            self.set_text(text.as_str());
+            self.browsing = Some(index);
+        } else {
+            let draft = self.draft.clone();
+            self.set_text(draft.as_str());
+            self.browsing = None;
+        }
+    }
+
+    /// Show the line that was run before the one being shown.
+    pub fn prev_history(&mut self) {
+        let index = match self.browsing {
+            Some(0)     => return,
+            Some(index) => index - 1,
+            None        => {
+                if self.history.is_empty() {
+                    return;
+                }
+
+                self.draft = self.text.clone();
+                self.history.len() - 1
+            },
+        };
+
+        let text = self.history[index].clone();
+        self.set_text(text.as_str());
+        self.browsing = Some(index);
+    }
+
+    /// Move the cursor a character right.
+    pub fn right(&mut self) {
+        if let Some(c) = self.text[self.cursor ..].chars().next() {
+            self.cursor += c.len_utf8();
+        }
+    }
+
+    /// Get the text that has been typed.
+    pub fn text(&self) -> &str {
+        self.text.as_str()
+    }
+}
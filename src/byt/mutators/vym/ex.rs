@@ -0,0 +1,544 @@
+//! byt - vym::ex
+//!
+//! Ex commands are what gets typed at the prompt `:` opens, like `:w` or
+//! `:%s/foo/bar/g`. A line is made of an optional range of lines, the
+//! name of a command, an optional `!` and the command's arguments.
+//!
+//! Commands are kept in a table by name, so anything that sets up vym can
+//! add its own alongside the ones here.
+
+// EXTERNS
+
+// LIBRARY INCLUDES
+use std::cmp;
+use std::fs;
+
+// SUBMODULES
+
+// LOCAL INCLUDES
+use byt::io::diff::unified;
+use byt::io::encoding::Encoding;
+use byt::io::search::Pattern;
+use byt::views::file::{FileView, LineEnding};
+
+/// The number of unchanged lines `:diff` shows around each change.
+const DIFF_CONTEXT : usize = 3;
+
+/// Every encoding `:set fileencoding` knows about.
+const ENCODINGS : [Encoding; 5] = [
+    Encoding::Utf8,
+    Encoding::Utf8Bom,
+    Encoding::Utf16Le,
+    Encoding::Utf16Be,
+    Encoding::Latin1,
+];
+
+/// The first and last numbers of a range of lines.
+type Lines = (usize, usize);
+
+/// What an ex command is given when it runs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ex {
+    /// The first and last lines typed before the command, if any were.
+    pub range : Option<(usize, usize)>,
+    /// Whether the command's name was followed by `!`.
+    pub bang  : bool,
+    /// Everything after the command's name, without the whitespace around
+    /// it.
+    pub args  : String,
+}
+
+impl Ex {
+    /// Get the lines the command was given, which is just the current line
+    /// if no range was typed.
+    pub fn lines(&self, view : &FileView) -> (usize, usize) {
+        let current = view.current_line().number();
+        self.range.unwrap_or((current, current))
+    }
+}
+
+/// What happens after an ex command runs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Response {
+    /// Nothing needs to be said about it.
+    Done,
+    /// Show the user a message.
+    Message(String),
+    /// Show the user text too long for a message in a scratch buffer.
+    Scratch(String),
+    /// Close the file, or the editor if it's the last one.
+    Quit,
+}
+
+/// Runs an ex command on a FileView. Errors are shown to the user.
+pub type Command = Box<dyn Fn(&mut FileView, &Ex) -> Result<Response, String>>;
+
+struct Entry {
+    name     : String,
+    /// The number of letters of the name that have to be typed.
+    shortest : usize,
+    command  : Command,
+}
+
+/// Every ex command by name.
+pub struct Commands {
+    entries : Vec<Entry>,
+}
+
+impl Commands {
+    /// Add a command. Its name is written the way vim's documentation does,
+    /// with the letters that can be left off in brackets, so `w[rite]` can
+    /// be typed as anything from `w` to `write`. A command with the same
+    /// name is replaced.
+    pub fn add<F>(&mut self, name : &str, command : F)
+        where F: Fn(&mut FileView, &Ex) -> Result<Response, String> + 'static {
+        let shortest = name.find('[').unwrap_or(name.len());
+        let name     = name.replace(['[', ']'], "");
+
+        self.entries.retain(|entry| entry.name != name);
+        self.entries.push(Entry {
+            name,
+            shortest,
+            command : Box::new(command),
+        });
+    }
+
+    /// Find the command that `typed` is a name for.
+    pub fn find(&self, typed : &str) -> Option<&Command> {
+        self.entries
+            .iter()
+            .find(|entry| typed.len() >= entry.shortest && entry.name.starts_with(typed))
+            .map(|entry| &entry.command)
+    }
+
+    /// Get the full names of the commands that start with `prefix`, in
+    /// order.
+    pub fn names(&self, prefix : &str) -> Vec<String> {
+        let mut names : Vec<String> = self.entries
+            .iter()
+            .filter(|entry| entry.name.starts_with(prefix))
+            .map(|entry| entry.name.clone())
+            .collect();
+
+        names.sort();
+        names
+    }
+
+    pub fn new() -> Commands {
+        Commands {
+            entries : Vec::new(),
+        }
+    }
+}
+
+// #######
+// PARSING
+// #######
+
+/// Read a number from the start of some text.
+fn number(text : &str) -> (Option<usize>, &str) {
+    let digits = text.find(|c : char| !c.is_ascii_digit()).unwrap_or(text.len());
+    (text[.. digits].parse().ok(), &text[digits ..])
+}
+
+/// Read a line address from the start of some text, which is a line number,
+/// `.` for the current line, `$` for the last one or `'<` and `'>` for the
+/// first and last lines of the last selection. Any number of `+n` and `-n`
+/// after it move it down and up.
+fn address<'a>(text : &'a str, view : &FileView, marks : Option<(usize, usize)>) -> Result<(Option<usize>, &'a str), String> {
+    let current = view.current_line().number();
+    let last    = view.line_at(view.len()).number();
+
+    let (mut line, mut rest) = match text.chars().next() {
+        Some('.') => (Some(current), &text[1 ..]),
+        Some('$') => (Some(last), &text[1 ..]),
+        Some('\'') if text.starts_with("'<") || text.starts_with("'>") => {
+            let (first, last) = marks.ok_or("Mark not set")?;
+            (Some(if text.starts_with("'<") { first } else { last }), &text[2 ..])
+        },
+        Some(c) if c.is_ascii_digit() => number(text),
+        _                             => (None, text),
+    };
+
+    while rest.starts_with('+') || rest.starts_with('-') {
+        let down           = rest.starts_with('+');
+        let (delta, after) = number(&rest[1 ..]);
+        let delta          = delta.unwrap_or(1) as i64;
+        let base           = line.unwrap_or(current) as i64;
+
+        line = Some(cmp::max(0, if down { base + delta } else { base - delta }) as usize);
+        rest = after;
+    }
+
+    Ok((line, rest))
+}
+
+/// Read the range of lines from the start of a line typed at the prompt,
+/// which is `%` for every line or one or two addresses split by a comma.
+fn range<'a>(text : &'a str, view : &FileView, marks : Option<Lines>) -> Result<(Option<Lines>, &'a str), String> {
+    let last = view.line_at(view.len()).number();
+
+    if let Some(rest) = text.strip_prefix('%') {
+        return Ok((Some((1, last)), rest));
+    }
+
+    let (first, rest) = match address(text, view, marks)? {
+        (Some(first), rest) => (first, rest),
+        (None, rest)        => return Ok((None, rest)),
+    };
+
+    let (second, rest) = if let Some(after) = rest.strip_prefix(',') {
+        match address(after, view, marks)? {
+            (Some(second), rest) => (second, rest),
+            (None, _)            => return Err(String::from("Invalid range")),
+        }
+    } else {
+        (first, rest)
+    };
+
+    if first < 1 || second < 1 || first > last || second > last {
+        return Err(String::from("Invalid range"));
+    }
+
+    Ok((Some((cmp::min(first, second), cmp::max(first, second))), rest))
+}
+
+/// Split a line typed at the prompt into the name of the command and what
+/// it is given. `marks` are the first and last lines of the last selection.
+pub fn parse(line : &str, view : &FileView, marks : Option<(usize, usize)>) -> Result<(String, Ex), String> {
+    let (range, rest) = range(line.trim_start(), view, marks)?;
+    let rest          = rest.trim_start();
+
+    let length = rest.find(|c : char| !c.is_alphabetic()).unwrap_or(rest.len());
+    let name   = String::from(&rest[.. length]);
+    let rest   = &rest[length ..];
+    let bang   = rest.starts_with('!');
+    let rest   = if bang { &rest[1 ..] } else { rest };
+
+    Ok((name, Ex { range, bang, args : String::from(rest.trim()) }))
+}
+
+/// Run a line typed at the prompt. A range without a command moves the
+/// cursor to the last line in it.
+pub fn run(commands : &Commands, line : &str, view : &mut FileView, marks : Option<(usize, usize)>) -> Result<Response, String> {
+    let (name, ex) = parse(line, view, marks)?;
+
+    if name.is_empty() {
+        if !ex.args.is_empty() {
+            return Err(format!("Not an editor command: {}", line.trim()));
+        }
+
+        if let Some((_, last)) = ex.range {
+            goto_line(view, last);
+        }
+
+        return Ok(Response::Done);
+    }
+
+    match commands.find(name.as_str()) {
+        Some(command) => command(view, &ex),
+        None          => Err(format!("Not an editor command: {}", name)),
+    }
+}
+
+/// Fail if the file has changes that haven't been saved, unless the
+/// command was given with a `!` to throw them away.
+fn check_saved(view : &FileView, ex : &Ex) -> Result<(), String> {
+    if view.is_modified() && !ex.bang {
+        return Err(String::from("No write since last change (add ! to override)"));
+    }
+
+    Ok(())
+}
+
+/// Move the cursor to the first thing on a line, like vim does after a
+/// range is typed on its own.
+fn goto_line(view : &mut FileView, number : usize) {
+    let line  = match view.get_line(number) {
+        Some(line) => line,
+        None       => return,
+    };
+
+    let text  = view.text(line.start(), line.content_end()).unwrap_or_default();
+    let blank = text.len() - text.trim_start().len();
+
+    view.clear_selection();
+    view.set_cursor(line.start() + blank);
+}
+
+// ##########
+// COMPLETION
+// ##########
+
+/// Get the files and directories that a path could be completed to.
+/// Directories end with a slash so that completing can go on into them.
+fn paths(word : &str) -> Vec<String> {
+    let split           = word.rfind('/').map_or(0, |index| index + 1);
+    let (parent, start) = word.split_at(split);
+
+    let entries = match fs::read_dir(if parent.is_empty() { "." } else { parent }) {
+        Ok(entries) => entries,
+        Err(_)      => return Vec::new(),
+    };
+
+    let mut paths : Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+
+            // Hidden files only come up if they are asked for.
+            if !name.starts_with(start) || (name.starts_with('.') && !start.starts_with('.')) {
+                return None;
+            }
+
+            let slash = if entry.path().is_dir() { "/" } else { "" };
+            Some(format!("{}{}{}", parent, name, slash))
+        })
+        .collect();
+
+    paths.sort();
+    paths
+}
+
+/// Find what the end of a line typed at the prompt can be completed to. The
+/// name of a command completes to the names of commands, and anything after
+/// it completes to paths. Returns where the text being completed starts
+/// along with what it can become.
+pub fn completions(commands : &Commands, line : &str) -> (usize, Vec<String>) {
+    let start = line.find(|c : char| c.is_alphabetic()).unwrap_or(line.len());
+    let end   = line[start ..].find(|c : char| !c.is_alphabetic()).map_or(line.len(), |length| start + length);
+
+    if end == line.len() {
+        return (start, commands.names(&line[start ..]));
+    }
+
+    let word = line.rfind(char::is_whitespace).map_or(line.len(), |index| index + 1);
+
+    if word <= end {
+        return (line.len(), Vec::new());
+    }
+
+    (word, paths(&line[word ..]))
+}
+
+// ########
+// COMMANDS
+// ########
+
+/// `:w`, which saves the file, or saves it somewhere else if given a path.
+/// With `!` the file is saved even if it changed on disk, as long as none
+/// of the text being edited was lost with it.
+pub fn write(view : &mut FileView, ex : &Ex) -> Result<Response, String> {
+    if ex.bang {
+        view.keep_buffer().map_err(|err| err.to_string())?;
+    }
+
+    let result = match ex.args.as_str() {
+        ""   => view.save(),
+        path => view.save_as(path),
+    };
+
+    let written = result.map_err(|err| err.to_string())?;
+    let path    = view.path().unwrap_or_default();
+
+    Ok(Response::Message(format!("\"{}\" {}B written", path, written)))
+}
+
+/// `:q`, which closes the editor. It refuses if there are unsaved changes
+/// unless it's given as `:q!`.
+pub fn quit(view : &mut FileView, ex : &Ex) -> Result<Response, String> {
+    check_saved(view, ex)?;
+    Ok(Response::Quit)
+}
+
+/// `:wq`, which saves the file and closes the editor.
+pub fn write_quit(view : &mut FileView, ex : &Ex) -> Result<Response, String> {
+    write(view, ex)?;
+    Ok(Response::Quit)
+}
+
+/// `:e`, which opens the file at a path in place of the one being edited,
+/// or opens the file being edited again from disk if there's no path. It
+/// refuses if there are unsaved changes unless it's given as `:e!`. `:e!`
+/// without a path throws away every change, including the undo history,
+/// but leaves the cursor where it is.
+pub fn edit(view : &mut FileView, ex : &Ex) -> Result<Response, String> {
+    let path = match ex.args.as_str() {
+        ""   => String::from(view.path().ok_or("No file name")?),
+        path => String::from(path),
+    };
+
+    check_saved(view, ex)?;
+
+    if ex.bang && ex.args.is_empty() {
+        view.reload().map_err(|err| err.to_string())?;
+    } else {
+        *view = FileView::new(path.as_str()).map_err(|err| err.to_string())?;
+    }
+
+    let lines = view.line_at(view.len()).number();
+    Ok(Response::Message(format!("\"{}\" {}L", path, lines)))
+}
+
+/// `:diff`, which shows how the file on disk differs from the text being
+/// edited as a unified diff in a scratch buffer. Lines only on disk start
+/// with `-` and lines only being edited start with `+`.
+pub fn diff(view : &mut FileView, _ex : &Ex) -> Result<Response, String> {
+    let changes = view.diff_disk().map_err(|err| err.to_string())?;
+
+    let text = unified(&changes, DIFF_CONTEXT);
+
+    match text.len() {
+        0 => Ok(Response::Message(String::from("No differences"))),
+        _ => Ok(Response::Scratch(text)),
+    }
+}
+
+/// `:recover`, which replays the unsaved edits an earlier session left
+/// behind onto the file.
+pub fn recover(view : &mut FileView, _ex : &Ex) -> Result<Response, String> {
+    view.recover().map_err(|err| err.to_string())?;
+    Ok(Response::Done)
+}
+
+/// `:discard`, which deletes the unsaved edits an earlier session left
+/// behind without replaying them.
+pub fn discard(view : &mut FileView, _ex : &Ex) -> Result<Response, String> {
+    view.discard_recovery().map_err(|err| err.to_string())?;
+    Ok(Response::Done)
+}
+
+/// Split the arguments to `:s` by the character they start with, which
+/// can be put in them by escaping it with a backslash.
+fn split_substitute(args : &str) -> Result<Vec<String>, String> {
+    let mut chars = args.chars();
+    let delimiter = match chars.next() {
+        Some(c) if !c.is_alphanumeric() && !c.is_whitespace() && c != '\\' => c,
+        _                                                                  => return Err(String::from("Invalid command")),
+    };
+
+    let mut parts   = vec![String::new()];
+    let mut escaped = false;
+
+    for c in chars {
+        if escaped && c != delimiter {
+            parts.last_mut().unwrap().push('\\');
+        }
+
+        if !escaped && c == delimiter {
+            parts.push(String::new());
+        } else if !escaped && c == '\\' {
+            escaped = true;
+            continue;
+        } else {
+            parts.last_mut().unwrap().push(c);
+        }
+
+        escaped = false;
+    }
+
+    if escaped {
+        parts.last_mut().unwrap().push('\\');
+    }
+
+    Ok(parts)
+}
+
+/// `:s/pattern/replacement/flags`, which replaces the first match of a
+/// regular expression on each line, or every match with the `g` flag. An
+/// empty pattern is the last one searched for. `^` and `$` match at the
+/// start and end of each line, and the replacement can use the pattern's
+/// capture groups as `$1` or `${name}`.
+pub fn substitute(view : &mut FileView, ex : &Ex) -> Result<Response, String> {
+    let parts       = split_substitute(ex.args.as_str())?;
+    let replacement = parts.get(1).map_or("", |part| part.as_str());
+    let flags       = parts.get(2).map_or("", |part| part.as_str());
+
+    let pattern = match parts[0].as_str() {
+        ""     => view.search().cloned().ok_or("No previous regular expression")?,
+        source => Pattern::regex(format!("(?m){}", source).as_str()).map_err(|err| err.to_string())?,
+    };
+
+    let global = match flags {
+        ""  => false,
+        "g" => true,
+        _   => return Err(format!("Trailing characters: {}", flags)),
+    };
+
+    let (first, last) = ex.lines(view);
+    let counts        = view.replace_lines(&pattern, replacement, first, last, global);
+    let count : usize = counts.iter().sum();
+
+    // The cursor goes to the last line something was replaced on.
+    let index = counts.iter().rposition(|&count| count > 0);
+    let index = index.ok_or_else(|| format!("Pattern not found: {}", parts[0]))?;
+    goto_line(view, first + index);
+
+    match count {
+        1     => Ok(Response::Done),
+        count => Ok(Response::Message(format!("{} substitutions", count))),
+    }
+}
+
+/// `:set`, which changes options or shows what they are. Each argument is
+/// the name of an option, which shows it, or `name=value`, which sets it.
+/// The options are `fileformat` (`ff`), which is `unix` or `dos`, and
+/// `fileencoding` (`fenc`), which is the name of an encoding.
+pub fn set(view : &mut FileView, ex : &Ex) -> Result<Response, String> {
+    let args : Vec<&str> = match ex.args.as_str() {
+        ""   => vec!["fileformat", "fileencoding"],
+        args => args.split_whitespace().collect(),
+    };
+
+    let mut shown = Vec::new();
+
+    for arg in args {
+        let (name, value) = match arg.find('=') {
+            Some(index) => (&arg[.. index], Some(&arg[index + 1 ..])),
+            None        => (arg.trim_end_matches('?'), None),
+        };
+
+        match (name, value) {
+            ("fileformat", None) | ("ff", None) => {
+                let format = match view.line_ending() {
+                    LineEnding::Lf   => "unix",
+                    LineEnding::CrLf => "dos",
+                };
+
+                shown.push(format!("fileformat={}", format));
+            },
+            ("fileformat", Some(value)) | ("ff", Some(value)) => {
+                let ending = match value {
+                    "unix" => LineEnding::Lf,
+                    "dos"  => LineEnding::CrLf,
+                    _      => return Err(format!("Invalid argument: {}", arg)),
+                };
+
+                view.convert_line_endings(ending);
+            },
+            ("fileencoding", None) | ("fenc", None) => {
+                shown.push(format!("fileencoding={}", view.encoding().name()));
+            },
+            ("fileencoding", Some(value)) | ("fenc", Some(value)) => {
+                let encoding = ENCODINGS
+                    .iter()
+                    .find(|encoding| encoding.name() == value)
+                    .ok_or_else(|| format!("Invalid argument: {}", arg))?;
+
+                view.set_encoding(*encoding);
+            },
+            _ => return Err(format!("Unknown option: {}", name)),
+        }
+    }
+
+    match shown.len() {
+        0 => Ok(Response::Done),
+        _ => Ok(Response::Message(shown.join("  "))),
+    }
+}
+
+/// `:noh`, which stops highlighting the matches of the last search.
+pub fn nohlsearch(view : &mut FileView, _ex : &Ex) -> Result<Response, String> {
+    view.clear_search();
+    Ok(Response::Done)
+}
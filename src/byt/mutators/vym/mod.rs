@@ -11,9 +11,17 @@
 
 // LIBRARY INCLUDES
 use termion::event::Key;
+use std::cmp;
+use std::collections::HashMap;
 use std::io;
+use termion;
 
 // SUBMODULES
+mod command_line;
+mod ex;
+mod motions;
+mod operators;
+mod registers;
 mod tests;
 
 // LOCAL INCLUDES
@@ -21,113 +29,401 @@ use byt::editor::mutator::*;
 use byt::editor::*;
 use byt::render;
 use byt::render::Renderable;
-use byt::views::file::FileView;
-use byt::io::binds::{Arrow, Keymaster, KeyInput};
+use byt::views::file::{FileView, Selection, SelectionKind};
+use byt::io::binds::{Keymaster, KeyInput};
+use self::command_line::CommandLine;
+use self::ex::Commands;
+pub use self::ex::{Ex, Response};
+use self::motions::{Motion, Range};
+use self::operators::Operator;
+use self::registers::Registers;
 
 // TODO add comments and explain everything
 
 fn init_vym(vym : &mut Vym) {
-    let mut normal = &mut vym.normal;
-    let mut insert = &mut vym.insert;
-    let mut rust   = &mut vym.rust;
+    // #######
+    // MOTIONS
+    // #######
+    vym.add_motion([Key::Char('h')], "vym.left", motions::left);
+    vym.add_motion([Key::Char('l')], "vym.right", motions::right);
+    vym.add_motion([Key::Char('j')], "vym.down", motions::down);
+    vym.add_motion([Key::Char('k')], "vym.up", motions::up);
+    vym.register_motion("vym.0", motions::line_start);
+    vym.add_motion([Key::Char('$')], "vym.$", motions::line_end);
+    vym.add_motion([Key::Char('g'), Key::Char('g')], "vym.to_file_start", motions::file_start);
+    vym.add_motion([Key::Char('G')], "vym.to_file_end", motions::file_end);
+    vym.add_motion([Key::Char('w')], "vym.word", motions::word_forward);
+    vym.add_motion([Key::Char('b')], "vym.word_back", motions::word_backward);
+    vym.add_motion([Key::Char('e')], "vym.word_end", motions::word_end);
+    vym.add_motion([Key::Char('}')], "vym.paragraph", motions::paragraph_forward);
+    vym.add_motion([Key::Char('{')], "vym.paragraph_back", motions::paragraph_backward);
+
+    // These take the character typed after them.
+    vym.add_char_motion([Key::Char('f')], "vym.find", motions::find_forward);
+    vym.add_char_motion([Key::Char('t')], "vym.till", motions::till_forward);
+    vym.add_char_motion([Key::Char('F')], "vym.find_back", motions::find_backward);
+    vym.add_char_motion([Key::Char('T')], "vym.till_back", motions::till_backward);
+
+    // Text objects, like `iw` and `a(`, only work after an operator.
+    vym.add_text_object([Key::Char('i')], "vym.inner", motions::inner);
+    vym.add_text_object([Key::Char('a')], "vym.around", motions::around);
+
+    let normal      = &mut vym.normal;
+    let pending     = &mut vym.pending;
+    let insert      = &mut vym.insert;
+    let command     = &mut vym.command;
+    let mut visuals = [&mut vym.visual, &mut vym.visual_line, &mut vym.visual_block];
+    let rust        = &mut vym.rust;
+
+    // ######
+    // COUNTS
+    // ######
+    // Digits typed before a command are a count for it. `0` moves to the
+    // start of the line unless a count is being typed.
+    rust.register("vym.count", |state, target, input| {
+        let digit = match input.key {
+            Key::Char(c) => c.to_digit(10),
+            _            => None,
+        };
+
+        match (digit, state.count) {
+            (Some(0), None)      => state.motion("vym.0", target, input),
+            (Some(digit), count) => {
+                let count   = count.unwrap_or(0).saturating_mul(10);
+                state.count = Some(count.saturating_add(digit as usize));
+            },
+            _ => {},
+        }
+    });
+
+    for digit in "0123456789".chars() {
+        normal.bind_action([Key::Char(digit)], "vym.count").unwrap();
+        pending.bind_action([Key::Char(digit)], "vym.count").unwrap();
+
+        for visual in visuals.iter_mut() {
+            visual.bind_action([Key::Char(digit)], "vym.count").unwrap();
+        }
+    }
 
     // ###########
     // NORMAL MODE
     // ###########
-    // Initialize the HJKL motions
-    rust.register("vym.right", |state, target, key| {
-        target.move_cursor_right();
+    // Append to end of line
+    rust.register("vym.append", |state, target, _input| {
+        target.goto_line_end();
+        state.insert_mode(target);
     });
-    normal.bind_action([Key::Char('l')], "vym.right");
+    normal.bind_action([Key::Char('A')], "vym.append").unwrap();
 
-    rust.register("vym.left", |state, target, key| {
-        target.move_cursor_left();
+    // Prepend at beginning of line
+    rust.register("vym.prepend", |state, target, _input| {
+        target.goto_line_start();
+        state.insert_mode(target);
     });
-    normal.bind_action([Key::Char('h')], "vym.left");
+    normal.bind_action([Key::Char('I')], "vym.prepend").unwrap();
 
-    rust.register("vym.down", |state, target, key| {
-        target.move_cursor_down();
+    // Move the viewport up and down
+    rust.register("vym.viewport_up", |_state, target, input| {
+        target.move_viewport(-(input.times() as i64));
     });
-    normal.bind_action([Key::Char('j')], "vym.down");
+    normal.bind_action([Key::Ctrl('y')], "vym.viewport_up").unwrap();
 
-    rust.register("vym.up", |state, target, key| {
-        target.move_cursor_up();
+    rust.register("vym.viewport_down", |_state, target, input| {
+        target.move_viewport(input.times() as i64);
     });
-    normal.bind_action([Key::Char('k')], "vym.up");
+    normal.bind_action([Key::Ctrl('e')], "vym.viewport_down").unwrap();
+
+    // Operators wait for a motion or text object and then act on the
+    // text it covers.
+    let operators = [
+        ("vym.delete", Operator::Delete),
+        ("vym.change", Operator::Change),
+        ("vym.yank",   Operator::Yank),
+        ("vym.indent", Operator::Indent),
+        ("vym.outdent", Operator::Outdent),
+    ];
+
+    for &(name, operator) in operators.iter() {
+        rust.register(name, move |state, _target, input| {
+            state.operator       = Some(operator);
+            state.operator_count = input.count;
+            state.mode           = Mode::Operator;
+        });
+        normal.bind_action([operator.key()], name).unwrap();
+
+        // Typing an operator twice applies it to the current line.
+        pending.bind_action([operator.key()], "vym.current_line").unwrap();
+    }
 
-    // Append to end of line
-    rust.register("vym.append", |state, target, key| {
-        target.goto_line_end();
-        state.insert_mode();
+    rust.register("vym.current_line", |state, target, input| {
+        match state.operator {
+            Some(operator) if operator.key() == input.key => state.operate(motions::current_line, target, input),
+            _                                             => state.cancel(),
+        }
     });
-    normal.bind_action([Key::Char('A')], "vym.append");
 
-    // Prepend at beginning of line
-    rust.register("vym.prepend", |state, target, key| {
-        target.goto_line_start();
-        state.insert_mode();
+    // Anything else typed after an operator cancels it.
+    rust.register("vym.cancel", |state, _target, _input| {
+        state.cancel();
+    });
+    let cancel = pending.mutator_action("vym.cancel");
+    pending.get_root().set_wildcard(cancel);
+    normal.bind_action([Key::Esc], "vym.cancel").unwrap();
+
+    // `x` and `X` delete the characters after and before the cursor.
+    rust.register("vym.delete_char", |state, target, input| {
+        state.operator       = Some(Operator::Delete);
+        state.operator_count = None;
+        state.operate(motions::right, target, input);
     });
-    normal.bind_action([Key::Char('I')], "vym.prepend");
+    normal.bind_action([Key::Char('x')], "vym.delete_char").unwrap();
 
-    // Moves to the beginning of the line.
-    rust.register("vym.0", |state, target, key| {
-        target.goto_line_start();
+    rust.register("vym.delete_char_back", |state, target, input| {
+        state.operator       = Some(Operator::Delete);
+        state.operator_count = None;
+        state.operate(motions::left, target, input);
     });
-    normal.bind_action([Key::Char('0')], "vym.0");
+    normal.bind_action([Key::Char('X')], "vym.delete_char_back").unwrap();
+
+    // Typing " and the name of a register makes the next yank, delete or
+    // put use that register.
+    rust.register("vym.register", |state, _target, input| {
+        if let Key::Char(c) = input.key {
+            if Registers::is_register(c) {
+                state.register = Some(c);
+            }
+        }
+    });
+    normal.bind_wildcard([Key::Char('"')], "vym.register").unwrap();
 
-    // Moves to the end of the line.
-    rust.register("vym.$", |state, target, key| {
-        target.goto_line_end();
+    for visual in visuals.iter_mut() {
+        visual.bind_wildcard([Key::Char('"')], "vym.register").unwrap();
+    }
+
+    rust.register("vym.put_after", |state, target, input| {
+        state.put(target, true, input.times());
     });
-    normal.bind_action([Key::Char('$')], "vym.$");
+    normal.bind_action([Key::Char('p')], "vym.put_after").unwrap();
 
-    // Move the viewport up and down
-    rust.register("vym.viewport_up", |state, target, key| {
-        target.move_viewport(-1);
+    rust.register("vym.put_before", |state, target, input| {
+        state.put(target, false, input.times());
     });
-    normal.bind_action([Key::Ctrl('y')], "vym.viewport_up");
+    normal.bind_action([Key::Char('P')], "vym.put_before").unwrap();
 
-    rust.register("vym.viewport_down", |state, target, key| {
-        target.move_viewport(1);
+    rust.register("vym.undo", |_state, target, input| {
+        for _ in 0 .. input.times() {
+            target.undo();
+        }
     });
-    normal.bind_action([Key::Ctrl('e')], "vym.viewport_down");
+    normal.bind_action([Key::Char('u')], "vym.undo").unwrap();
 
-    rust.register("vym.to_file_end", |state, target, key| {
-        target.move_cursor_to_end();
+    rust.register("vym.redo", |_state, target, input| {
+        for _ in 0 .. input.times() {
+            target.redo();
+        }
     });
-    normal.bind_action([Key::Char('G')], "vym.to_file_end");
+    normal.bind_action([Key::Ctrl('r')], "vym.redo").unwrap();
+
+    // ###########
+    // VISUAL MODE
+    // ###########
+    // `v`, `V` and Ctrl+v select characters, lines and blocks. Typing the
+    // one for the kind of selection being made stops selecting, and typing
+    // another changes the kind.
+    rust.register("vym.visual", |state, target, input| {
+        let kind = match input.key {
+            Key::Char('V') => SelectionKind::Line,
+            Key::Ctrl('v') => SelectionKind::Block,
+            _              => SelectionKind::Char,
+        };
 
-    rust.register("vym.to_file_start", |state, target, key| {
-        target.move_cursor_to_start();
+        match (state.mode, target.selection()) {
+            (Mode::Visual(current), _) if current == kind => state.normal_mode(target),
+            (Mode::Visual(_), Some(selection))            => {
+                target.set_selection(Selection { kind, ..selection });
+                state.mode = Mode::Visual(kind);
+            },
+            _ => {
+                target.select(kind);
+                state.mode = Mode::Visual(kind);
+            },
+        }
     });
-    normal.bind_action([Key::Char('g'), Key::Char('g')], "vym.to_file_start");
 
-    // Moves to the end of the line.
-    rust.register("vym.delete_line", |state, target, key| {
-        target.delete_current_line();
+    let kinds = [Key::Char('v'), Key::Char('V'), Key::Ctrl('v')];
+
+    for &key in kinds.iter() {
+        normal.bind_action([key], "vym.visual").unwrap();
+
+        for visual in visuals.iter_mut() {
+            visual.bind_action([key], "vym.visual").unwrap();
+        }
+    }
+
+    // `gv` selects what was selected last time again.
+    rust.register("vym.reselect", |state, target, _input| {
+        if let Some(selection) = state.last_selection {
+            target.set_selection(selection);
+            state.mode = Mode::Visual(selection.kind);
+        }
+    });
+    normal.bind_action([Key::Char('g'), Key::Char('v')], "vym.reselect").unwrap();
+
+    // `o` moves the cursor to the other end of the selection.
+    rust.register("vym.other_end", |_state, target, _input| {
+        if let Some(selection) = target.selection() {
+            target.set_selection(Selection {
+                anchor : selection.cursor,
+                cursor : selection.anchor,
+                ..selection
+            });
+        }
     });
-    normal.bind_action([Key::Char('d'), Key::Char('d')], "vym.delete_line");
+
+    // Operators act on the selection straight away.
+    let visual_operators = [
+        ("vym.visual_delete",      Operator::Delete),
+        ("vym.visual_change",      Operator::Change),
+        ("vym.visual_yank",        Operator::Yank),
+        ("vym.visual_indent",      Operator::Indent),
+        ("vym.visual_outdent",     Operator::Outdent),
+        ("vym.visual_toggle_case", Operator::ToggleCase),
+    ];
+
+    for &(name, operator) in visual_operators.iter() {
+        rust.register(name, move |state, target, input| {
+            state.operate_selection(operator, target, input);
+        });
+
+        for visual in visuals.iter_mut() {
+            visual.bind_action([operator.key()], name).unwrap();
+        }
+    }
+
+    for visual in visuals.iter_mut() {
+        visual.bind_action([Key::Char('x')], "vym.visual_delete").unwrap();
+        visual.bind_action([Key::Char('s')], "vym.visual_change").unwrap();
+        visual.bind_action([Key::Char('o')], "vym.other_end").unwrap();
+        visual.bind_action([Key::Ctrl('c')], "vym.normal").unwrap();
+        visual.bind_action([Key::Esc], "vym.normal").unwrap();
+    }
+
+    // #################
+    // COMMAND-LINE MODE
+    // #################
+    // `:` opens a prompt for ex commands. With a count it starts with the
+    // range of lines from the current one down, and from visual mode it
+    // starts with the lines that were selected.
+    rust.register("vym.command", |state, target, input| {
+        let text = match (state.mode, input.count) {
+            (Mode::Visual(_), _) => String::from("'<,'>"),
+            (_, Some(1))         => String::from("."),
+            (_, Some(count))     => format!(".,.+{}", count - 1),
+            (_, None)            => String::new(),
+        };
+
+        state.normal_mode(target);
+        state.prompt.open(text.as_str());
+        state.mode          = Mode::Command;
+        state.render_prompt = true;
+    });
+    normal.bind_action([Key::Char(':')], "vym.command").unwrap();
+
+    for visual in visuals.iter_mut() {
+        visual.bind_action([Key::Char(':')], "vym.command").unwrap();
+    }
+
+    rust.register("vym.command_char", |state, _target, input| {
+        if let Key::Char(c) = input.key {
+            state.prompt.insert(c);
+            state.render_prompt = true;
+        }
+    });
+    let command_char = command.mutator_action("vym.command_char");
+    command.get_root().set_wildcard(command_char);
+
+    rust.register("vym.command_run", |state, target, _input| {
+        let line = state.prompt.finish();
+        state.close_prompt(target);
+        state.run(line.as_str(), target);
+    });
+    command.bind_action([Key::Char('\n')], "vym.command_run").unwrap();
+
+    rust.register("vym.command_cancel", |state, target, _input| {
+        state.prompt.open("");
+        state.close_prompt(target);
+    });
+    command.bind_action([Key::Esc], "vym.command_cancel").unwrap();
+    command.bind_action([Key::Ctrl('c')], "vym.command_cancel").unwrap();
+
+    // Deleting when there's nothing left closes the prompt.
+    rust.register("vym.command_backspace", |state, target, _input| {
+        if state.prompt.backspace() {
+            state.render_prompt = true;
+        } else {
+            state.close_prompt(target);
+        }
+    });
+    command.bind_action([Key::Backspace], "vym.command_backspace").unwrap();
+
+    rust.register("vym.command_complete", |state, _target, _input| {
+        if !state.prompt.next_completion() {
+            let (start, candidates) = ex::completions(&state.commands, state.prompt.text());
+            state.prompt.complete(start, candidates);
+        }
+
+        state.render_prompt = true;
+    });
+    command.bind_action([Key::Char('\t')], "vym.command_complete").unwrap();
+
+    // Moving around the line and through the lines that were run before.
+    rust.register("vym.command_edit", |state, _target, input| {
+        match input.key {
+            Key::Left  | Key::Ctrl('b') => state.prompt.left(),
+            Key::Right | Key::Ctrl('f') => state.prompt.right(),
+            Key::Home  | Key::Ctrl('a') => state.prompt.home(),
+            Key::End   | Key::Ctrl('e') => state.prompt.end(),
+            Key::Up    | Key::Ctrl('p') => state.prompt.prev_history(),
+            Key::Down  | Key::Ctrl('n') => state.prompt.next_history(),
+            Key::Ctrl('w')              => state.prompt.delete_word(),
+            _                           => {},
+        }
+
+        state.render_prompt = true;
+    });
+
+    let edits = [
+        Key::Left, Key::Right, Key::Home, Key::End, Key::Up, Key::Down,
+        Key::Ctrl('b'), Key::Ctrl('f'), Key::Ctrl('a'), Key::Ctrl('e'),
+        Key::Ctrl('p'), Key::Ctrl('n'), Key::Ctrl('w'),
+    ];
+
+    for &key in edits.iter() {
+        command.bind_action([key], "vym.command_edit").unwrap();
+    }
 
     // ###########
     // INSERT MODE
     // ###########
-    rust.register("vym.insert", |state, target, key| {
-        state.insert_mode();
+    rust.register("vym.insert", |state, target, _input| {
+        state.insert_mode(target);
     });
 
-    rust.register("vym.insert_char", |state, target, key| {
-        if let Key::Char('\t') = key {
+    rust.register("vym.insert_char", |_state, target, input| {
+        if let Key::Char('\t') = input.key {
             target.insert(' ');
             target.insert(' ');
-        } else if let Key::Char(c) = key {
+        } else if let Key::Char('\n') = input.key {
+            target.insert_newline();
+        } else if let Key::Char(c) = input.key {
             target.insert(c);
         }
     });
-    normal.bind_action([Key::Char('i')], "vym.insert");
+    normal.bind_action([Key::Char('i')], "vym.insert").unwrap();
 
     // Transition back to normal mode with normal keybindings.
-    rust.register("vym.normal", |state, target, key| {
-        state.normal_mode();
+    rust.register("vym.normal", |state, target, _input| {
+        state.normal_mode(target);
     });
 
     // Insert mode has its own binding table that defaults to just
@@ -138,54 +434,499 @@ fn init_vym(vym : &mut Vym) {
 
     insert.get_root().set_wildcard(insert_char);
 
-    insert.bind_action([Key::Ctrl('c')], "vym.normal");
-    insert.bind_action([Key::Esc], "vym.normal");
+    insert.bind_action([Key::Ctrl('c')], "vym.normal").unwrap();
+    insert.bind_action([Key::Esc], "vym.normal").unwrap();
 
-    rust.register("vym.backspace", |state, target, key| {
+    rust.register("vym.backspace", |_state, target, _input| {
         target.backspace();
     });
-    insert.bind_action([Key::Backspace], "vym.backspace");
+    insert.bind_action([Key::Backspace], "vym.backspace").unwrap();
+
+    // Ctrl+r and the name of a register inserts what's in it.
+    rust.register("vym.insert_register", |state, target, input| {
+        if let Key::Char(c) = input.key {
+            if let Some(register) = state.registers.get(c) {
+                let text = register.text.as_str();
+
+                target.at_each_cursor(|view| {
+                    let offset = view.cursor();
+                    view.insert_at(offset, text);
+                });
+            }
+        }
+    });
+    insert.bind_wildcard([Key::Ctrl('r')], "vym.insert_register").unwrap();
+
+    // ###########
+    // EX COMMANDS
+    // ###########
+    vym.add_command("w[rite]", ex::write);
+    vym.add_command("q[uit]", ex::quit);
+    vym.add_command("wq", ex::write_quit);
+    vym.add_command("x[it]", ex::write_quit);
+    vym.add_command("e[dit]", ex::edit);
+    vym.add_command("dif[f]", ex::diff);
+    vym.add_command("rec[over]", ex::recover);
+    vym.add_command("disc[ard]", ex::discard);
+    vym.add_command("s[ubstitute]", ex::substitute);
+    vym.add_command("se[t]", ex::set);
+    vym.add_command("noh[lsearch]", ex::nohlsearch);
 }
 
+/// Put text after or before the cursor, leaving the cursor on the last
+/// character that was put.
+fn put_chars(target : &mut FileView, text : &str, after : bool) {
+    let mut offset = target.cursor();
+
+    if after {
+        offset = target.next_char(offset);
+    }
+
+    target.insert_at(offset, text);
+
+    let last = text.chars().last().map_or(0, |c| c.len_utf8());
+    target.set_cursor(offset + text.len() - last);
+}
+
+/// Put whole lines below or above the current one, leaving the cursor at
+/// the start of the first line that was put.
+fn put_lines(target : &mut FileView, text : &str, after : bool) {
+    let line = target.current_line();
+
+    if !after {
+        target.insert_at(line.start(), text);
+        target.set_cursor(line.start());
+        return;
+    }
+
+    if line.end_size() > 0 {
+        target.insert_at(line.end(), text);
+        target.set_cursor(line.end());
+        return;
+    }
+
+    // The last line doesn't end in a line ending, so the text's line ending
+    // goes before it instead.
+    let ending = target.line_ending().as_str();
+    let text   = text.strip_suffix("\r\n")
+        .or_else(|| text.strip_suffix('\n'))
+        .unwrap_or(text);
+
+    target.insert_at(line.end(), format!("{}{}", ending, text).as_str());
+    target.set_cursor(line.end() + ending.len());
+}
+
+/// Put a block of text into the lines from the current one down, starting
+/// at the cursor's column or the one after it, with each of its lines
+/// repeated `times` times. Short lines are padded with
+/// spaces and lines are added to the end of the file if the block needs
+/// them. The cursor is left at the block's top left corner.
+fn put_block(target : &mut FileView, text : &str, after : bool, times : usize) {
+    let line       = target.current_line();
+    let mut column = target.cursor() - line.start();
+
+    if after {
+        let cursor = target.cursor();
+        column = target.next_char(cursor) - line.start();
+    }
+
+    let ending = target.line_ending().as_str();
+
+    for (index, part) in text.split(ending).enumerate() {
+        let number = line.number() + index;
+
+        let row = match target.get_line(number) {
+            Some(row) => row,
+            None      => {
+                let length = target.len();
+                target.insert_at(length, ending);
+                target.get_line(number).unwrap()
+            },
+        };
+
+        // Short lines are padded out to the block's column first.
+        let width   = row.content_end() - row.start();
+        let padding = " ".repeat(column.saturating_sub(width));
+        let offset  = row.start() + cmp::min(column, width);
+
+        target.insert_at(offset, format!("{}{}", padding, part.repeat(times)).as_str());
+    }
+
+    target.set_cursor(line.start() + column);
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
 enum Mode {
     Normal,
-    Insert
+    Insert,
+    /// An operator was typed and is waiting for a motion.
+    Operator,
+    /// Text of some kind is being selected.
+    Visual(SelectionKind),
+    /// An ex command is being typed at the prompt.
+    Command,
 }
 
 struct VymState {
-    mode : Mode
+    mode : Mode,
+    registers : Registers,
+    /// The register named with " for the next yank, delete or put.
+    register : Option<char>,
+    /// The operator waiting for a motion, if there is one.
+    operator : Option<Operator>,
+    /// The count typed before the operator.
+    operator_count : Option<usize>,
+    /// The count typed so far for the next command.
+    count : Option<usize>,
+    /// Every motion and text object by name.
+    motions : HashMap<String, Motion>,
+    /// What was selected when visual mode was last left.
+    last_selection : Option<Selection>,
+    /// Whether insert mode is typing at a cursor on each line of a block
+    /// that was changed. The extra cursors go when it's left.
+    block_insert : bool,
+    /// Every ex command by name.
+    commands : Commands,
+    /// The prompt ex commands are typed at.
+    prompt : CommandLine,
+    /// Whether the prompt needs to be drawn again.
+    render_prompt : bool,
+    /// Actions for the editor from the commands that were run, like
+    /// quitting.
+    actions : Vec<Action>,
 }
 
 impl VymState {
     pub fn new() -> VymState {
         VymState {
-            mode : Mode::Normal
+            mode           : Mode::Normal,
+            registers      : Registers::new(),
+            register       : None,
+            operator       : None,
+            operator_count : None,
+            count          : None,
+            motions        : HashMap::new(),
+            last_selection : None,
+            block_insert   : false,
+            commands       : Commands::new(),
+            prompt         : CommandLine::new(),
+            render_prompt  : false,
+            actions        : Vec::new(),
+        }
+    }
+
+    /// Forget the operator, register and count that were typed.
+    pub fn cancel(&mut self) {
+        self.operator       = None;
+        self.operator_count = None;
+        self.register       = None;
+        self.count          = None;
+        self.mode           = Mode::Normal;
+    }
+
+    /// Make the motion called `name`. If an operator is waiting for one,
+    /// the operator is applied to the text the motion covers. Otherwise
+    /// each cursor moves.
+    pub fn motion(&mut self, name : &str, target : &mut FileView, input : Input) {
+        let motion = match self.motions.get(name) {
+            Some(&motion) => motion,
+            None          => return,
+        };
+
+        if self.operator.is_some() {
+            let motion = match self.operator {
+                Some(Operator::Change) if name == "vym.word" => motions::change_word,
+                _                                           => motion,
+            };
+
+            self.operate(motion, target, input);
+            return;
+        }
+
+        target.at_each_cursor(|view| {
+            if let Some(range) = motion(view, input) {
+                view.set_cursor(range.cursor);
+            }
+        });
+    }
+
+    /// Apply the operator that is waiting to what a motion covers at each
+    /// cursor. Changes leave vym in insert mode and are undone along with
+    /// what is typed. Counts typed before the operator and the motion are
+    /// multiplied, so `2d3w` deletes six words.
+    pub fn operate(&mut self, motion : Motion, target : &mut FileView, input : Input) {
+        let operator = match self.operator.take() {
+            Some(operator) => operator,
+            None           => return,
+        };
+
+        let input = Input {
+            count : match (self.operator_count.take(), input.count) {
+                (None, None)    => None,
+                (first, second) => Some(first.unwrap_or(1).saturating_mul(second.unwrap_or(1))),
+            },
+            ..input
+        };
+
+        let name  = self.register.take();
+        self.mode = Mode::Normal;
+
+        if operator == Operator::Change {
+            self.insert_mode(target);
+        } else {
+            target.begin_group();
+        }
+
+        let registers = &mut self.registers;
+        target.at_each_cursor(|view| {
+            if let Some(range) = motion(view, input) {
+                operators::apply(operator, range, view, registers, name);
+            }
+        });
+
+        if operator != Operator::Change {
+            target.end_group();
+        }
+    }
+
+    /// Apply an operator to what is selected and leave visual mode. A
+    /// count repeats indenting and outdenting.
+    pub fn operate_selection(&mut self, operator : Operator, target : &mut FileView, input : Input) {
+        let selection = match target.selection() {
+            Some(selection) => selection,
+            None            => return,
+        };
+
+        let ranges = target.selection_ranges();
+        let name   = self.register.take();
+
+        self.normal_mode(target);
+
+        if operator == Operator::Change {
+            self.insert_mode(target);
+            self.block_insert = selection.kind == SelectionKind::Block;
+        } else {
+            target.begin_group();
+        }
+
+        let times = match operator {
+            Operator::Indent | Operator::Outdent => input.times(),
+            _                                    => 1,
+        };
+
+        for _ in 0 .. times {
+            if selection.kind == SelectionKind::Block {
+                operators::apply_block(operator, &ranges, target, &mut self.registers, name);
+                continue;
+            }
+
+            let (start, end) = ranges[0];
+            let range        = Range {
+                start,
+                end,
+                kind   : selection.kind,
+                cursor : cmp::min(selection.anchor, selection.cursor),
+            };
+
+            operators::apply(operator, range, target, &mut self.registers, name);
+        }
+
+        if operator != Operator::Change {
+            target.end_group();
+        }
+    }
+
+    /// Close the prompt and go back to normal mode. The file is drawn again
+    /// to cover the prompt up.
+    pub fn close_prompt(&mut self, target : &mut FileView) {
+        self.mode          = Mode::Normal;
+        self.render_prompt = false;
+        target.redraw();
+    }
+
+    /// Run a line typed at the prompt, showing the user anything it has
+    /// to say.
+    pub fn run(&mut self, line : &str, target : &mut FileView) {
+        let marks = self.last_selection.map(|selection| {
+            let anchor = target.line_at(selection.anchor).number();
+            let cursor = target.line_at(selection.cursor).number();
+            (cmp::min(anchor, cursor), cmp::max(anchor, cursor))
+        });
+
+        match ex::run(&self.commands, line, target, marks) {
+            Ok(Response::Done)             => {},
+            Ok(Response::Message(message)) => target.show_message(message.as_str()),
+            Ok(Response::Scratch(text))    => self.actions.push(Action::Scratch(text)),
+            Ok(Response::Quit)             => self.actions.push(Action::Quit),
+            Err(message)                   => target.show_message(message.as_str()),
+        }
+    }
+
+    /// Put the contents of the register that was named, or of the unnamed
+    /// register, after or before the cursor `times` times over. How it is
+    /// put depends on the kind of text it holds.
+    pub fn put(&mut self, target : &mut FileView, after : bool, times : usize) {
+        let name     = self.register.take().unwrap_or('"');
+        let register = match self.registers.get(name) {
+            Some(register) => register.clone(),
+            None           => return,
+        };
+
+        target.begin_group();
+
+        let text = register.text.repeat(times);
+
+        match register.kind {
+            SelectionKind::Char  => put_chars(target, text.as_str(), after),
+            SelectionKind::Line  => put_lines(target, text.as_str(), after),
+            SelectionKind::Block => put_block(target, register.text.as_str(), after, times),
         }
+
+        target.end_group();
     }
 
-    /// Change to insert mode.
-    pub fn insert_mode(&mut self) {
+    /// Change to insert mode. Everything typed until we leave it is
+    /// undone as one edit.
+    pub fn insert_mode(&mut self, target : &mut FileView) {
+        if let Mode::Normal = self.mode {
+            target.begin_group();
+        }
+
         self.mode = Mode::Insert;
     }
 
-    /// Change to normal mode.
-    pub fn normal_mode(&mut self) {
+    /// Change to normal mode, remembering the selection if there was one
+    /// so that `gv` can select it again.
+    pub fn normal_mode(&mut self, target : &mut FileView) {
+        match self.mode {
+            Mode::Insert => {
+                target.end_group();
+
+                if self.block_insert {
+                    target.clear_cursors();
+                    self.block_insert = false;
+                }
+            },
+            Mode::Visual(_) => {
+                self.last_selection = target.selection();
+                target.clear_selection();
+            },
+            _ => {},
+        }
+
         self.mode = Mode::Normal;
     }
 }
 
+/// What vym's functions are given when they are called.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Input {
+    /// The last key typed for the function.
+    pub key   : Key,
+    /// The count typed before the function, if there was one.
+    pub count : Option<usize>,
+}
+
+impl Input {
+    /// Get the number of times to do something, which is once if no count
+    /// was typed.
+    pub fn times(&self) -> usize {
+        self.count.unwrap_or(1)
+    }
+}
+
 pub struct Vym<'a> {
-    rust : RustScope<'a, VymState, FileView>,
+    rust : RustScope<'a, VymState, FileView, Input>,
     normal : Keymaster,
     insert : Keymaster,
+    /// The bindings used after an operator is typed.
+    pending : Keymaster,
+    visual : Keymaster,
+    visual_line : Keymaster,
+    visual_block : Keymaster,
+    /// The bindings used while typing at the prompt.
+    command : Keymaster,
 }
 
 impl<'a> Vym<'a> {
+    // #################################
+    // P R I V A T E  F U N C T I O N S
+    // #################################
+
+    /// Get the bindings for the mode vym is in.
+    fn keymaster(&mut self) -> &mut Keymaster {
+        match self.rust.state().mode {
+            Mode::Normal                       => &mut self.normal,
+            Mode::Insert                       => &mut self.insert,
+            Mode::Operator                     => &mut self.pending,
+            Mode::Visual(SelectionKind::Char)  => &mut self.visual,
+            Mode::Visual(SelectionKind::Line)  => &mut self.visual_line,
+            Mode::Visual(SelectionKind::Block) => &mut self.visual_block,
+            Mode::Command                      => &mut self.command,
+        }
+    }
+
+    /// Add a motion to the registry along with a function that makes it.
+    fn register_motion(&mut self, name : &str, motion : Motion) {
+        let owned = String::from(name);
+
+        self.rust.state_mut().motions.insert(owned.clone(), motion);
+        self.rust.register(name, move |state, target, input| {
+            state.motion(owned.as_str(), target, input);
+        });
+    }
+
+    // ###############################
+    // P U B L I C  F U N C T I O N S
+    // ###############################
+
+    /// Add a motion called `name` that is typed with `keys` in normal mode,
+    /// after an operator and in the visual modes, where it moves the end of
+    /// the selection.
+    pub fn add_motion<T: AsRef<[Key]>>(&mut self, keys : T, name : &str, motion : Motion) {
+        self.register_motion(name, motion);
+        self.normal.bind_action(keys.as_ref(), name).unwrap();
+        self.pending.bind_action(keys.as_ref(), name).unwrap();
+        self.visual.bind_action(keys.as_ref(), name).unwrap();
+        self.visual_line.bind_action(keys.as_ref(), name).unwrap();
+        self.visual_block.bind_action(keys.as_ref(), name).unwrap();
+    }
+
+    /// Add a motion called `name` that is typed with `prefix` and then any
+    /// character, which the motion is given, like `f`.
+    pub fn add_char_motion<T: AsRef<[Key]>>(&mut self, prefix : T, name : &str, motion : Motion) {
+        self.register_motion(name, motion);
+        self.normal.bind_wildcard(prefix.as_ref(), name).unwrap();
+        self.pending.bind_wildcard(prefix.as_ref(), name).unwrap();
+        self.visual.bind_wildcard(prefix.as_ref(), name).unwrap();
+        self.visual_line.bind_wildcard(prefix.as_ref(), name).unwrap();
+        self.visual_block.bind_wildcard(prefix.as_ref(), name).unwrap();
+    }
+
+    /// Add an ex command that can be run from the prompt. See
+    /// `Commands::add` for how its name is written.
+    pub fn add_command<F>(&mut self, name : &str, command : F)
+        where F: Fn(&mut FileView, &Ex) -> Result<Response, String> + 'static {
+        self.rust.state_mut().commands.add(name, command);
+    }
+
+    /// Add text objects called `name` that are typed after an operator
+    /// with `prefix` and then a key that picks the object, like `iw`.
+    pub fn add_text_object<T: AsRef<[Key]>>(&mut self, prefix : T, name : &str, object : Motion) {
+        self.register_motion(name, object);
+        self.pending.bind_wildcard(prefix.as_ref(), name).unwrap();
+    }
+
     pub fn new() -> Vym<'a> {
         let mut vym = Vym {
-            rust  : RustScope::new(VymState::new()),
+            rust    : RustScope::new(VymState::new()),
             normal  : Keymaster::new(),
             insert  : Keymaster::new(),
+            pending : Keymaster::new(),
+            visual       : Keymaster::new(),
+            visual_line  : Keymaster::new(),
+            visual_block : Keymaster::new(),
+            command      : Keymaster::new(),
         };
 
         init_vym(&mut vym);
@@ -198,21 +939,38 @@ impl<'a> Mutator<FileView> for Vym<'a> {}
 
 impl<'a> Actionable for Vym<'a> {
     fn actions(&mut self) -> Vec<Action> {
-        match self.rust.state().mode {
-            Mode::Normal => self.normal.actions(),
-            Mode::Insert => self.insert.actions(),
-        }
+        let mut actions = self.keymaster().actions();
+        actions.append(&mut self.rust.state_mut().actions);
+        actions
     }
 }
 
 impl<'a> Renderable for Vym<'a> {
+    /// Draw the prompt over the bottom line of the file while it is open,
+    /// scrolling it sideways if the cursor would be off the screen.
     fn render(&mut self, renderer : &mut render::Renderer, size : (u16, u16)) -> io::Result<()> {
-        let (rows, cols) = size;
-        Ok(())
+        let (cols, rows) = size;
+        let state        = self.rust.state_mut();
+
+        if state.mode != Mode::Command {
+            return Ok(());
+        }
+
+        state.render_prompt = false;
+
+        // The cursor goes after the colon.
+        let cursor = state.prompt.cursor() + 1;
+        let skip   = (cursor + 1).saturating_sub(cols as usize);
+        let line   = format!(":{}", state.prompt.text());
+        let shown  = line.chars().skip(skip).take(cols as usize).collect::<String>();
+
+        renderer.move_cursor(rows, 1)?;
+        renderer.write(format!("{}{}", termion::clear::CurrentLine, shown).as_str())?;
+        renderer.move_cursor(rows, (cursor - skip + 1) as u16)
     }
 
     fn should_render(&self) -> bool {
-        false
+        self.rust.state().render_prompt
     }
 }
 
@@ -222,15 +980,19 @@ impl<'a> Scope<FileView> for Vym<'a> {
     }
 
     fn call(&mut self, name : &str, target : &mut FileView, key : Key) -> io::Result<()> {
-        self.rust.call(name, target, key)
+        // Every function uses up the count typed before it, except the ones
+        // that are typed before the command the count is for.
+        let count = match name {
+            "vym.count" | "vym.register" => self.rust.state().count,
+            _                            => self.rust.state_mut().count.take(),
+        };
+
+        self.rust.call_with(name, target, Input { key, count })
     }
 }
 
 impl<'a> KeyInput for Vym<'a> {
     fn consume(&mut self, key : Key) -> Option<()> {
-        match self.rust.state().mode {
-            Mode::Normal => self.normal.consume(key),
-            Mode::Insert => self.insert.consume(key),
-        }
+        self.keymaster().consume(key)
     }
 }
@@ -0,0 +1,339 @@
+//! byt - vym::motions
+//!
+//! Motions move the cursor in normal mode. After an operator they say what
+//! text the operator works on instead. Text objects are motions that can
+//! only be used after an operator, like `iw`, and don't care which way the
+//! cursor would have moved.
+//!
+//! Every motion finds a Range from the cursor using the FileView's own
+//! functions for finding words, paragraphs and pairs.
+
+// EXTERNS
+
+// LIBRARY INCLUDES
+use std::cmp;
+use termion::event::Key;
+
+// SUBMODULES
+
+// LOCAL INCLUDES
+use byt::views::file::{FileView, SelectionKind};
+use super::Input;
+
+/// Finds the text a motion covers from the cursor, given the last key typed
+/// for it and the count typed before it. Returns None if the motion can't
+/// be made from where the cursor is.
+pub type Motion = fn(&mut FileView, Input) -> Option<Range>;
+
+/// The text a motion covers, from `start` up to but not including `end`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Range {
+    pub start  : usize,
+    pub end    : usize,
+    /// Whether the range is made of characters or whole lines.
+    pub kind   : SelectionKind,
+    /// Where the cursor goes when the motion isn't used by an operator.
+    pub cursor : usize,
+}
+
+impl Range {
+    /// The characters from the cursor to `to`, not including the later of
+    /// the two. Like vim, a range that would end at the start of a later
+    /// line than it starts on ends at the end of the line before instead.
+    pub fn exclusive(view : &mut FileView, to : usize) -> Range {
+        let from    = view.cursor();
+        let start   = cmp::min(from, to);
+        let mut end = cmp::max(from, to);
+        let last    = view.line_at(end);
+
+        if end == last.start() && view.line_at(start).number() < last.number() {
+            end = cmp::max(start, view.get_line(last.number() - 1).unwrap().content_end());
+        }
+
+        Range { start, end, kind : SelectionKind::Char, cursor : to }
+    }
+
+    /// The characters from the cursor to `to`, including both.
+    pub fn inclusive(view : &mut FileView, to : usize) -> Range {
+        let from  = view.cursor();
+        let start = cmp::min(from, to);
+        let end   = view.next_char(cmp::max(from, to));
+
+        Range { start, end, kind : SelectionKind::Char, cursor : to }
+    }
+
+    /// Every line from the cursor's to the one `to` is on, along with their
+    /// line endings.
+    pub fn lines(view : &mut FileView, to : usize) -> Range {
+        let from  = view.cursor();
+        let first = view.line_at(cmp::min(from, to));
+        let last  = view.line_at(cmp::max(from, to));
+
+        Range {
+            start  : first.start(),
+            end    : last.end(),
+            kind   : SelectionKind::Line,
+            cursor : to,
+        }
+    }
+
+    /// The characters between two offsets, wherever the cursor is. The
+    /// cursor goes to the start.
+    pub fn object(range : (usize, usize)) -> Range {
+        let (start, end) = range;
+        Range { start, end, kind : SelectionKind::Char, cursor : start }
+    }
+}
+
+// #######
+// MOTIONS
+// #######
+
+/// Find where the cursor ends up after taking a step `count` times, where
+/// `step` finds where a step goes from an offset. The motion fails if any
+/// of the steps do.
+fn repeat<F>(view : &mut FileView, input : Input, mut step : F) -> Option<usize>
+    where F: FnMut(&mut FileView, usize) -> Option<usize> {
+    let mut offset = view.cursor();
+
+    for _ in 0 .. input.times() {
+        offset = step(view, offset)?;
+    }
+
+    Some(offset)
+}
+
+/// Get the offset `count` lines up or down from the cursor, going as far as
+/// there are lines. Returns None if the cursor can't move at all.
+fn vertical(view : &mut FileView, count : usize, down : bool) -> Option<usize> {
+    let cursor = view.cursor();
+    let number = view.current_line().number();
+    let last   = view.line_at(view.len()).number();
+
+    let delta = if down {
+        cmp::min(count, last - number) as i64
+    } else {
+        -(cmp::min(count, number - 1) as i64)
+    };
+
+    if delta == 0 {
+        return None;
+    }
+
+    view.line_offset(cursor, delta)
+}
+
+/// Get the offset on line `number` in the cursor's column, clamped to the
+/// lines there are.
+fn on_line(view : &mut FileView, number : usize) -> Option<usize> {
+    let cursor = view.cursor();
+    let last   = view.line_at(view.len()).number();
+    let number = cmp::max(1, cmp::min(number, last));
+    let delta  = (number as i64) - (view.current_line().number() as i64);
+
+    view.line_offset(cursor, delta)
+}
+
+pub fn left(view : &mut FileView, input : Input) -> Option<Range> {
+    let cursor = view.cursor();
+    let to     = repeat(view, input, |view, offset| Some(view.prev_char(offset)))?;
+
+    if to == cursor {
+        return None;
+    }
+
+    Some(Range::exclusive(view, to))
+}
+
+pub fn right(view : &mut FileView, input : Input) -> Option<Range> {
+    let cursor = view.cursor();
+    let to     = repeat(view, input, |view, offset| Some(view.next_char(offset)))?;
+
+    if to == cursor {
+        return None;
+    }
+
+    Some(Range::exclusive(view, to))
+}
+
+pub fn down(view : &mut FileView, input : Input) -> Option<Range> {
+    let to = vertical(view, input.times(), true)?;
+    Some(Range::lines(view, to))
+}
+
+pub fn up(view : &mut FileView, input : Input) -> Option<Range> {
+    let to = vertical(view, input.times(), false)?;
+    Some(Range::lines(view, to))
+}
+
+pub fn line_start(view : &mut FileView, _input : Input) -> Option<Range> {
+    let to = view.current_line().start();
+    Some(Range::exclusive(view, to))
+}
+
+/// The end of the line, or with a count, of the line that many lines down
+/// counting the current one.
+pub fn line_end(view : &mut FileView, input : Input) -> Option<Range> {
+    let to = match input.times() - 1 {
+        0     => view.cursor(),
+        lines => vertical(view, lines, true)?,
+    };
+
+    let to = view.line_at(to).content_end();
+    Some(Range::exclusive(view, to))
+}
+
+/// The first line, or with a count, the line with that number.
+pub fn file_start(view : &mut FileView, input : Input) -> Option<Range> {
+    let to = on_line(view, input.times())?;
+    Some(Range::lines(view, to))
+}
+
+/// The last line, or with a count, the line with that number.
+pub fn file_end(view : &mut FileView, input : Input) -> Option<Range> {
+    let to = on_line(view, input.count.unwrap_or(usize::MAX))?;
+    Some(Range::lines(view, to))
+}
+
+pub fn word_forward(view : &mut FileView, input : Input) -> Option<Range> {
+    let to = repeat(view, input, |view, offset| Some(view.next_word_start(offset)))?;
+    Some(Range::exclusive(view, to))
+}
+
+pub fn word_backward(view : &mut FileView, input : Input) -> Option<Range> {
+    let to = repeat(view, input, |view, offset| Some(view.prev_word_start(offset)))?;
+    Some(Range::exclusive(view, to))
+}
+
+pub fn word_end(view : &mut FileView, input : Input) -> Option<Range> {
+    let to = repeat(view, input, |view, offset| Some(view.word_end(offset)))?;
+    Some(Range::inclusive(view, to))
+}
+
+/// What `w` covers after `c`. Like vim, `cw` on a word only changes to the
+/// end of the word, leaving the whitespace after it alone.
+pub fn change_word(view : &mut FileView, input : Input) -> Option<Range> {
+    let cursor = view.cursor();
+    let next   = view.next_char(cursor);
+    let blank  = view.text(cursor, next)
+        .and_then(|text| text.chars().next())
+        .is_none_or(|c| c.is_whitespace());
+
+    if blank {
+        return word_forward(view, input);
+    }
+
+    // Every word but the last is changed along with the whitespace after
+    // it.
+    let mut end = cursor;
+    for count in 0 .. input.times() {
+        if count > 0 {
+            end = view.next_word_start(end);
+        }
+
+        end = view.word_range(end, false).1;
+    }
+
+    Some(Range { start : cursor, end, kind : SelectionKind::Char, cursor })
+}
+
+pub fn paragraph_forward(view : &mut FileView, input : Input) -> Option<Range> {
+    let to = repeat(view, input, |view, offset| Some(view.next_paragraph(offset)))?;
+    Some(Range::exclusive(view, to))
+}
+
+pub fn paragraph_backward(view : &mut FileView, input : Input) -> Option<Range> {
+    let to = repeat(view, input, |view, offset| Some(view.prev_paragraph(offset)))?;
+    Some(Range::exclusive(view, to))
+}
+
+/// Get the character a key types, if it types one.
+fn typed(key : Key) -> Option<char> {
+    match key {
+        Key::Char(c) => Some(c),
+        _            => None,
+    }
+}
+
+pub fn find_forward(view : &mut FileView, input : Input) -> Option<Range> {
+    let c  = typed(input.key)?;
+    let to = repeat(view, input, |view, offset| view.find_in_line(offset, c, true, false))?;
+
+    Some(Range::inclusive(view, to))
+}
+
+/// Like `f`, but stopping before the character. Each step after the first
+/// looks past the character the last one stopped in front of.
+pub fn till_forward(view : &mut FileView, input : Input) -> Option<Range> {
+    let c      = typed(input.key)?;
+    let cursor = view.cursor();
+    let to     = repeat(view, input, |view, offset| {
+        let from = if offset == cursor { offset } else { view.next_char(offset) };
+        view.find_in_line(from, c, true, true)
+    })?;
+
+    Some(Range::inclusive(view, to))
+}
+
+pub fn find_backward(view : &mut FileView, input : Input) -> Option<Range> {
+    let c  = typed(input.key)?;
+    let to = repeat(view, input, |view, offset| view.find_in_line(offset, c, false, false))?;
+
+    Some(Range::exclusive(view, to))
+}
+
+/// Like `F`, but stopping after the character.
+pub fn till_backward(view : &mut FileView, input : Input) -> Option<Range> {
+    let c      = typed(input.key)?;
+    let cursor = view.cursor();
+    let to     = repeat(view, input, |view, offset| {
+        let from = if offset == cursor { offset } else { view.prev_char(offset) };
+        view.find_in_line(from, c, false, true)
+    })?;
+
+    Some(Range::exclusive(view, to))
+}
+
+/// The line the cursor is on, which is what an operator typed twice works
+/// on, along with the lines after it if there is a count.
+pub fn current_line(view : &mut FileView, input : Input) -> Option<Range> {
+    let to = match input.times() - 1 {
+        0     => view.cursor(),
+        lines => vertical(view, lines, true)?,
+    };
+
+    Some(Range::lines(view, to))
+}
+
+// ############
+// TEXT OBJECTS
+// ############
+
+/// Find a text object at the cursor by the key typed after `i` or `a`.
+fn object(view : &mut FileView, key : Key, around : bool) -> Option<Range> {
+    let cursor = view.cursor();
+
+    let range = match typed(key)? {
+        'w'                          => Some(view.word_range(cursor, around)),
+        '(' | ')' | 'b'              => view.pair_range(cursor, '(', ')', around),
+        '{' | '}' | 'B'              => view.pair_range(cursor, '{', '}', around),
+        '[' | ']'                    => view.pair_range(cursor, '[', ']', around),
+        '<' | '>'                    => view.pair_range(cursor, '<', '>', around),
+        c @ '"' | c @ '\'' | c @ '`' => view.quote_range(cursor, c, around),
+        _                            => None,
+    };
+
+    range.map(Range::object)
+}
+
+/// The text object typed after `i`, which leaves out the brackets, quotes
+/// or whitespace around it.
+pub fn inner(view : &mut FileView, input : Input) -> Option<Range> {
+    object(view, input.key, false)
+}
+
+/// The text object typed after `a`, which includes the brackets, quotes or
+/// whitespace around it.
+pub fn around(view : &mut FileView, input : Input) -> Option<Range> {
+    object(view, input.key, true)
+}
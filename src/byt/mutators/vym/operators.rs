@@ -0,0 +1,253 @@
+//! byt - vym::operators
+//!
+//! Operators do something to the text a motion covers, like `d` deleting
+//! it. An operator is typed first and waits for a motion, so any operator
+//! works with any motion or text object.
+
+// EXTERNS
+
+// LIBRARY INCLUDES
+use std::cmp;
+use termion::event::Key;
+
+// SUBMODULES
+
+// LOCAL INCLUDES
+use byt::views::file::{FileView, SelectionKind};
+use super::motions::Range;
+use super::registers::{Register, Registers};
+
+/// The text inserted at the start of a line to indent it by one level,
+/// which is the same as a tab in insert mode.
+pub const INDENT : &str = "  ";
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Operator {
+    Delete,
+    Change,
+    Yank,
+    Indent,
+    Outdent,
+    ToggleCase,
+}
+
+impl Operator {
+    /// Get the key that types the operator. Typing it again applies the
+    /// operator to the current line.
+    pub fn key(&self) -> Key {
+        match *self {
+            Operator::Delete     => Key::Char('d'),
+            Operator::Change     => Key::Char('c'),
+            Operator::Yank       => Key::Char('y'),
+            Operator::Indent     => Key::Char('>'),
+            Operator::Outdent    => Key::Char('<'),
+            Operator::ToggleCase => Key::Char('~'),
+        }
+    }
+}
+
+/// Get the text in a range the way it goes in a register. Lines always end
+/// with a line ending so that they can be put as whole lines.
+fn register_text(view : &mut FileView, range : &Range) -> Register {
+    let mut text = view.text(range.start, range.end).unwrap_or_default();
+
+    if range.kind == SelectionKind::Line && !text.ends_with('\n') {
+        text.push_str(view.line_ending().as_str());
+    }
+
+    Register::new(text, range.kind)
+}
+
+/// Delete whole lines. Deleting the last line takes the line ending before
+/// it too, since it has none of its own. The cursor goes to the start of
+/// the line after the ones deleted.
+fn delete_lines(view : &mut FileView, range : &Range) {
+    let mut start = range.start;
+
+    if range.end == view.len() && start > 0 && view.line_at(range.end).end_size() == 0 {
+        start = view.line_at(start - 1).content_end();
+    }
+
+    view.delete(start, range.end - start);
+
+    let line = view.line_at(start);
+    view.set_cursor(line.start());
+}
+
+/// Indent every line in a range that has something on it.
+fn indent(view : &mut FileView, range : &Range) {
+    let first = view.line_at(range.start).number();
+    let last  = view.line_at(cmp::max(range.start, range.end.saturating_sub(1))).number();
+
+    for number in (first ..= last).rev() {
+        let line = view.get_line(number).unwrap();
+
+        if line.content_end() > line.start() {
+            view.insert_at(line.start(), INDENT);
+        }
+    }
+
+    let line = view.get_line(first).unwrap();
+    view.set_cursor(line.start());
+}
+
+/// Take one level of indentation off of every line in a range, which is
+/// as many spaces as INDENT has or a tab.
+fn outdent(view : &mut FileView, range : &Range) {
+    let first = view.line_at(range.start).number();
+    let last  = view.line_at(cmp::max(range.start, range.end.saturating_sub(1))).number();
+
+    for number in (first ..= last).rev() {
+        let line   = view.get_line(number).unwrap();
+        let text   = view.text(line.start(), line.content_end()).unwrap_or_default();
+        let spaces = text.chars().take(INDENT.len()).take_while(|&c| c == ' ').count();
+
+        let width = match spaces {
+            0 if text.starts_with('\t') => 1,
+            spaces                      => spaces,
+        };
+
+        if width > 0 {
+            view.delete(line.start(), width);
+        }
+    }
+
+    let line = view.get_line(first).unwrap();
+    view.set_cursor(line.start());
+}
+
+/// Swap the case of every letter between two offsets.
+fn toggle_case(view : &mut FileView, start : usize, end : usize) {
+    let text    = view.text(start, end).unwrap_or_default();
+    let toggled = text
+        .chars()
+        .map(|c| if c.is_lowercase() {
+            c.to_uppercase().collect::<String>()
+        } else {
+            c.to_lowercase().collect::<String>()
+        })
+        .collect::<String>();
+
+    if toggled != text {
+        view.delete(start, end - start);
+        view.insert_at(start, toggled.as_str());
+    }
+}
+
+/// Apply an operator to a range. Text that is deleted or yanked goes in the
+/// register called `name`, or where vim would put it if there isn't one.
+/// This is done at each cursor, so it mustn't do anything at every cursor
+/// itself.
+pub fn apply(operator : Operator, range : Range, view : &mut FileView, registers : &mut Registers, name : Option<char>) {
+    match operator {
+        Operator::Delete => {
+            registers.delete(name, register_text(view, &range));
+
+            match range.kind {
+                SelectionKind::Line => delete_lines(view, &range),
+                _                   => {
+                    view.delete(range.start, range.end - range.start);
+                    view.set_cursor(range.start);
+                },
+            }
+        },
+        Operator::Change => {
+            registers.delete(name, register_text(view, &range));
+
+            // Lines that are changed leave an empty line to type on.
+            let end = match range.kind {
+                SelectionKind::Line => {
+                    let last = view.line_at(cmp::max(range.start, range.end.saturating_sub(1)));
+                    last.content_end()
+                },
+                _ => range.end,
+            };
+
+            view.delete(range.start, end - range.start);
+            view.set_cursor(range.start);
+        },
+        Operator::Yank => {
+            registers.yank(name, register_text(view, &range));
+
+            let cursor = match range.kind {
+                SelectionKind::Line => cmp::min(view.cursor(), range.cursor),
+                _                   => range.start,
+            };
+
+            view.set_cursor(cursor);
+        },
+        Operator::Indent     => indent(view, &range),
+        Operator::Outdent    => outdent(view, &range),
+        Operator::ToggleCase => {
+            toggle_case(view, range.start, range.end);
+            view.set_cursor(range.start);
+        },
+    }
+}
+
+/// Apply an operator to a block, given the range it covers on each of its
+/// lines. Lines that are too short to reach the block have empty ranges and
+/// are left alone. The cursor goes to the block's top left corner, and
+/// after a change there is a cursor there on every line the block reached,
+/// so that what is typed goes on all of them.
+pub fn apply_block(operator : Operator, ranges : &[(usize, usize)], view : &mut FileView, registers : &mut Registers, name : Option<char>) {
+    let (first, last) = match (ranges.first(), ranges.last()) {
+        (Some(&first), Some(&last)) => (first, last),
+        _                           => return,
+    };
+
+    let mut parts = Vec::with_capacity(ranges.len());
+    for &(start, end) in ranges {
+        parts.push(view.text(start, end).unwrap_or_default());
+    }
+
+    let text     = parts.join(view.line_ending().as_str());
+    let register = Register::new(text, SelectionKind::Block);
+
+    match operator {
+        Operator::Delete | Operator::Change => {
+            registers.delete(name, register);
+
+            for &(start, end) in ranges.iter().rev() {
+                view.delete(start, end - start);
+            }
+
+            view.set_cursor(first.0);
+
+            if operator == Operator::Change {
+                // Each line's part of the block moved back by as much as
+                // was deleted from the lines above it.
+                let mut deleted = 0;
+
+                for &(start, end) in ranges {
+                    if end > start && start != first.0 {
+                        view.add_cursor(start - deleted);
+                    }
+
+                    deleted += end - start;
+                }
+            }
+        },
+        Operator::Yank => {
+            registers.yank(name, register);
+            view.set_cursor(first.0);
+        },
+        Operator::Indent | Operator::Outdent => {
+            let lines = Range {
+                start  : first.0,
+                end    : last.1,
+                kind   : SelectionKind::Line,
+                cursor : first.0,
+            };
+
+            apply(operator, lines, view, registers, name);
+        },
+        Operator::ToggleCase => {
+            for &(start, end) in ranges.iter().rev() {
+                toggle_case(view, start, end);
+            }
+
+            view.set_cursor(first.0);
+        },
+    }
+}
@@ -0,0 +1,186 @@
+//! byt - vym::registers
+//!
+//! Registers hold text that was yanked or deleted so that it can be put
+//! somewhere else. They work like vim's:
+//!
+//! * `"` is the unnamed register, which holds whatever was yanked or
+//!   deleted last.
+//! * `0` holds the last yank that didn't name a register.
+//! * `1` through `9` hold the last nine deletes of whole lines, or of text
+//!   that spans lines, with the newest in `1`.
+//! * `-` holds the last delete within a line.
+//! * `a` through `z` are only filled when they are asked for. Naming one in
+//!   uppercase adds to it instead of replacing it.
+
+// EXTERNS
+
+// LIBRARY INCLUDES
+use std::collections::HashMap;
+
+// SUBMODULES
+
+// LOCAL INCLUDES
+use byt::views::file::SelectionKind;
+
+/// The number of numbered registers that hold deletes.
+const NUM_DELETE_REGISTERS : usize = 9;
+
+/// Some text in a register, along with the shape of the text it came from,
+/// which decides how it is put back.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Register {
+    pub text : String,
+    pub kind : SelectionKind,
+}
+
+impl Register {
+    /// Make a register holding `text` of some kind.
+    pub fn new<N: AsRef<str>>(text : N, kind : SelectionKind) -> Register {
+        Register {
+            text : String::from(text.as_ref()),
+            kind,
+        }
+    }
+}
+
+/// All of vym's registers.
+pub struct Registers {
+    /// The register `"`.
+    unnamed : Option<Register>,
+    /// The register `0`.
+    yank    : Option<Register>,
+    /// The registers `1` through `9`, newest first. A register that was
+    /// named before the ones ahead of it were filled leaves gaps.
+    deletes : Vec<Option<Register>>,
+    /// The register `-`.
+    small   : Option<Register>,
+    /// The registers `a` through `z`.
+    named   : HashMap<char, Register>,
+}
+
+impl Registers {
+    // #################################
+    // P R I V A T E  F U N C T I O N S
+    // #################################
+
+    /// Put text into a named register, adding to it if the name is
+    /// uppercase. A register that gets something linewise added to it
+    /// becomes linewise.
+    fn store_named(&mut self, name : char, register : Register) {
+        let lower = name.to_ascii_lowercase();
+
+        let register = match self.named.remove(&lower) {
+            Some(mut existing) if name.is_ascii_uppercase() => {
+                existing.text.push_str(register.text.as_str());
+
+                if register.kind == SelectionKind::Line {
+                    existing.kind = SelectionKind::Line;
+                }
+
+                existing
+            },
+            _ => register,
+        };
+
+        self.named.insert(lower, register.clone());
+        self.unnamed = Some(register);
+    }
+
+    /// Put text straight into a numbered register, without shifting the
+    /// others along.
+    fn store_numbered(&mut self, name : char, register : Register) {
+        if name == '0' {
+            self.yank = Some(register.clone());
+        } else {
+            let index = name as usize - '1' as usize;
+
+            if index >= self.deletes.len() {
+                self.deletes.resize(index + 1, None);
+            }
+
+            self.deletes[index] = Some(register.clone());
+        }
+
+        self.unnamed = Some(register);
+    }
+
+    // ###############################
+    // P U B L I C  F U N C T I O N S
+    // ###############################
+
+    /// Record text that was deleted, in the register called `name` if
+    /// there is one.
+    pub fn delete(&mut self, name : Option<char>, register : Register) {
+        match name {
+            Some('"') | None => {},
+            Some('-')        => {
+                self.small   = Some(register.clone());
+                self.unnamed = Some(register);
+                return;
+            },
+            Some(name) if name.is_ascii_digit() => {
+                self.store_numbered(name, register);
+                return;
+            },
+            Some(name) => {
+                self.store_named(name, register);
+                return;
+            },
+        }
+
+        if register.kind == SelectionKind::Line || register.text.contains('\n') {
+            self.deletes.insert(0, Some(register.clone()));
+            self.deletes.truncate(NUM_DELETE_REGISTERS);
+        } else {
+            self.small = Some(register.clone());
+        }
+
+        self.unnamed = Some(register);
+    }
+
+    /// Get the register called `name`.
+    pub fn get(&self, name : char) -> Option<&Register> {
+        match name {
+            '"'         => self.unnamed.as_ref(),
+            '0'         => self.yank.as_ref(),
+            '1' ..= '9' => self.deletes.get(name as usize - '1' as usize)
+                               .and_then(Option::as_ref),
+            '-'         => self.small.as_ref(),
+            _           => self.named.get(&name.to_ascii_lowercase()),
+        }
+    }
+
+    /// Check whether `name` is the name of a register.
+    pub fn is_register(name : char) -> bool {
+        name == '"' || name == '-' || name.is_ascii_alphanumeric()
+    }
+
+    pub fn new() -> Registers {
+        Registers {
+            unnamed : None,
+            yank    : None,
+            deletes : Vec::new(),
+            small   : None,
+            named   : HashMap::new(),
+        }
+    }
+
+    /// Record text that was yanked, in the register called `name` if there
+    /// is one.
+    pub fn yank(&mut self, name : Option<char>, register : Register) {
+        match name {
+            Some('"') | None => {
+                self.yank    = Some(register.clone());
+                self.unnamed = Some(register);
+            },
+            Some('-') => {
+                self.small   = Some(register.clone());
+                self.unnamed = Some(register);
+            },
+            Some(name) if name.is_ascii_digit() => {
+                self.store_numbered(name, register)
+            },
+            Some(name) => self.store_named(name, register),
+        }
+    }
+}
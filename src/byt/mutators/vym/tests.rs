@@ -8,11 +8,14 @@ use byt::editor::mutator::*;
 use byt::views::file::FileView;
 
 use super::*;
+#[cfg(test)]
+use super::registers::Register;
 
 /// Make a FileView with vym already injected.
+#[cfg(test)]
 fn make_file() -> MutatePair<FileView> {
     let mut file = MutatePair::new(FileView::empty().unwrap());
-    file.register_mutator(Box::new(Vym::new()));
+    file.register_mutator(Box::new(Vym::new())).unwrap();
     file
 }
 
@@ -20,3 +23,740 @@ fn make_file() -> MutatePair<FileView> {
 fn it_enters_insert_mode() {
     let mut file = make_file();
 }
+
+/// Send keys to a FileView the way the editor does.
+#[cfg(test)]
+fn press(file : &mut MutatePair<FileView>, keys : &[Key]) {
+    for &key in keys {
+        if file.consume(key).is_none() {
+            continue;
+        }
+
+        for action in file.actions() {
+            if let Action::Mutator(name) = action {
+                file.call_action(name.as_str(), key).unwrap();
+            }
+        }
+    }
+}
+
+#[test]
+fn it_undoes_an_insert_session_at_once() {
+    let mut file = make_file();
+
+    press(&mut file, &[Key::Char('i'), Key::Char('f'), Key::Char('o'), Key::Char('o'), Key::Esc]);
+    press(&mut file, &[Key::Char('A'), Key::Char('!'), Key::Esc]);
+    assert_eq!(file.target().len(), 4);
+
+    press(&mut file, &[Key::Char('u')]);
+    assert_eq!(file.target().len(), 3);
+
+    press(&mut file, &[Key::Char('u')]);
+    assert_eq!(file.target().len(), 0);
+
+    press(&mut file, &[Key::Ctrl('r')]);
+    assert_eq!(file.target().len(), 3);
+}
+
+/// Type some text in insert mode and go back to normal mode.
+#[cfg(test)]
+fn type_text(file : &mut MutatePair<FileView>, text : &str) {
+    press(file, &[Key::Char('i')]);
+
+    for c in text.chars() {
+        press(file, &[Key::Char(c)]);
+    }
+
+    press(file, &[Key::Esc]);
+}
+
+/// Get all of the text in a FileView.
+#[cfg(test)]
+fn text_of(file : &mut MutatePair<FileView>) -> String {
+    let length = file.target().len();
+    file.target_mut().text(0, length).unwrap()
+}
+
+#[test]
+fn it_yanks_and_puts_lines() {
+    let mut file = make_file();
+
+    type_text(&mut file, "foo\nbar");
+    press(&mut file, &[Key::Char('g'), Key::Char('g'), Key::Char('y'), Key::Char('y'), Key::Char('p')]);
+    assert_eq!(text_of(&mut file), "foo\nfoo\nbar");
+    assert_eq!(file.target().cursor(), 4);
+
+    press(&mut file, &[Key::Char('G'), Key::Char('p')]);
+    assert_eq!(text_of(&mut file), "foo\nfoo\nbar\nfoo");
+
+    press(&mut file, &[Key::Char('u')]);
+    assert_eq!(text_of(&mut file), "foo\nfoo\nbar");
+
+    press(&mut file, &[Key::Char('P')]);
+    assert_eq!(text_of(&mut file), "foo\nfoo\nfoo\nbar");
+}
+
+#[test]
+fn it_shifts_deletes_through_the_numbered_registers() {
+    let mut file = make_file();
+
+    type_text(&mut file, "a\nb\nc");
+    press(&mut file, &[Key::Char('g'), Key::Char('g')]);
+    press(&mut file, &[Key::Char('d'), Key::Char('d'), Key::Char('d'), Key::Char('d')]);
+    assert_eq!(text_of(&mut file), "c");
+
+    press(&mut file, &[Key::Char('"'), Key::Char('2'), Key::Char('p')]);
+    assert_eq!(text_of(&mut file), "c\na");
+
+    press(&mut file, &[Key::Char('P')]);
+    assert_eq!(text_of(&mut file), "c\nb\na");
+}
+
+#[test]
+fn it_appends_to_named_registers() {
+    let mut file = make_file();
+
+    type_text(&mut file, "foo\nbar");
+    press(&mut file, &[Key::Char('g'), Key::Char('g'), Key::Char('"'), Key::Char('a'), Key::Char('y'), Key::Char('y')]);
+    press(&mut file, &[Key::Char('j'), Key::Char('"'), Key::Char('A'), Key::Char('y'), Key::Char('y')]);
+    press(&mut file, &[Key::Char('"'), Key::Char('a'), Key::Char('p')]);
+    assert_eq!(text_of(&mut file), "foo\nbar\nfoo\nbar");
+}
+
+#[test]
+fn it_inserts_registers_in_insert_mode() {
+    let mut file = make_file();
+
+    type_text(&mut file, "foo");
+    press(&mut file, &[Key::Char('y'), Key::Char('y')]);
+    press(&mut file, &[Key::Char('A'), Key::Ctrl('r'), Key::Char('"'), Key::Char('!'), Key::Esc]);
+    assert_eq!(text_of(&mut file), "foofoo\n!");
+}
+
+#[test]
+fn it_keeps_small_deletes_apart() {
+    let mut registers = Registers::new();
+
+    registers.delete(None, Register::new("x", SelectionKind::Char));
+    registers.delete(None, Register::new("line\n", SelectionKind::Line));
+
+    assert_eq!(registers.get('-'), Some(&Register::new("x", SelectionKind::Char)));
+    assert_eq!(registers.get('1'), Some(&Register::new("line\n", SelectionKind::Line)));
+    assert_eq!(registers.get('"'), registers.get('1'));
+    assert_eq!(registers.get('2'), None);
+
+    registers.yank(Some('b'), Register::new("one", SelectionKind::Char));
+    registers.yank(Some('B'), Register::new("two\n", SelectionKind::Line));
+
+    assert_eq!(registers.get('b'), Some(&Register::new("onetwo\n", SelectionKind::Line)));
+    assert_eq!(registers.get('0'), None);
+}
+
+#[test]
+fn it_deletes_into_numbered_registers_by_name() {
+    let mut registers = Registers::new();
+
+    registers.delete(Some('0'), Register::new("zero", SelectionKind::Char));
+    registers.delete(Some('3'), Register::new("three\n", SelectionKind::Line));
+
+    assert_eq!(registers.get('0'), Some(&Register::new("zero", SelectionKind::Char)));
+    assert_eq!(registers.get('3'), Some(&Register::new("three\n", SelectionKind::Line)));
+    assert_eq!(registers.get('"'), registers.get('3'));
+    assert_eq!(registers.get('1'), None);
+    assert_eq!(registers.get('-'), None);
+
+    registers.delete(None, Register::new("line\n", SelectionKind::Line));
+
+    assert_eq!(registers.get('1'), Some(&Register::new("line\n", SelectionKind::Line)));
+    assert_eq!(registers.get('4'), Some(&Register::new("three\n", SelectionKind::Line)));
+}
+
+/// Turn some text into the keys that type it.
+#[cfg(test)]
+fn keys(text : &str) -> Vec<Key> {
+    text.chars().map(Key::Char).collect()
+}
+
+/// Make a FileView holding `text` with the cursor at `cursor`.
+#[cfg(test)]
+fn file_with(text : &str, cursor : usize) -> MutatePair<FileView> {
+    let mut file = make_file();
+
+    file.target_mut().insert_str(text);
+    file.target_mut().set_cursor(cursor);
+    file
+}
+
+#[test]
+fn it_applies_operators_to_motions() {
+    let mut file = file_with("foo bar baz\nqux", 4);
+
+    press(&mut file, &keys("dw"));
+    assert_eq!(text_of(&mut file), "foo baz\nqux");
+    assert_eq!(file.target().cursor(), 4);
+
+    // A word motion doesn't take the line ending with it.
+    press(&mut file, &keys("dw"));
+    assert_eq!(text_of(&mut file), "foo \nqux");
+
+    press(&mut file, &keys("0de"));
+    assert_eq!(text_of(&mut file), " \nqux");
+
+    press(&mut file, &keys("dj"));
+    assert_eq!(text_of(&mut file), "");
+
+    press(&mut file, &keys("u"));
+    assert_eq!(text_of(&mut file), " \nqux");
+}
+
+#[test]
+fn it_applies_operators_to_characters_found_in_the_line() {
+    let mut file = file_with("call(a, b);", 0);
+
+    press(&mut file, &keys("dt("));
+    assert_eq!(text_of(&mut file), "(a, b);");
+
+    press(&mut file, &keys("df,"));
+    assert_eq!(text_of(&mut file), " b);");
+
+    press(&mut file, &keys("$hdF "));
+    assert_eq!(text_of(&mut file), ";");
+}
+
+#[test]
+fn it_applies_operators_to_text_objects() {
+    let mut file = file_with("f(a, (b)) \"x y\" end", 3);
+
+    press(&mut file, &keys("di("));
+    assert_eq!(text_of(&mut file), "f() \"x y\" end");
+
+    press(&mut file, &keys("fxda\""));
+    assert_eq!(text_of(&mut file), "f() end");
+
+    press(&mut file, &keys("yiwP"));
+    assert_eq!(text_of(&mut file), "f() endend");
+}
+
+#[test]
+fn it_changes_words_and_lines() {
+    let mut file = file_with("foo bar\nbaz", 0);
+
+    press(&mut file, &keys("cwx"));
+    press(&mut file, &[Key::Esc]);
+    assert_eq!(text_of(&mut file), "x bar\nbaz");
+
+    press(&mut file, &keys("jccnew"));
+    press(&mut file, &[Key::Esc]);
+    assert_eq!(text_of(&mut file), "x bar\nnew");
+
+    press(&mut file, &keys("u"));
+    assert_eq!(text_of(&mut file), "x bar\nbaz");
+}
+
+#[test]
+fn it_indents_lines() {
+    let mut file = file_with("a\n\nb", 0);
+
+    press(&mut file, &keys(">G"));
+    assert_eq!(text_of(&mut file), "  a\n\n  b");
+
+    press(&mut file, &keys(">>"));
+    assert_eq!(text_of(&mut file), "    a\n\n  b");
+}
+
+#[test]
+fn it_cancels_an_operator_without_a_motion() {
+    let mut file = file_with("foo bar", 0);
+
+    press(&mut file, &keys("dzw"));
+    assert_eq!(text_of(&mut file), "foo bar");
+    assert_eq!(file.target().cursor(), 4);
+
+    press(&mut file, &keys("dy"));
+    assert_eq!(text_of(&mut file), "foo bar");
+}
+
+#[test]
+fn it_moves_by_words_and_paragraphs() {
+    let mut file = file_with("one two\n\nthree", 0);
+
+    press(&mut file, &keys("w"));
+    assert_eq!(file.target().cursor(), 4);
+
+    press(&mut file, &keys("e"));
+    assert_eq!(file.target().cursor(), 6);
+
+    press(&mut file, &keys("}"));
+    assert_eq!(file.target().cursor(), 8);
+
+    press(&mut file, &keys("b"));
+    assert_eq!(file.target().cursor(), 4);
+
+    press(&mut file, &keys("G"));
+    assert_eq!(file.target().cursor(), 13);
+}
+
+#[test]
+fn it_repeats_motions_with_a_count() {
+    let mut file = file_with("a b c d\n1\n2\n3\n4", 0);
+
+    press(&mut file, &keys("3w"));
+    assert_eq!(file.target().cursor(), 6);
+
+    press(&mut file, &keys("0"));
+    assert_eq!(file.target().cursor(), 0);
+
+    press(&mut file, &keys("3j"));
+    assert_eq!(file.target().cursor(), 12);
+
+    press(&mut file, &keys("10j"));
+    assert_eq!(file.target().cursor(), 14);
+
+    press(&mut file, &keys("2G"));
+    assert_eq!(file.target().cursor(), 8);
+
+    press(&mut file, &keys("gg2fc"));
+    assert_eq!(file.target().cursor(), 0);
+
+    press(&mut file, &keys("2fb"));
+    assert_eq!(file.target().cursor(), 0);
+
+    press(&mut file, &keys("2f "));
+    assert_eq!(file.target().cursor(), 3);
+}
+
+#[test]
+fn it_repeats_operators_with_a_count() {
+    let mut file = file_with("a b c d e f g\n1\n2\n3\n4", 0);
+
+    press(&mut file, &keys("2d2w"));
+    assert_eq!(text_of(&mut file), "e f g\n1\n2\n3\n4");
+
+    press(&mut file, &keys("jd2d"));
+    assert_eq!(text_of(&mut file), "e f g\n3\n4");
+
+    press(&mut file, &keys("5dd"));
+    assert_eq!(text_of(&mut file), "e f g");
+
+    press(&mut file, &keys("010x"));
+    assert_eq!(text_of(&mut file), "");
+
+    press(&mut file, &keys("u"));
+    assert_eq!(text_of(&mut file), "e f g");
+}
+
+#[test]
+fn it_puts_with_a_count() {
+    let mut file = file_with("ab\ncd", 0);
+
+    press(&mut file, &keys("yl2p"));
+    assert_eq!(text_of(&mut file), "aaab\ncd");
+
+    press(&mut file, &keys("yy3P"));
+    assert_eq!(text_of(&mut file), "aaab\naaab\naaab\naaab\ncd");
+
+    press(&mut file, &keys("2u"));
+    assert_eq!(text_of(&mut file), "ab\ncd");
+}
+
+#[test]
+fn it_puts_text_as_a_single_edit() {
+    let mut file = file_with("ab\ncd\n", 0);
+    let edits    = |file : &MutatePair<FileView>| file.target().file().num_edits();
+
+    press(&mut file, &keys("yy"));
+    let before = edits(&file);
+
+    for put in &["5p", "3P", "yl4p"] {
+        let before = edits(&file);
+        press(&mut file, &keys(put));
+        assert_eq!(edits(&file), before + 1);
+    }
+
+    press(&mut file, &keys("A"));
+    press(&mut file, &[Key::Ctrl('r'), Key::Char('"'), Key::Esc]);
+    assert_eq!(edits(&file), before + 4);
+
+    // Blocks go in a line at a time.
+    press(&mut file, &[Key::Ctrl('v')]);
+    press(&mut file, &keys("jly"));
+    let before = edits(&file);
+    press(&mut file, &keys("G2p"));
+    assert_eq!(edits(&file), before + 3);
+}
+
+#[test]
+fn it_forgets_a_count_on_escape() {
+    let mut file = file_with("abc", 0);
+
+    press(&mut file, &[Key::Char('2'), Key::Esc, Key::Char('x')]);
+    assert_eq!(text_of(&mut file), "bc");
+}
+
+#[test]
+fn it_selects_characters_and_lines() {
+    let mut file = file_with("foo bar\nbaz", 0);
+
+    press(&mut file, &keys("vw"));
+    assert_eq!(file.target_mut().selected_text().unwrap(), "foo b");
+
+    // Typing another kind changes it, and typing the same one stops.
+    press(&mut file, &keys("V"));
+    assert_eq!(file.target_mut().selected_text().unwrap(), "foo bar\n");
+
+    press(&mut file, &keys("V"));
+    assert_eq!(file.target().selection(), None);
+
+    press(&mut file, &keys("0vlo"));
+    assert_eq!(file.target().cursor(), 0);
+
+    press(&mut file, &[Key::Esc]);
+    assert_eq!(file.target().selection(), None);
+}
+
+#[test]
+fn it_applies_operators_to_a_selection() {
+    let mut file = file_with("foo bar\nbaz", 0);
+
+    press(&mut file, &keys("vey"));
+    assert_eq!(file.target().selection(), None);
+    assert_eq!(file.target().cursor(), 0);
+
+    press(&mut file, &keys("wvd"));
+    assert_eq!(text_of(&mut file), "foo ar\nbaz");
+
+    press(&mut file, &keys("Vjd"));
+    assert_eq!(text_of(&mut file), "");
+
+    press(&mut file, &keys("ugg\"-P"));
+    assert_eq!(text_of(&mut file), "bfoo ar\nbaz");
+
+    press(&mut file, &keys("Vj>"));
+    assert_eq!(text_of(&mut file), "  bfoo ar\n  baz");
+
+    press(&mut file, &keys("Vj<"));
+    assert_eq!(text_of(&mut file), "bfoo ar\nbaz");
+
+    press(&mut file, &keys("ve~"));
+    assert_eq!(text_of(&mut file), "BFOO ar\nbaz");
+
+    press(&mut file, &keys("jVcqux"));
+    press(&mut file, &[Key::Esc]);
+    assert_eq!(text_of(&mut file), "BFOO ar\nqux");
+
+    press(&mut file, &keys("u"));
+    assert_eq!(text_of(&mut file), "BFOO ar\nbaz");
+}
+
+#[test]
+fn it_applies_operators_to_a_block() {
+    let mut file = file_with("abcd\nefgh\nijkl", 1);
+
+    press(&mut file, &[Key::Ctrl('v')]);
+    press(&mut file, &keys("jld"));
+    assert_eq!(text_of(&mut file), "ad\neh\nijkl");
+    assert_eq!(file.target().cursor(), 1);
+
+    press(&mut file, &keys("p"));
+    assert_eq!(text_of(&mut file), "adbc\nehfg\nijkl");
+
+    press(&mut file, &keys("u0"));
+    press(&mut file, &[Key::Ctrl('v')]);
+    press(&mut file, &keys("jcxy"));
+    press(&mut file, &[Key::Esc]);
+    assert_eq!(text_of(&mut file), "xyd\nxyh\nijkl");
+    assert_eq!(file.target().cursors().len(), 1);
+}
+
+#[test]
+fn it_reselects_the_last_selection() {
+    let mut file = file_with("foo bar", 0);
+
+    press(&mut file, &keys("vl"));
+    press(&mut file, &[Key::Esc]);
+    press(&mut file, &keys("$gv"));
+    assert_eq!(file.target_mut().selected_text().unwrap(), "fo");
+
+    press(&mut file, &keys("d"));
+    assert_eq!(text_of(&mut file), "o bar");
+}
+
+/// Type a line at the prompt and run it.
+#[cfg(test)]
+fn ex(file : &mut MutatePair<FileView>, line : &str) {
+    press(file, &[Key::Char(':')]);
+    press(file, &keys(line));
+    press(file, &[Key::Char('\n')]);
+}
+
+#[test]
+fn it_moves_to_a_line_from_the_prompt() {
+    let mut file = file_with("one\n  two\nthree", 0);
+
+    ex(&mut file, "2");
+    assert_eq!(file.target().cursor(), 6);
+
+    ex(&mut file, "$");
+    assert_eq!(file.target().cursor(), 10);
+
+    ex(&mut file, ".-1");
+    assert_eq!(file.target().cursor(), 6);
+
+    // Lines that aren't there leave the cursor alone.
+    ex(&mut file, "9");
+    assert_eq!(file.target().cursor(), 6);
+}
+
+#[test]
+fn it_substitutes_in_a_range_of_lines() {
+    let mut file = file_with("a a\na a\na a", 0);
+
+    ex(&mut file, "s/a/b/");
+    assert_eq!(text_of(&mut file), "b a\na a\na a");
+
+    ex(&mut file, "2,3s/a/c/g");
+    assert_eq!(text_of(&mut file), "b a\nc c\nc c");
+
+    ex(&mut file, "%s/^/- /");
+    assert_eq!(text_of(&mut file), "- b a\n- c c\n- c c");
+
+    press(&mut file, &keys("u"));
+    assert_eq!(text_of(&mut file), "b a\nc c\nc c");
+
+    press(&mut file, &keys("ggVj"));
+    ex(&mut file, "s/(\\w) (\\w)/$2$1/");
+    assert_eq!(text_of(&mut file), "ab\ncc\nc c");
+}
+
+#[test]
+fn it_edits_the_command_line() {
+    let mut file = file_with("foo", 0);
+
+    // The word and the character before the cursor can be deleted, and
+    // text can be typed in the middle of the line.
+    press(&mut file, &[Key::Char(':')]);
+    press(&mut file, &keys("s/o/x/ junk"));
+    press(&mut file, &[Key::Ctrl('w'), Key::Backspace, Key::Char('g'), Key::Left, Key::Left, Key::Char('y')]);
+    press(&mut file, &[Key::Char('\n')]);
+    assert_eq!(text_of(&mut file), "fxyxy");
+
+    // Earlier lines come back with the arrow keys.
+    press(&mut file, &keys("u:"));
+    press(&mut file, &[Key::Up, Key::Char('\n')]);
+    assert_eq!(text_of(&mut file), "fxyxy");
+
+    // Backspacing over an empty line closes the prompt.
+    press(&mut file, &keys("u:"));
+    press(&mut file, &[Key::Backspace]);
+    press(&mut file, &keys("x"));
+    assert_eq!(text_of(&mut file), "oo");
+
+    press(&mut file, &keys(":s"));
+    press(&mut file, &[Key::Esc]);
+    press(&mut file, &keys("x"));
+    assert_eq!(text_of(&mut file), "o");
+}
+
+#[test]
+fn it_completes_command_names() {
+    let mut file = file_with("foo", 0);
+
+    // `s` completes to `set` first and then to `substitute`.
+    press(&mut file, &keys(":s"));
+    press(&mut file, &[Key::Char('\t'), Key::Char('\t')]);
+    press(&mut file, &keys("/o/0/g\n"));
+    assert_eq!(text_of(&mut file), "f00");
+}
+
+#[test]
+fn it_quits_from_the_prompt() {
+    let mut file = file_with("foo", 0);
+
+    // The text was never saved, so it takes a `!` to quit.
+    ex(&mut file, "q");
+    assert!(!file.actions().contains(&Action::Quit));
+
+    let response = ex::quit(file.target_mut(), &Ex { range : None, bang : false, args : String::new() });
+    assert_eq!(response, Err(String::from("No write since last change (add ! to override)")));
+
+    ex(&mut file, "q!");
+    assert!(file.actions().contains(&Action::Quit));
+}
+
+#[test]
+fn it_quits_once_the_file_is_saved() {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    let path = env::temp_dir().join(format!("byt-{}-vym-quit", process::id()));
+    let path = String::from(path.to_str().unwrap());
+    fs::write(&path, "foo\n").unwrap();
+
+    let mut file = open_file(path.as_str());
+    ex(&mut file, "q");
+    assert!(file.actions().contains(&Action::Quit));
+
+    // Undoing back to what was saved counts as saved too.
+    let mut file = open_file(path.as_str());
+    press(&mut file, &keys("xu"));
+    ex(&mut file, "q");
+    assert!(file.actions().contains(&Action::Quit));
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn it_writes_and_edits_files_from_the_prompt() {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    let path = env::temp_dir().join(format!("byt-{}-vym-write", process::id()));
+    let path = String::from(path.to_str().unwrap());
+    let mut file = file_with("foo\n", 0);
+
+    ex(&mut file, format!("w {}", path).as_str());
+    assert_eq!(fs::read_to_string(&path).unwrap(), "foo\n");
+
+    press(&mut file, &keys("dd"));
+    ex(&mut file, "w");
+    assert_eq!(fs::read_to_string(&path).unwrap(), "");
+
+    fs::write(&path, "bar\n").unwrap();
+    ex(&mut file, "e");
+    assert_eq!(text_of(&mut file), "bar\n");
+
+    // Unsaved changes aren't thrown away without a `!`.
+    press(&mut file, &keys("x"));
+    ex(&mut file, format!("e {}", path).as_str());
+    assert_eq!(text_of(&mut file), "ar\n");
+
+    ex(&mut file, format!("e! {}", path).as_str());
+    assert_eq!(text_of(&mut file), "bar\n");
+
+    ex(&mut file, "wq");
+    assert!(file.actions().contains(&Action::Quit));
+
+    fs::remove_file(&path).unwrap();
+}
+
+/// Open a file on disk with vym already injected.
+#[cfg(test)]
+fn open_file(path : &str) -> MutatePair<FileView> {
+    let mut file = MutatePair::new(FileView::new(path).unwrap());
+    file.register_mutator(Box::new(Vym::new())).unwrap();
+    file
+}
+
+#[test]
+fn it_resolves_conflicts_from_the_prompt() {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    let path = env::temp_dir().join(format!("byt-{}-vym-conflict", process::id()));
+    let path = String::from(path.to_str().unwrap());
+    fs::write(&path, "foo\nbaz\n").unwrap();
+
+    let mut file = open_file(path.as_str());
+    press(&mut file, &keys("jcwbar"));
+    press(&mut file, &[Key::Esc]);
+
+    // Replace the file like a formatter or checkout would.
+    let other = format!("{}.new", path);
+    fs::write(&other, "foo\nqux\n").unwrap();
+    fs::rename(&other, &path).unwrap();
+
+    let response = ex::diff(file.target_mut(), &Ex { range : None, bang : false, args : String::new() });
+    assert_eq!(response, Ok(Response::Scratch(String::from("@@ -1,2 +1,2 @@\n foo\n-qux\n+bar\n"))));
+
+    ex(&mut file, "w");
+    assert_eq!(fs::read_to_string(&path).unwrap(), "foo\nqux\n");
+
+    ex(&mut file, "w!");
+    assert_eq!(fs::read_to_string(&path).unwrap(), "foo\nbar\n");
+    assert_eq!(file.target().conflict(), None);
+
+    // Reloading throws the changes away but keeps the cursor on its line.
+    press(&mut file, &keys("x"));
+    fs::write(&other, "foo\nbaz\n").unwrap();
+    fs::rename(&other, &path).unwrap();
+
+    ex(&mut file, "e!");
+    assert_eq!(text_of(&mut file), "foo\nbaz\n");
+    assert_eq!(file.target().conflict(), None);
+    assert_eq!(file.target().current_line().start(), 4);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn it_recovers_unsaved_edits_from_the_prompt() {
+    use std::env;
+    use std::fs;
+    use std::mem;
+    use std::process;
+
+    let path = env::temp_dir().join(format!("byt-{}-vym-recover", process::id()));
+    let path = String::from(path.to_str().unwrap());
+    fs::write(&path, "foo").unwrap();
+
+    // Pretend byt died twice with unsaved edits.
+    for _ in 0 .. 2 {
+        let mut file = open_file(path.as_str());
+        press(&mut file, &keys("x"));
+        mem::forget(file);
+    }
+
+    let mut file = open_file(path.as_str());
+    assert!(file.target().has_recovery());
+
+    ex(&mut file, "recover");
+    assert!(!file.target().has_recovery());
+    assert_eq!(text_of(&mut file), "oo");
+    mem::forget(file);
+
+    let mut file = open_file(path.as_str());
+    ex(&mut file, "discard");
+    assert!(!file.target().has_recovery());
+    assert_eq!(text_of(&mut file), "foo");
+
+    drop(file);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn it_runs_ex_commands_that_were_added() {
+    let mut vym = Vym::new();
+
+    vym.add_command("gr[eet]", |view, ex| {
+        view.insert_str(format!("hello {}", ex.args));
+        Ok(Response::Done)
+    });
+
+    let mut file = MutatePair::new(FileView::empty().unwrap());
+    file.register_mutator(Box::new(vym)).unwrap();
+
+    ex(&mut file, "gre world");
+    assert_eq!(text_of(&mut file), "hello world");
+}
+
+#[test]
+fn it_sets_options_from_the_prompt() {
+    use byt::io::encoding::Encoding;
+    use byt::io::search::Pattern;
+
+    let mut file = file_with("a\nb", 0);
+
+    ex(&mut file, "set ff=dos fenc=latin1");
+    assert_eq!(text_of(&mut file), "a\r\nb");
+    assert_eq!(file.target().encoding(), Encoding::Latin1);
+
+    ex(&mut file, "se ff=mac");
+    assert_eq!(text_of(&mut file), "a\r\nb");
+
+    file.target_mut().find_next(&Pattern::literal("b"));
+    ex(&mut file, "noh");
+    assert!(file.target().search().is_none());
+}
@@ -12,35 +12,44 @@
 
 // LIBRARY INCLUDES
 use std::cmp;
-use std::fs::File;
 use std::fs;
-use std::io::Seek;
-use std::io::SeekFrom;
-use std::io::{BufReader, ErrorKind, Error, Result};
-use std::io;
+use std::io::{ErrorKind, Error, Result};
+use std::path::Path;
+use std::time;
 use termion::event::Key;
 use termion;
 
 // SUBMODULES
+mod motions;
 mod tests;
 
 // LOCAL INCLUDES
 use byt::io::binds::Keymaster;
-use byt::io::file::PieceFile;
+use byt::io::diff::{Change, diff_lines};
+use byt::io::encoding::Encoding;
+use byt::io::file::{Conflict, FileError, PieceFile, TextChange};
+use byt::io::file::Result as FileResult;
+use byt::io::search::{Match, Pattern};
+use byt::io::tree::{Tree, Weighted};
 use byt::render;
 use byt::editor::{
     Action,
-    Actionable,
-    mutator
+    Actionable
 };
 use byt::io::binds::KeyInput;
 
+/// The number of bytes read at a time when finding the lines of a whole
+/// file.
+const SCAN_CHUNK_SIZE : usize = 64 * 1024;
+
 #[derive(Debug, Clone)]
 /// Stores information about a line of text in the file.
 pub struct Line {
-    /// The line number. Starts at 1.
+    /// The line number. Starts at 1. Only kept up to date on Lines that
+    /// were read out of a FileView.
     number : usize,
-    /// The line's offset in the file.
+    /// The line's offset in the file. Only kept up to date on Lines that
+    /// were read out of a FileView.
     offset : usize,
     /// The length of the line in bytes without its line ending characters.
     content_length : usize,
@@ -86,15 +95,162 @@ impl Line {
     }
 }
 
+impl Weighted for Line {
+    fn weight(&self) -> usize {
+        self.len()
+    }
+
+    fn locate(&mut self, index : usize, offset : usize) {
+        self.number = index + 1;
+        self.offset = offset;
+    }
+}
+
+/// Splits text into Lines a piece at a time, so that a file can be split
+/// without all of it being in memory.
+struct LineScanner {
+    /// The offset of the line being scanned.
+    line_offset      : usize,
+    num_chars        : usize,
+    /// The number of line ending characters seen since the last character
+    /// that wasn't one.
+    num_ending_chars : usize,
+}
+
+impl LineScanner {
+    /// Start scanning text that starts at `offset` in the file.
+    fn new(offset : usize) -> LineScanner {
+        LineScanner {
+            line_offset      : offset,
+            num_chars        : 0,
+            num_ending_chars : 0,
+        }
+    }
+
+    /// Scan the text that follows whatever was scanned before, adding the
+    /// lines that end in it to `lines`.
+    fn scan(&mut self, text : &[u8], lines : &mut Vec<Line>) {
+        for &byte in text {
+            match byte {
+                b'\r' => {
+                    self.num_ending_chars += 1;
+                },
+                b'\n' => {
+                    self.num_ending_chars += 1;
+
+                    lines.push(Line {
+                        number             : 0,
+                        offset             : self.line_offset,
+                        content_length     : self.num_chars,
+                        line_ending_length : self.num_ending_chars,
+                    });
+
+                    self.line_offset     += self.num_chars + self.num_ending_chars;
+                    self.num_chars        = 0;
+                    self.num_ending_chars = 0;
+                },
+                _ => {
+                    // A carriage return that isn't followed by a
+                    // newline is just part of the line.
+                    self.num_chars       += self.num_ending_chars + 1;
+                    self.num_ending_chars = 0;
+                }
+            }
+        }
+    }
+
+    /// Add whatever follows the last line ending to `lines` as the last
+    /// line of the file.
+    fn finish(self, lines : &mut Vec<Line>) {
+        lines.push(Line {
+            number             : 0,
+            offset             : self.line_offset,
+            content_length     : self.num_chars + self.num_ending_chars,
+            line_ending_length : 0,
+        });
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// The characters that end a line.
+pub enum LineEnding {
+    /// A bare newline, as on Unix.
+    Lf,
+    /// A carriage return followed by a newline, as on Windows.
+    CrLf,
+}
+
+impl LineEnding {
+    /// Get the text of the line ending.
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            LineEnding::Lf   => "\n",
+            LineEnding::CrLf => "\r\n",
+        }
+    }
+
+    /// Get the line ending a line ends with, if it has one.
+    fn of(line : &Line) -> Option<LineEnding> {
+        match line.line_ending_length {
+            0 => None,
+            1 => Some(LineEnding::Lf),
+            _ => Some(LineEnding::CrLf),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// The shapes a selection can take.
+pub enum SelectionKind {
+    /// Every character from the anchor to the cursor, including both.
+    Char,
+    /// Every line from the anchor's to the cursor's.
+    Line,
+    /// The rectangle with the anchor and the cursor at its corners.
+    Block,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// A selected region of the file. The anchor stays where the selection
+/// was started while the cursor moves.
+pub struct Selection {
+    pub kind   : SelectionKind,
+    pub anchor : usize,
+    pub cursor : usize,
+}
+
+/// A search and replace that asks about each match before replacing it.
+struct Replacement {
+    pattern     : Pattern,
+    replacement : String,
+    /// The match the user is being asked about.
+    current     : Match,
+    /// The end of the range being replaced in. It moves as text before it
+    /// is replaced.
+    end         : usize,
+    /// The number of matches replaced so far.
+    count       : usize,
+}
+
 /// Analogous to a buffer in vim. Offers abstractions
 /// over byt's PieceFile type.
 pub struct FileView {
     /// The path to the file this FileView references.
     path : Option<String>,
     file : Box<PieceFile>,
+    /// Whether this is a scratch buffer, which only shows some text and
+    /// can be closed without saving it.
+    scratch : bool,
 
     /// The location of the cursor in the file
     cursor_offset : usize,
+    /// The locations of any other cursors, in order. Edits and movement
+    /// happen at each of them as well as at the main cursor, which is the
+    /// one the viewport follows.
+    cursors : Vec<usize>,
+    /// The kind of selection being made and its anchor, if there is one.
+    /// The selection's other end is the main cursor.
+    selection : Option<(SelectionKind, usize)>,
 
     /// The line number of the top of the viewport.
     /// Line numbers are zero-indexed.
@@ -103,9 +259,16 @@ pub struct FileView {
     /// this FileView was rendered.
     viewport_rows: usize,
 
-    /// A Vec of the locations of line ending characters.
-    /// Regenerated after insertion or deletion.
-    lines : Vec<Line>,
+    /// The lines in the file, indexed by both line number and offset.
+    /// Only the lines touched by an insertion or deletion are updated.
+    lines : Tree<Line>,
+    /// The line ending inserted for new lines. Detected from the file
+    /// when it is loaded.
+    line_ending : LineEnding,
+    /// The number of lines that end with a bare newline.
+    lf_lines : usize,
+    /// The number of lines that end with a carriage return and a newline.
+    crlf_lines : usize,
 
     /// Whether or not the file's lines should be rendered after
     /// the next event.
@@ -114,6 +277,13 @@ pub struct FileView {
     /// to rerender the lines, just the cursor, so this saves us
     /// some work.
     render_cursor : bool,
+    /// An error or some other message to show the next time the FileView
+    /// is rendered.
+    error : Option<String>,
+    /// The pattern from the last search. Its matches are highlighted.
+    search : Option<Pattern>,
+    /// The interactive search and replace in progress, if there is one.
+    replacement : Option<Replacement>,
 
     /// Stores and interprets keybindings for this buffer
     /// in particular.
@@ -124,91 +294,545 @@ impl FileView {
     // #################################
     // P R I V A T E  F U N C T I O N S
     // #################################
+    /// Find the index of the line an offset falls on. The offset at the
+    /// very end of the file belongs to the last line.
+    fn line_index(&self, offset : usize) -> usize {
+        match self.lines.find(offset) {
+            Some(index) => index,
+            None        => self.lines.len().saturating_sub(1),
+        }
+    }
+
     /// Rebuild self.lines to have the proper line locations.
     /// May only need to be called upon file load.
     fn regenerate_lines(&mut self) {
-        // Read the whole piece file.
+        let length      = self.file.len();
+        let mut lines   = Vec::new();
+        let mut scanner = LineScanner::new(0);
+        let mut offset  = 0;
+
+        // Read the file a chunk at a time so that big files don't have to
+        // fit in memory. If we can't, the file changed on disk and we keep
+        // the lines we had until the conflict is resolved.
+        while offset < length {
+            let num_bytes = cmp::min(SCAN_CHUNK_SIZE, length - offset);
+            let text      = match self.file.read_bytes_at(offset, num_bytes) {
+                Ok(text) => text,
+                Err(_)   => return,
+            };
+
+            scanner.scan(&text, &mut lines);
+            offset += num_bytes;
+        }
+
+        scanner.finish(&mut lines);
+
+        self.lf_lines   = 0;
+        self.crlf_lines = 0;
+        self.count_line_endings(&lines, true);
+
+        self.lines = Tree::from_vec(lines);
+    }
+
+    /// Use whichever line ending most of the file's lines have for new
+    /// lines. Files without any CRLF line endings get LF.
+    fn detect_line_ending(&mut self) {
+        self.line_ending = if self.crlf_lines > self.lf_lines {
+            LineEnding::CrLf
+        } else {
+            LineEnding::Lf
+        };
+    }
+
+    /// Add or remove some lines from the tally of line endings.
+    fn count_line_endings(&mut self, lines : &[Line], add : bool) {
+        for line in lines {
+            let count = match LineEnding::of(line) {
+                Some(LineEnding::Lf)   => &mut self.lf_lines,
+                Some(LineEnding::CrLf) => &mut self.crlf_lines,
+                None                   => continue,
+            };
+
+            if add {
+                *count += 1;
+            } else {
+                *count -= 1;
+            }
+        }
+    }
+
+    /// Split text that starts at `offset` in the file into Lines. Whatever
+    /// follows the last line ending only becomes a Line if `last` is set,
+    /// which should be the case if the text runs to the end of the file.
+    fn scan_lines(text : &[u8], offset : usize, last : bool) -> Vec<Line> {
+        let mut lines   = Vec::new();
+        let mut scanner = LineScanner::new(offset);
+
+        scanner.scan(text, &mut lines);
+
+        if last {
+            scanner.finish(&mut lines);
+        }
+
+        lines
+    }
+
+    /// Bring self.lines up to date after `removed` bytes at `offset` were
+    /// replaced by `inserted` bytes. Only the lines the edit touched are
+    /// scanned again.
+    fn update_lines(&mut self, offset : usize, removed : usize, inserted : usize) {
+        // Lines only end at newlines, so an edit can't change anything
+        // before the start of the line it begins on. It can join the line
+        // it ends on with the ones before it, though.
+        let first   = self.line_index(offset);
+        let last    = self.line_index(offset + removed);
+        let start   = self.lines.get(first).unwrap().start();
+        let end     = self.lines.get(last).unwrap().end() + inserted - removed;
+        let is_last = last + 1 == self.lines.len();
+
+        let text = match self.file.read_bytes_at(start, end - start) {
+            Ok(text) => text,
+            Err(_)   => return,
+        };
+
+        let mut removed_lines = Vec::with_capacity(last - first + 1);
+        for index in (first .. last + 1).rev() {
+            removed_lines.push(self.lines.remove(index));
+        }
+
+        let lines = FileView::scan_lines(&text, start, is_last);
+        self.count_line_endings(&removed_lines, false);
+        self.count_line_endings(&lines, true);
+
+        for (index, line) in lines.into_iter().enumerate() {
+            self.lines.insert(first + index, line);
+        }
+    }
+
+    /// Bring the lines and the other cursors up to date after `removed`
+    /// bytes at `offset` were replaced by `inserted` bytes. The main cursor
+    /// is left to whatever made the edit.
+    fn text_changed(&mut self, offset : usize, removed : usize, inserted : usize) {
+        self.update_lines(offset, removed, inserted);
+
+        let shift = |location : &mut usize| {
+            if *location >= offset + removed {
+                *location = *location + inserted - removed;
+            } else if *location > offset {
+                *location = offset;
+            }
+        };
+
+        for cursor in self.cursors.iter_mut() {
+            shift(cursor);
+        }
+
+        if let Some((_, ref mut anchor)) = self.selection {
+            shift(anchor);
+        }
+    }
+
+    /// Get the length of the character at `offset`, or of the line ending
+    /// if that's where the offset is. Bytes that aren't part of a valid
+    /// character count as one character each.
+    fn char_len_at(&mut self, offset : usize) -> usize {
+        let line = self.lines.get(self.line_index(offset)).unwrap();
+
+        if offset >= line.content_end() {
+            return line.end() - offset;
+        }
+
+        let first = match self.file.read_bytes_at(offset, 1) {
+            Ok(bytes) => bytes[0],
+            Err(_)    => return 1,
+        };
+
+        let length = match first {
+            0x00 ..= 0x7f => 1,
+            0xc0 ..= 0xdf => 2,
+            0xe0 ..= 0xef => 3,
+            0xf0 ..= 0xf7 => 4,
+            _             => 1,
+        };
+
+        cmp::min(length, line.content_end() - offset)
+    }
+
+    /// Make `main` the main cursor, tidying up the other cursors so that
+    /// they are in order and don't share a place with each other or the
+    /// main cursor.
+    fn set_cursors(&mut self, main : usize) {
         let length = self.file.len();
-        let text   = self.file.read_at(0, length).unwrap();
 
-        self.lines.clear();
+        for cursor in self.cursors.iter_mut() {
+            *cursor = cmp::min(*cursor, length);
+        }
 
-        let mut line_number : usize      = 1;
-        let mut offset : usize           = 0;
-        let mut line_offset : usize      = 0;
-        let mut num_chars : usize        = 0;
-        let mut num_ending_chars : usize = 0;
+        if let Some((_, ref mut anchor)) = self.selection {
+            *anchor = cmp::min(*anchor, length);
+        }
 
-        for (index, byte) in text.char_indices() {
-            match byte {
-                // TODO make sure these are sequential
-                '\r' => {
-                    num_ending_chars += 1;
-                },
-                '\n' => {
-                    num_ending_chars += 1;
-
-                    self.lines.push(Line {
-                        number             : line_number,
-                        offset             : line_offset,
-                        content_length     : num_chars,
-                        line_ending_length : num_ending_chars,
-                    });
+        self.cursors.sort();
+        self.cursors.dedup();
+        self.cursors.retain(|&cursor| cursor != main);
 
-                    line_offset      = offset + num_ending_chars;
-                    line_number     += 1;
-                    num_chars        = 0;
-                    num_ending_chars = 0;
-                },
-                _ => {
-                    num_chars += 1;
-                }
+        // The other cursors are drawn along with the lines.
+        if !self.cursors.is_empty() {
+            self.render_lines = true;
+        }
+
+        self.set_cursor(main);
+    }
+
+    /// Turn the text of a line that starts at `offset` into something that
+    /// can be rendered, with the parts of it that are in `matches`
+    /// highlighted. The matches should be in order of where they start.
+    fn highlight_matches(text : &[u8], offset : usize, matches : &[Match]) -> String {
+        let mut result = String::new();
+        let mut start  = 0;
+
+        for found in matches {
+            // Skip whatever overlaps with what we already highlighted.
+            let match_start = cmp::max(found.start, offset + start) - offset;
+            let match_end   = cmp::min(found.end, offset + text.len());
+
+            if found.is_empty() || match_start >= text.len() || match_end <= offset + match_start {
+                continue;
+            }
+
+            // Don't split a character, which a cursor's highlight would.
+            let mut match_end = match_end - offset;
+            while match_end < text.len() && text[match_end] & 0xc0 == 0x80 {
+                match_end += 1;
             }
 
-            offset += 1;
+            result.push_str(String::from_utf8_lossy(&text[start .. match_start]).as_ref());
+            result.push_str(format!("{}{}{}",
+                                    termion::style::Invert,
+                                    String::from_utf8_lossy(&text[match_start .. match_end]),
+                                    termion::style::Reset).as_str());
+            start = match_end;
         }
 
-        self.lines.push(Line {
-            number             : line_number,
-            offset             : line_offset,
-            content_length     : num_chars,
-            line_ending_length : 0,
-        });
+        result.push_str(String::from_utf8_lossy(&text[start ..]).as_ref());
+        result
+    }
+
+    /// Move an interactive replacement on to the first match that starts
+    /// at or after `from`, finishing it if there isn't one left.
+    fn next_replacement(&mut self, from : usize) -> Option<Match> {
+        let (pattern, end) = match self.replacement {
+            Some(ref replacement) => (replacement.pattern.clone(), replacement.end),
+            None                  => return None,
+        };
+
+        let result = self.file.find_next(&pattern, from);
+
+        match self.report(result) {
+            Some(Some(found)) if found.end <= end => {
+                self.replacement.as_mut().unwrap().current = found;
+                self.set_cursor(found.start);
+                self.render_lines = true;
+                Some(found)
+            },
+            _ => {
+                self.finish_replace();
+                None
+            },
+        }
+    }
+
+    /// Show an error from the PieceFile to the user rather than passing
+    /// it on to whatever made the change, which is usually a keybinding.
+    fn report<T>(&mut self, result : FileResult<T>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(err)  => {
+                self.error        = Some(err.to_string());
+                self.render_lines = true;
+                None
+            },
+        }
+    }
+
+    /// Add a cursor `delta` lines away from the one at `offset`, in the
+    /// main cursor's column.
+    fn add_cursor_on_line(&mut self, offset : usize, delta : i64) {
+        let main   = self.current_line();
+        let column = self.cursor_offset - main.start();
+        let index  = self.line_index(offset) as i64 + delta;
+
+        if index < 0 || index >= self.lines.len() as i64 {
+            return;
+        }
+
+        let line = self.lines.get(index as usize).unwrap();
+        self.add_cursor(line.start() + cmp::min(column, line.content_length));
+    }
+
+    /// Bring the view up to date after the PieceFile moved somewhere else
+    /// in its history by making `changes` to the text. Only the lines the
+    /// changes touched are scanned again. If it failed partway the lines
+    /// are found again from scratch instead.
+    fn history_moved(&mut self, result : FileResult<Vec<TextChange>>) {
+        match self.report(result) {
+            Some(changes) => self.changes_made(&changes),
+            None          => self.history_changed(),
+        }
+    }
+
+    /// Bring the lines and cursors up to date after the PieceFile made
+    /// `changes`, in the order it made them.
+    fn changes_made(&mut self, changes : &[TextChange]) {
+        // The file is only there to be read as it is after the last
+        // change, so the lines are updated for all of them at once.
+        let mut changes = changes.iter();
+
+        if let Some(first) = changes.next() {
+            let change = changes.fold(*first, |change, next| change.then(next));
+            self.text_changed(change.offset, change.removed, change.inserted);
+        }
+
+        self.cursors_changed();
+    }
+
+    /// Bring the view up to date after the PieceFile changed in ways we
+    /// don't know the details of.
+    fn history_changed(&mut self) {
+        self.regenerate_lines();
+        self.cursors_changed();
+    }
+
+    /// Make sure the cursors are inside of the file after it changed. The
+    /// main cursor is kept where it was if it still falls inside of it.
+    fn cursors_changed(&mut self) {
+        let cursor = cmp::min(self.cursor_offset, self.file.len());
+        self.set_cursors(cursor);
+        self.render_lines = true;
     }
 
     // ###############################
     // P U B L I C  F U N C T I O N S
     // ###############################
 
+    /// Add a cursor at `offset`. Edits and movement happen at every
+    /// cursor.
+    pub fn add_cursor(&mut self, offset : usize) {
+        let main = self.cursor_offset;
+
+        self.cursors.push(offset);
+        self.set_cursors(main);
+    }
+
+    /// Add a cursor on the line above the topmost cursor, in the same
+    /// column as the main cursor or at the end of the line if it is
+    /// shorter.
+    pub fn add_cursor_above(&mut self) {
+        let top = self.cursors().into_iter().min().unwrap();
+        self.add_cursor_on_line(top, -1);
+    }
+
+    /// Add a cursor on the line below the bottommost cursor, in the same
+    /// column as the main cursor or at the end of the line if it is
+    /// shorter.
+    pub fn add_cursor_below(&mut self) {
+        let bottom = self.cursors().into_iter().max().unwrap();
+        self.add_cursor_on_line(bottom, 1);
+    }
+
+    /// Do something at every cursor, one at a time, as though each of them
+    /// were the main cursor. Cursors that end up in the same place become
+    /// one. The action shouldn't call functions that do things at every
+    /// cursor themselves, like `insert` and the `move_cursor` functions.
+    pub fn at_each_cursor<F>(&mut self, mut action : F) where F: FnMut(&mut FileView) {
+        if self.cursors.is_empty() {
+            action(self);
+            return;
+        }
+
+        // Go from the last cursor to the first so that edits can only
+        // move the cursors we've finished with, which text_changed keeps
+        // up to date as long as they're in self.cursors.
+        let main        = self.cursor_offset;
+        let mut pending = self.cursors.clone();
+        pending.push(main);
+        pending.sort();
+        pending.dedup();
+
+        self.cursors.clear();
+
+        let mut main_index = 0;
+
+        for &cursor in pending.iter().rev() {
+            self.cursor_offset = cursor;
+            action(self);
+
+            if cursor == main {
+                main_index = self.cursors.len();
+            }
+
+            self.cursors.push(self.cursor_offset);
+        }
+
+        let main = self.cursors.remove(main_index);
+        self.set_cursors(main);
+    }
+
     /// Delete the character before the cursor. Works whether or not
     /// you are currently in an insertion.
     pub fn backspace(&mut self) {
-        if self.cursor_offset == 0 {
+        self.at_each_cursor(FileView::_backspace);
+    }
+
+    /// Delete the character before the cursor. Works whether or not
+    /// you are currently in an insertion.
+    fn _backspace(&mut self) {
+        let current = self.cursor_offset;
+
+        if current == 0 {
             return;
         }
 
-        self.file.delete(self.cursor_offset - 1, 1);
-        self.regenerate_lines();
-        self.move_cursor_left();
+        // At the start of a line, the whole line ending before it goes.
+        let offset = if current > self.current_line().start() {
+            self.prev_char(current)
+        } else {
+            current - self.line_at(current - 1).end_size()
+        };
+        let length = current - offset;
+        let result = self.file.delete(offset, length);
+
+        if self.report(result).is_none() {
+            return;
+        }
+
+        self.text_changed(offset, length, 0);
+        self.set_cursor(offset);
         self.render_lines = true;
     }
 
-    /// Get the current line and its index.
-    pub fn current_line(&self) -> &Line {
-        let offset = self.cursor_offset;
+    /// Start a group of edits that are undone and redone as one.
+    pub fn begin_group(&mut self) {
+        self.file.begin_group();
+    }
+
+    /// Stop selecting text.
+    pub fn clear_selection(&mut self) {
+        if self.selection.take().is_some() {
+            self.render_lines = true;
+        }
+    }
+
+    /// Remove every cursor but the main one.
+    pub fn clear_cursors(&mut self) {
+        if !self.cursors.is_empty() {
+            self.cursors.clear();
+            self.render_lines = true;
+        }
+    }
+
+    /// Get the offset of the main cursor.
+    pub fn cursor(&self) -> usize {
+        self.cursor_offset
+    }
+
+    /// Get the offsets of every cursor, including the main one, in order.
+    pub fn cursors(&self) -> Vec<usize> {
+        let mut cursors = self.cursors.clone();
+        cursors.push(self.cursor_offset);
+        cursors.sort();
+        cursors
+    }
+
+    /// Stop highlighting the matches of the last search.
+    pub fn clear_search(&mut self) {
+        self.search       = None;
+        self.render_lines = true;
+    }
+
+    /// Start replacing the matches of `pattern` between the offsets in
+    /// `range`, or in the whole file, one at a time. Each match is shown to
+    /// the user with `replace_current`, `skip_current`, `replace_remaining`
+    /// and `finish_replace` deciding what happens to it. Each replacement
+    /// is undone on its own, and `replace_remaining` is undone as one, so
+    /// that nothing is left grouped while waiting on the user. Returns the
+    /// first match.
+    pub fn begin_replace(&mut self, pattern : &Pattern, replacement : &str, range : Option<(usize, usize)>) -> Option<Match> {
+        let (start, end) = range.unwrap_or((0, self.file.len()));
+
+        if self.replacement.is_some() {
+            self.finish_replace();
+        }
+
+        self.search      = Some(pattern.clone());
+        self.replacement = Some(Replacement {
+            pattern     : pattern.clone(),
+            replacement : String::from(replacement),
+            current     : Match { start, end : start },
+            end,
+            count       : 0,
+        });
+
+        self.next_replacement(start)
+    }
+
+    /// Check whether the file changed on disk since it was opened or last
+    /// saved. Resolve a conflict with `reload` or `keep_buffer`, and use
+    /// `diff_disk` to see what changed.
+    pub fn conflict(&self) -> Option<Conflict> {
+        self.file.conflict()
+    }
+
+    /// Get the line the cursor is on.
+    pub fn current_line(&self) -> Line {
+        let index = self.line_index(self.cursor_offset);
+        self.lines.get(index).unwrap()
+    }
+
+    /// Change every line ending in the file to `ending`, which is also
+    /// used for new lines from then on.
+    pub fn convert_line_endings(&mut self, ending : LineEnding) {
+        let line   = self.current_line();
+        let column = self.cursor_offset - line.start();
+        let lines  = self.lines.len();
+
+        self.line_ending = ending;
+
+        // The conversion is undone all at once. Go backwards so the
+        // offsets of the lines we haven't changed yet stay the same.
+        self.file.begin_group();
+
+        for index in (0 .. lines).rev() {
+            let line = self.lines.get(index).unwrap();
 
-        let mut index = 0;
-        // TODO make this binary search
-        for line in self.lines.iter() {
-            if line.contains(offset) ||
-               offset == line.content_end() {
-                return &line;
+            match LineEnding::of(&line) {
+                Some(current) if current != ending => {},
+                _                                  => continue,
             }
 
-            index += 1;
+            let offset  = line.content_end();
+            let removed = line.end_size();
+            let text    = ending.as_str();
+
+            let result = self.file.delete(offset, removed)
+                .and_then(|_| self.file.insert(text, offset));
+
+            // Start over from the file if it only got partway.
+            if self.report(result).is_none() {
+                self.regenerate_lines();
+                break;
+            }
+
+            self.text_changed(offset, removed, text.len());
         }
 
-        return &self.lines[0];
+        self.file.end_group();
+
+        // Keep the cursor on the same line and column.
+        let line   = self.get_line(line.number()).unwrap();
+        let column = cmp::min(column, line.content_length);
+        self.set_cursor(line.start() + column);
+        self.render_lines = true;
     }
 
     /// Delete some text from the file.
@@ -218,58 +842,194 @@ impl FileView {
             return;
         }
 
-        // TODO handle case where offset + num_bytes is greater than
-        // the file length
+        let num_bytes = cmp::min(num_bytes, self.file.len() - offset);
+
+        let result = self.file.delete(offset, num_bytes);
+
+        if self.report(result).is_none() {
+            return;
+        }
+
+        self.text_changed(offset, num_bytes, 0);
+
         let cursor = self.cursor_offset;
         if cursor > offset {
-            self.set_cursor(cursor - cmp::min(num_bytes, cursor - (offset + num_bytes)));
+            self.set_cursor(cursor - cmp::min(num_bytes, cursor - offset));
         }
 
-        self.file.delete(offset, num_bytes);
-        self.regenerate_lines();
         self.render_lines = true;
     }
 
+    /// Delete the selected text and stop selecting. The cursor is left
+    /// where the selection started. Returns the text that was deleted, as
+    /// `selected_text` would have.
+    pub fn delete_selection(&mut self) -> Option<String> {
+        self.replace_selection("")
+    }
+
     /// Delete the current line.
     pub fn delete_current_line(&mut self) {
-        let mut offset : usize;
-        let mut length : usize;
+        self.at_each_cursor(FileView::_delete_current_line);
+    }
 
-        let line = self.current_line().clone();
+    /// Delete the current line.
+    fn _delete_current_line(&mut self) {
+        let line = self.current_line();
 
         if line.number() == self.lines.len() &&
            line.len() == 0 {
-            self.backspace();
+            self._backspace();
             return;
         }
 
         self.delete(line.start(), line.len());
     }
 
+    /// Show the file as it was some amount of time before the current edit
+    /// was made.
+    pub fn earlier(&mut self, duration : time::Duration) {
+        let result = self.file.earlier(duration);
+        self.history_moved(result);
+    }
+
+    /// Show the file as it was a number of edits ago.
+    pub fn earlier_edits(&mut self, count : usize) {
+        let result = self.file.earlier_edits(count);
+        self.history_moved(result);
+    }
+
+    /// Compare the file on disk to the text in this FileView. Lines only
+    /// on disk are deletions and lines only in the view are insertions.
+    pub fn diff_disk(&mut self) -> Result<Vec<Change>> {
+        let path = match self.path {
+            Some(ref path) => path.clone(),
+            None           => return Err(Error::new(ErrorKind::InvalidInput, "FileView has no path")),
+        };
+
+        let disk   = fs::read(path)?;
+        let length = self.file.len();
+        let text   = self.file.read_bytes_at(0, length)?;
+
+        Ok(diff_lines(String::from_utf8_lossy(&disk).as_ref(),
+                      String::from_utf8_lossy(&text).as_ref()))
+    }
+
+    /// Delete a recovery file left behind by an earlier session without
+    /// using it.
+    pub fn discard_recovery(&mut self) -> Result<()> {
+        self.file.discard_recovery()?;
+        self.render_lines = true;
+        Ok(())
+    }
+
+    /// Stop an interactive replacement, leaving the matches that are left
+    /// as they are. Returns the number of matches that were replaced.
+    pub fn finish_replace(&mut self) -> usize {
+        match self.replacement.take() {
+            Some(replacement) => {
+                self.render_lines = true;
+                replacement.count
+            },
+            None => 0,
+        }
+    }
+
+    /// Move the cursor to the next match of `pattern` after it, wrapping
+    /// around to the start of the file, and return the match. Its matches
+    /// are highlighted until the next search or `clear_search`.
+    pub fn find_next(&mut self, pattern : &Pattern) -> Option<Match> {
+        self.search       = Some(pattern.clone());
+        self.render_lines = true;
+
+        let from   = self.cursor_offset + 1;
+        let result = self.file.find_next(pattern, from).and_then(|found| {
+            if found.is_some() { Ok(found) } else { self.file.find_next(pattern, 0) }
+        });
+
+        let found = self.report(result)??;
+        self.set_cursor(found.start);
+        Some(found)
+    }
+
+    /// Move the cursor to the last match of `pattern` before it, wrapping
+    /// around to the end of the file, and return the match. Its matches
+    /// are highlighted until the next search or `clear_search`.
+    pub fn find_prev(&mut self, pattern : &Pattern) -> Option<Match> {
+        self.search       = Some(pattern.clone());
+        self.render_lines = true;
+
+        let before = self.cursor_offset;
+        let end    = self.file.len() + 1;
+        let result = self.file.find_prev(pattern, before).and_then(|found| {
+            if found.is_some() { Ok(found) } else { self.file.find_prev(pattern, end) }
+        });
+
+        let found = self.report(result)??;
+        self.set_cursor(found.start);
+        Some(found)
+    }
+
     /// Make a new FileView with an empty, in-memory PieceFile.
     pub fn empty() -> Result<FileView> {
         let mut view = FileView {
             path : Option::None,
-            file : PieceFile::empty().unwrap(),
+            file : PieceFile::empty()?,
+            scratch : false,
             cursor_offset : 0,
+            cursors : Vec::new(),
+            selection : None,
             viewport_top : 1,
             viewport_rows : 26,
-            lines : Vec::new(),
+            lines : Tree::new(),
+            line_ending : LineEnding::Lf,
+            lf_lines : 0,
+            crlf_lines : 0,
             render_lines : true,
             render_cursor : true,
+            error : None,
+            search : None,
+            replacement : None,
             keys  : Keymaster::new(),
         };
 
         view.regenerate_lines();
+        view.detect_line_ending();
 
         Ok(view)
     }
 
+    /// Get the encoding the file is saved in.
+    pub fn encoding(&self) -> Encoding {
+        self.file.encoding()
+    }
+
+    /// Finish the group of edits started by `begin_group`.
+    pub fn end_group(&mut self) {
+        self.file.end_group();
+    }
+
+    /// Check whether the file has lines that end with both LF and CRLF.
+    pub fn has_mixed_line_endings(&self) -> bool {
+        self.lf_lines > 0 && self.crlf_lines > 0
+    }
+
+    /// Check whether an earlier session left unsaved edits to this file
+    /// behind.
+    pub fn has_recovery(&self) -> bool {
+        self.file.has_recovery()
+    }
+
     /// Get a reference to the view's PieceFile.
     pub fn file(&self) -> &PieceFile {
         &self.file
     }
 
+    /// Check whether the file has changed since it was opened or last
+    /// saved.
+    pub fn is_modified(&self) -> bool {
+        !self.scratch && self.file.is_modified()
+    }
+
     /// Get a mutable reference to the view's PieceFile. Only call this
     /// if you're sure you know what you're doing.
     pub fn file_mut(&mut self) -> &mut PieceFile {
@@ -278,48 +1038,121 @@ impl FileView {
 
     /// Get a line from the file. This only includes byte offsets,
     /// so don't expect to get any text from this yet.
-    pub fn get_line(&self, number : usize) -> Option<&Line> {
-        if number < 1 || number > self.lines.len() {
+    pub fn get_line(&self, number : usize) -> Option<Line> {
+        if number < 1 {
             return None;
         }
 
-        Some(&self.lines[number - 1])
+        self.lines.get(number - 1)
+    }
+
+    /// Show the file as it was right after a particular edit. Edit 0 is
+    /// the file as it was opened.
+    pub fn goto_edit(&mut self, edit : usize) {
+        let result = self.file.goto_edit(edit);
+        self.history_moved(result);
     }
 
     /// Move the cursor to the beginning of the line.
     pub fn goto_line_end(&mut self) {
-        let offset = self.current_line().content_end();
-        self.set_cursor(offset);
+        self.at_each_cursor(|view| {
+            let offset = view.current_line().content_end();
+            view.set_cursor(offset);
+        });
     }
 
     /// Move the cursor to the beginning of the line.
     pub fn goto_line_start(&mut self) {
-        let offset = self.current_line().start();
-        self.set_cursor(offset);
+        self.at_each_cursor(|view| {
+            let offset = view.current_line().start();
+            view.set_cursor(offset);
+        });
     }
 
     /// Insert a character into the PieceFile at the offset of the cursor. Does NOT actually insert
     /// the character into the underlying PieceFile until you call done_inserting().
     pub fn insert(&mut self, c : char) {
+        self.at_each_cursor(|view| view._insert(c));
+    }
+
+    /// Insert a character at the offset of the cursor.
+    fn _insert(&mut self, c : char) {
         let offset = self.cursor_offset;
-        self.file.insert(c.to_string().as_str(), offset);
+        let result = self.file.insert(c.to_string().as_str(), offset);
+
+        if self.report(result).is_none() {
+            return;
+        }
+
+        self.text_changed(offset, 0, c.len_utf8());
+        self.set_cursor(offset + c.len_utf8());
+        self.render_lines = true;
+    }
+
+    /// Insert a string at an offset. The cursor moves along with the text
+    /// after it.
+    pub fn insert_at(&mut self, offset : usize, text : &str) {
+        let result = self.file.insert(text, offset);
+
+        if self.report(result).is_none() {
+            return;
+        }
+
+        self.text_changed(offset, 0, text.len());
+
+        let cursor = self.cursor_offset;
+        if cursor >= offset {
+            self.set_cursor(cursor + text.len());
+        }
 
-        self.regenerate_lines();
-        self.set_cursor(offset + 1);
         self.render_lines = true;
     }
 
+    /// Start a new line at the cursor using the file's line ending.
+    pub fn insert_newline(&mut self) {
+        let ending = self.line_ending.as_str();
+        self.insert_str(ending);
+    }
+
     /// Insert a string at the offset of the cursor.
     pub fn insert_str<N: AsRef<str>>(&mut self, text: N) {
-        let text = text.as_ref();
-
-        let mut offset = self.cursor_offset;
-        for c in text.chars() {
+        for c in text.as_ref().chars() {
             self.insert(c);
-            offset += 1;
         }
     }
 
+    /// Resolve a conflict by keeping the text in this FileView. The next
+    /// save overwrites the file on disk.
+    pub fn keep_buffer(&mut self) -> Result<()> {
+        self.file.keep_buffer()?;
+        self.render_lines = true;
+        Ok(())
+    }
+
+    /// Show the file as it was some amount of time after the current edit
+    /// was made.
+    pub fn later(&mut self, duration : time::Duration) {
+        let result = self.file.later(duration);
+        self.history_moved(result);
+    }
+
+    /// Show the file as it was a number of edits later.
+    pub fn later_edits(&mut self, count : usize) {
+        let result = self.file.later_edits(count);
+        self.history_moved(result);
+    }
+
+    /// Get the line an offset falls on. The offset at the very end of the
+    /// file belongs to the last line.
+    pub fn line_at(&self, offset : usize) -> Line {
+        self.lines.get(self.line_index(offset)).unwrap()
+    }
+
+    /// Get the line ending used for new lines.
+    pub fn line_ending(&self) -> LineEnding {
+        self.line_ending
+    }
+
     /// Get the length of the file .
     pub fn len(&self) -> usize {
         self.file.len()
@@ -329,26 +1162,25 @@ impl FileView {
     /// Negative numbers move the cursor more towards the top of
     /// the screen.
     pub fn move_cursor_vertically(&mut self, delta : i64) {
+        self.at_each_cursor(|view| view._move_cursor_vertically(delta));
+    }
+
+    /// Move the cursor a number of lines according to a delta.
+    fn _move_cursor_vertically(&mut self, delta : i64) {
         let current_start = self.current_line().start();
         // Have to subtract by one becauase line numbers are 1-indexed.
         let index         = self.current_line().number() - 1;
 
         // The distance of the cursor from the line's beginning.
         let current_column = self.cursor_offset - current_start;
-        let num_lines      = (self.lines.len() as i64);
-
-        // We want to allow the user to go to one past the newline
-        // characters at the end of the final line if there are any so that
-        // they can delete or edit past the newline at the end of the file.
-        let last           = self.lines.len() - 1;
-        let last_end_chars = self.lines[last].line_ending_length;
-        let last_end       = self.lines[last].end();
+        let num_lines      = self.lines.len() as i64;
 
         // Calculate the bounded result of the move.
         let dest_index  = cmp::max(0, cmp::min(num_lines - 1, (index as i64) + delta)) as usize;
-        let dest_column = cmp::min(self.lines[dest_index].content_length, current_column);
+        let dest_line   = self.lines.get(dest_index).unwrap();
+        let dest_column = cmp::min(dest_line.content_length, current_column);
 
-        let line_start = self.lines[dest_index].start();
+        let line_start = dest_line.start();
         self.set_cursor(dest_column + line_start);
     }
 
@@ -359,6 +1191,11 @@ impl FileView {
 
     /// Move the cursor left one.
     pub fn move_cursor_left(&mut self) {
+        self.at_each_cursor(FileView::_move_cursor_left);
+    }
+
+    /// Move the cursor left one.
+    fn _move_cursor_left(&mut self) {
         let current = self.cursor_offset;
         let offset  = self.current_line().start();
 
@@ -366,19 +1203,21 @@ impl FileView {
             return;
         }
 
-        self.set_cursor(current - 1);
+        let dest = self.prev_char(current);
+        self.set_cursor(dest);
     }
 
     /// Move the cursor right one.
     pub fn move_cursor_right(&mut self) {
-        let current = self.cursor_offset;
-        let limit   = self.current_line().content_end();
-
-        if current == limit {
-            return;
-        }
+        self.at_each_cursor(|view| {
+            let current = view.cursor_offset;
+            let limit   = view.current_line().content_end();
 
-        self.set_cursor(current + 1);
+            if current < limit {
+                let dest = view.next_char(current);
+                view.set_cursor(dest);
+            }
+        });
     }
 
     /// Move the cursor up one.
@@ -388,14 +1227,18 @@ impl FileView {
 
     /// Move the cursor to the end of the file.
     pub fn move_cursor_to_start(&mut self) {
-        let change =  (-1 * (self.current_line().number() as i64)) + 1;
-        self.move_cursor_vertically(change as i64);
+        self.at_each_cursor(|view| {
+            let change = 1 - (view.current_line().number() as i64);
+            view._move_cursor_vertically(change);
+        });
     }
 
     /// Move the cursor to the end of the file.
     pub fn move_cursor_to_end(&mut self) {
-        let change = self.lines.len() - self.current_line().number();
-        self.move_cursor_vertically(change as i64);
+        self.at_each_cursor(|view| {
+            let change = view.lines.len() - view.current_line().number();
+            view._move_cursor_vertically(change as i64);
+        });
     }
 
     /// Move the viewport up and down in the file.
@@ -406,31 +1249,411 @@ impl FileView {
         self.set_viewport_top(dest_index);
     }
 
-    /// Make a new FileView with a predefined path. Does not attempt to open the file
-    /// corresponding to the path.  You must call open() on the returned instance to do so.
+    /// Make a new FileView for the file at `path`. If there is no file
+    /// there yet the FileView starts out empty, and saving creates it.
     pub fn new(path : &str) -> Result<FileView> {
+        let file = match PieceFile::open(path) {
+            Ok(file) => file,
+            Err(FileError::Io(ref err)) if err.kind() == ErrorKind::NotFound => PieceFile::empty()?,
+            Err(err) => return Err(err.into()),
+        };
+
         let mut view = FileView {
             path : Option::Some(String::from(path)),
-            file : PieceFile::open(path).unwrap(),
+            file,
+            scratch : false,
             cursor_offset : 0,
+            cursors : Vec::new(),
+            selection : None,
             viewport_top : 1,
             viewport_rows : 26,
-            lines : Vec::new(),
+            lines : Tree::new(),
+            line_ending : LineEnding::Lf,
+            lf_lines : 0,
+            crlf_lines : 0,
             render_lines : true,
             render_cursor : true,
+            error : None,
+            search : None,
+            replacement : None,
             keys  : Keymaster::new(),
         };
 
         view.regenerate_lines();
+        view.detect_line_ending();
 
         Ok(view)
     }
 
+    /// Get the path of the file, if it has one.
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+
+    /// Replace every match of `pattern` between the offsets in `range`, or
+    /// in the whole file, with `replacement`. Regular expressions can use
+    /// their capture groups in the replacement as `$1` or `${name}`. The
+    /// replacements are undone as one. Returns the number of matches that
+    /// were replaced.
+    pub fn replace_all(&mut self, pattern : &Pattern, replacement : &str, range : Option<(usize, usize)>) -> usize {
+        let (start, end) = range.unwrap_or((0, self.file.len()));
+        let result       = self.file.replace_all(pattern, replacement, start, end);
+        let count        = self.report(result).unwrap_or(0);
+
+        // There could be any number of replacements, so the lines are
+        // scanned again all at once rather than after each of them.
+        self.history_changed();
+
+        count
+    }
+
+    /// Replace the first match of `pattern` on each of the lines from
+    /// `first` to `last`, or every match on them if `global` is set, with
+    /// `replacement`, as in `replace_all`. The replacements are undone as
+    /// one. Returns the number of matches replaced on each line.
+    pub fn replace_lines(&mut self, pattern : &Pattern, replacement : &str, first : usize, last : usize, global : bool) -> Vec<usize> {
+        let ranges : Vec<(usize, usize)> = (first ..= last)
+            .filter_map(|number| self.get_line(number))
+            .map(|line| (line.start(), line.content_end()))
+            .collect();
+
+        let result = self.file.replace_in_ranges(pattern, replacement, &ranges, global);
+
+        match self.report(result) {
+            Some((counts, changes)) => {
+                self.changes_made(&changes);
+                counts
+            },
+            None => {
+                // It could have failed partway.
+                self.history_changed();
+                Vec::new()
+            },
+        }
+    }
+
+    /// Replace the match an interactive replacement is showing and move on
+    /// to the next one, which is returned.
+    pub fn replace_current(&mut self) -> Option<Match> {
+        let (pattern, text, current) = match self.replacement {
+            Some(ref replacement) => (replacement.pattern.clone(), replacement.replacement.clone(), replacement.current),
+            None                  => return None,
+        };
+
+        let result = self.file.replace_next(&pattern, text.as_str(), current.start);
+
+        let (found, inserted) = match self.report(result) {
+            Some(Some(replaced)) => replaced,
+            _                    => {
+                self.finish_replace();
+                return None;
+            },
+        };
+
+        self.text_changed(found.start, found.len(), inserted);
+
+        {
+            let replacement = self.replacement.as_mut().unwrap();
+            replacement.end    = replacement.end + inserted - found.len();
+            replacement.count += 1;
+        }
+
+        // Don't look for matches in the text we just put in.
+        let next = if found.is_empty() && inserted == 0 { found.start + 1 } else { found.start + inserted };
+        self.next_replacement(next)
+    }
+
+    /// Replace the match an interactive replacement is showing along with
+    /// every one after it, finishing the replacement. Returns the number of
+    /// matches that were replaced altogether.
+    pub fn replace_remaining(&mut self) -> usize {
+        let (pattern, text, current, end) = match self.replacement {
+            Some(ref replacement) => (replacement.pattern.clone(),
+                                      replacement.replacement.clone(),
+                                      replacement.current,
+                                      replacement.end),
+            None                  => return 0,
+        };
+
+        let result = self.file.replace_all(&pattern, text.as_str(), current.start, end);
+        let count  = self.report(result).unwrap_or(0);
+
+        self.replacement.as_mut().unwrap().count += count;
+        self.history_changed();
+        self.finish_replace()
+    }
+
+    /// Get the match an interactive replacement is asking about, if one is
+    /// in progress.
+    pub fn replacing(&self) -> Option<Match> {
+        self.replacement.as_ref().map(|replacement| replacement.current)
+    }
+
+    /// Resolve a conflict by throwing away all changes and reading the file
+    /// from disk again.
+    pub fn reload(&mut self) -> Result<()> {
+        self.file.reload()?;
+        self.history_changed();
+        self.detect_line_ending();
+        Ok(())
+    }
+
+    /// Replace the selected text with `text` and stop selecting. A block
+    /// has each of its lines replaced. The cursor is left after the text.
+    /// Returns the text that was replaced, as `selected_text` would have.
+    pub fn replace_selection(&mut self, text : &str) -> Option<String> {
+        let selection = self.selection()?;
+        let selected  = self.selected_text()?;
+        let ranges    = self.selection_ranges();
+
+        self.selection    = None;
+        self.render_lines = true;
+
+        // Go backwards so the ranges we haven't got to yet stay put.
+        self.file.begin_group();
+
+        for &(start, end) in ranges.iter().rev() {
+            // Lines that are too short to reach a block are left alone.
+            if start == end && selection.kind == SelectionKind::Block {
+                continue;
+            }
+
+            if end > start {
+                let result = self.file.delete(start, end - start);
+                if self.report(result).is_none() {
+                    break;
+                }
+
+                self.text_changed(start, end - start, 0);
+            }
+
+            if text.is_empty() {
+                continue;
+            }
+
+            let result = self.file.insert(text, start);
+            if self.report(result).is_none() {
+                break;
+            }
+
+            self.text_changed(start, 0, text.len());
+        }
+
+        self.file.end_group();
+
+        // Vim leaves the cursor at the start of the line after deleting
+        // whole lines, which is where the first range started.
+        let start  = ranges.first().map_or(0, |&(start, _)| start);
+        let cursor = match selection.kind {
+            SelectionKind::Block => start,
+            _                    => start + text.len(),
+        };
+
+        self.set_cursor(cmp::min(cursor, self.file.len()));
+
+        Some(selected)
+    }
+
+    /// Draw everything again the next time the FileView is rendered, like
+    /// after something else has drawn over it.
+    pub fn redraw(&mut self) {
+        self.render_lines = true;
+    }
+
+    /// Redo the most recently undone edit or group of edits.
+    pub fn redo(&mut self) {
+        let result = self.file.redo();
+        self.history_moved(result);
+    }
+
+    /// Replay the unsaved edits an earlier session left behind onto the
+    /// file.
+    pub fn recover(&mut self) -> Result<()> {
+        self.file.recover()?;
+        self.history_changed();
+        Ok(())
+    }
+
+    /// Save the file. Returns the number of bytes written.
+    ///
+    /// A file that doesn't exist yet is created, along with any
+    /// directories it should be in.
+    pub fn save(&mut self) -> Result<usize> {
+        let path = match self.path {
+            Some(ref path) => path.clone(),
+            None           => return Err(Error::new(ErrorKind::InvalidInput, "FileView has no path")),
+        };
+
+        // A new file's PieceFile doesn't know where it goes.
+        if self.file.is_empty() {
+            return self.save_as(path.as_str());
+        }
+
+        let written = self.file.save()?;
+        self.render_lines = true;
+        Ok(written)
+    }
+
+    /// Save the file to `path`, which is where it is saved from then on.
+    /// Returns the number of bytes written. Any directories the file
+    /// should be in are created.
+    pub fn save_as(&mut self, path : &str) -> Result<usize> {
+        if let Some(parent) = Path::new(path).parent() {
+            if parent != Path::new("") {
+                fs::create_dir_all(parent)?;
+            }
+        }
+
+        let written = self.file.save_as(path)?;
+        self.path         = Some(String::from(path));
+        self.scratch      = false;
+        self.render_lines = true;
+        Ok(written)
+    }
+
+    /// Make a scratch buffer that shows `text`. It isn't backed by a file
+    /// and can be closed without saving it.
+    pub fn scratch(text : &str) -> Result<FileView> {
+        let mut view = FileView::empty()?;
+
+        view.file.insert(text, 0)?;
+        view.scratch = true;
+        view.regenerate_lines();
+        view.detect_line_ending();
+
+        Ok(view)
+    }
+
+    /// Change the encoding the file is saved in. Saving fails if the
+    /// text can't be represented in the new encoding.
+    pub fn set_encoding(&mut self, encoding : Encoding) {
+        self.file.set_encoding(encoding);
+    }
+
+    /// Use a different line ending for new lines without changing the
+    /// ones already in the file.
+    pub fn set_line_ending(&mut self, ending : LineEnding) {
+        self.line_ending = ending;
+    }
+
+    /// Start selecting text of some kind, with the anchor at the cursor.
+    pub fn select(&mut self, kind : SelectionKind) {
+        self.selection    = Some((kind, self.cursor_offset));
+        self.render_lines = true;
+    }
+
+    /// Get the text that is selected, if there is a selection. The lines of
+    /// a block are joined with the file's line ending.
+    pub fn selected_text(&mut self) -> Option<String> {
+        self.selection?;
+
+        let ranges    = self.selection_ranges();
+        let separator = match self.selection()?.kind {
+            SelectionKind::Block => self.line_ending.as_str(),
+            _                    => "",
+        };
+
+        let mut parts = Vec::with_capacity(ranges.len());
+        for (start, end) in ranges {
+            parts.push(self.text(start, end)?);
+        }
+
+        Some(parts.join(separator))
+    }
+
+    /// Get the selection, if there is one.
+    pub fn selection(&self) -> Option<Selection> {
+        self.selection.map(|(kind, anchor)| Selection {
+            kind,
+            anchor,
+            cursor : self.cursor_offset,
+        })
+    }
+
+    /// Get the ranges of bytes that are selected as start and end offsets,
+    /// in order. A block has one for each line, which is empty if the line
+    /// doesn't reach the block.
+    pub fn selection_ranges(&mut self) -> Vec<(usize, usize)> {
+        let selection = match self.selection() {
+            Some(selection) => selection,
+            None            => return Vec::new(),
+        };
+
+        let first = cmp::min(selection.anchor, selection.cursor);
+        let last  = cmp::max(selection.anchor, selection.cursor);
+
+        match selection.kind {
+            SelectionKind::Char => {
+                vec![(first, last + self.char_len_at(last))]
+            },
+            SelectionKind::Line => {
+                let start = self.lines.get(self.line_index(first)).unwrap().start();
+                let end   = self.lines.get(self.line_index(last)).unwrap().end();
+                vec![(start, end)]
+            },
+            SelectionKind::Block => {
+                let anchor_line   = self.lines.get(self.line_index(selection.anchor)).unwrap();
+                let cursor_line   = self.lines.get(self.line_index(selection.cursor)).unwrap();
+                let anchor_column = selection.anchor - anchor_line.start();
+                let cursor_column = selection.cursor - cursor_line.start();
+
+                let left   = cmp::min(anchor_column, cursor_column);
+                let right  = cmp::max(anchor_column, cursor_column);
+                let top    = cmp::min(anchor_line.number(), cursor_line.number());
+                let bottom = cmp::max(anchor_line.number(), cursor_line.number());
+
+                let mut ranges = Vec::with_capacity(bottom - top + 1);
+
+                for number in top .. bottom + 1 {
+                    let line  = self.get_line(number).unwrap();
+                    let start = line.start() + cmp::min(left, line.content_length);
+                    let end   = if right < line.content_length {
+                        let offset = line.start() + right;
+                        offset + self.char_len_at(offset)
+                    } else {
+                        line.content_end()
+                    };
+
+                    ranges.push((start, end));
+                }
+
+                ranges
+            },
+        }
+    }
+
+    /// Select a region, moving the cursor to its end.
+    pub fn set_selection(&mut self, selection : Selection) {
+        let length = self.file.len();
+
+        self.selection    = Some((selection.kind, cmp::min(selection.anchor, length)));
+        self.render_lines = true;
+        self.set_cursor(cmp::min(selection.cursor, length));
+    }
+
+    /// Show a message at the bottom of the view the next time it is
+    /// rendered, the same way errors are shown.
+    pub fn show_message(&mut self, message : &str) {
+        self.error        = Some(String::from(message));
+        self.render_lines = true;
+    }
+
+    /// Get the pattern from the last search, if its matches are still
+    /// highlighted.
+    pub fn search(&self) -> Option<&Pattern> {
+        self.search.as_ref()
+    }
+
     /// Set the cursor's location in the file.
-    pub fn set_cursor(&mut self, loc : usize) -> Result<()> {
+    pub fn set_cursor(&mut self, loc : usize) {
         self.cursor_offset = loc;
         self.render_cursor = true;
 
+        // The selection follows the cursor.
+        if self.selection.is_some() {
+            self.render_lines = true;
+        }
+
         // Ensure the cursor's new position falls on a line in the
         // viewport as it was at the time of the last render.
         let line           = self.current_line().number();
@@ -442,14 +1665,24 @@ impl FileView {
         } if line > viewport_end {
             self.move_viewport((line as i64) - (viewport_end as i64));
         }
+    }
 
-        Ok(())
+    /// Leave the match an interactive replacement is showing as it is and
+    /// move on to the next one, which is returned.
+    pub fn skip_current(&mut self) -> Option<Match> {
+        let current = match self.replacement {
+            Some(ref replacement) => replacement.current,
+            None                  => return None,
+        };
+
+        let next = if current.is_empty() { current.end + 1 } else { current.end };
+        self.next_replacement(next)
     }
 
     /// Set the line that is the top of the viewport. Lines are one-indexed
     /// so the top of the viewport should be at least 1. The cursor offset
     /// is clamped to the inside of the viewport.
-    pub fn set_viewport_top(&mut self, line : usize) -> Result<()> {
+    pub fn set_viewport_top(&mut self, line : usize) {
         self.viewport_top = cmp::max(1, cmp::min(line, self.lines.len()));
 
         let rows    = self.viewport_rows;
@@ -458,13 +1691,52 @@ impl FileView {
         let current = self.current_line().number;
 
         if current < top {
-            self.move_cursor_vertically((top - current) as i64);
+            self._move_cursor_vertically((top - current) as i64);
         } else if current > bottom {
-            self.move_cursor_vertically((bottom as i64) - (current as i64));
+            self._move_cursor_vertically((bottom as i64) - (current as i64));
         }
 
         self.render_lines = true;
-        Ok(())
+    }
+
+    /// Get the text between two offsets. Bytes that aren't valid UTF-8
+    /// are replaced.
+    pub fn text(&mut self, start : usize, end : usize) -> Option<String> {
+        let end    = cmp::min(end, self.file.len());
+        let start  = cmp::min(start, end);
+        let result = self.file.read_bytes_at(start, end - start);
+        let bytes  = self.report(result)?;
+
+        Some(String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    /// Undo the most recent edit or group of edits.
+    pub fn undo(&mut self) {
+        let result = self.file.undo();
+        self.history_moved(result);
+    }
+
+    /// Get the matches of the last search that start on the lines in the
+    /// viewport, for highlighting.
+    pub fn visible_matches(&mut self) -> Vec<Match> {
+        let pattern = match self.search {
+            Some(ref pattern) => pattern.clone(),
+            None              => return Vec::new(),
+        };
+
+        let top    = self.viewport_top;
+        let bottom = cmp::min(top + self.viewport_rows - 1, self.lines.len());
+
+        // The viewport can be left past the end of the file by a delete.
+        if top > bottom {
+            return Vec::new();
+        }
+
+        let start  = self.lines.get(top - 1).unwrap().start();
+        let end    = self.lines.get(bottom - 1).unwrap().end();
+        let result = self.file.find_all(&pattern, start, end);
+
+        self.report(result).unwrap_or_default()
     }
 }
 
@@ -475,21 +1747,25 @@ impl Actionable for FileView {
 }
 
 impl KeyInput for FileView {
-    fn consume(&mut self, key : Key) -> Option<()> {
+    fn consume(&mut self, _key : Key) -> Option<()> {
         None
     }
 }
 
 impl render::Renderable for FileView {
     fn render(&mut self, renderer : &mut render::Renderer, size : (u16, u16)) -> Result<()> {
-        let (cols, rows) = size;
-        let top          = self.viewport_top;
-        let bottom       = cmp::min(top + (rows as usize) - 1, self.lines.len());
+        let (_cols, rows) = size;
+        let top           = self.viewport_top;
+
+        // Look at the file on disk before reading anything from it. Reads
+        // trust what this finds until the next frame, and reading a file
+        // that was cut short in place would take the whole editor down.
+        let conflict = self.file.conflict();
 
         self.viewport_rows = rows as usize;
 
         let mut line_number = 1;
-        let mut cursor_offset = self.cursor_offset;
+        let cursor_offset = self.cursor_offset;
         let mut cursor_placed = false;
 
         // The calculated screen position of the cursor
@@ -497,15 +1773,18 @@ impl render::Renderable for FileView {
         let mut cursor_col : u16 = 1;
 
         if self.render_lines {
-            renderer.write(format!("{}", termion::clear::All).as_str());
+            renderer.write(format!("{}", termion::clear::All).as_str())?;
         }
 
-        for line in self.lines.iter() {
-            // Don't render anything before the top of the viewport.
-            if line.number < top {
-                continue;
-            }
+        // Matches, the selection and the cursors other than the main one
+        // are all shown in reverse video.
+        let mut highlights = if self.render_lines { self.visible_matches() } else { Vec::new() };
+        highlights.extend(self.cursors.iter().map(|&cursor| Match { start : cursor, end : cursor + 1 }));
+        highlights.extend(self.selection_ranges().into_iter().map(|(start, end)| Match { start, end }));
+        highlights.sort_by_key(|found| found.start);
 
+        // Don't render anything before the top of the viewport.
+        for line in self.lines.iter_from(top - 1) {
             line_number = line.number - top + 1;
 
             // Don't render anything past the bottom edge of the viewport.
@@ -530,24 +1809,67 @@ impl render::Renderable for FileView {
             }
 
             if self.render_lines {
-                renderer.move_cursor(line_number as u16, 1);
-                let text = self.file.read_at(line.start(), line.len() - line.end_size()).unwrap();
-                renderer.write(&text);
+                renderer.move_cursor(line_number as u16, 1)?;
+
+                // Bytes that aren't valid UTF-8 are shown as replacement
+                // characters, but they stay as they are in the file.
+                if let Ok(text) = self.file.read_bytes_at(line.start(), line.len() - line.end_size()) {
+                    renderer.write(FileView::highlight_matches(&text, line.start(), &highlights).as_str())?;
+                }
+
+                // A cursor past the end of the line's text still needs
+                // something to show it.
+                if self.cursors.contains(&line.content_end()) {
+                    renderer.write(format!("{} {}", termion::style::Invert, termion::style::Reset).as_str())?;
+                }
             }
         }
 
         let next_line = (line_number + 1) as u16;
         if self.render_lines && next_line <= rows {
             for line in next_line .. rows + 1 {
-                renderer.move_cursor(line, 1);
-                renderer.write("~");
+                renderer.move_cursor(line, 1)?;
+                renderer.write("~")?;
             }
         }
 
+        // Let the user know if someone changed the file out from
+        // under us.
+        if let Some(error) = self.error.take() {
+            renderer.move_cursor(rows, 1)?;
+            renderer.write(format!("{}{}",
+                                   termion::clear::CurrentLine,
+                                   error).as_str())?;
+        } else if let Some(ref replacement) = self.replacement {
+            renderer.move_cursor(rows, 1)?;
+            renderer.write(format!("{}Replace with {}? (yes, no, all, or quit)",
+                                   termion::clear::CurrentLine,
+                                   replacement.replacement).as_str())?;
+        } else if let Some(conflict) = conflict {
+            let message = match conflict {
+                Conflict::Modified => "File was modified on disk",
+                Conflict::Replaced => "File was replaced on disk",
+                Conflict::Deleted  => "File was deleted from disk",
+            };
+
+            renderer.move_cursor(rows, 1)?;
+            renderer.write(format!("{}{}. Reload it or keep the buffer to go on editing.",
+                                   termion::clear::CurrentLine,
+                                   message).as_str())?;
+        } else if self.render_lines && self.has_recovery() {
+            renderer.move_cursor(rows, 1)?;
+            renderer.write(format!("{}Found unsaved edits from an earlier session. Recover or discard them.",
+                                   termion::clear::CurrentLine).as_str())?;
+        } else if self.render_lines && self.has_mixed_line_endings() {
+            renderer.move_cursor(rows, 1)?;
+            renderer.write(format!("{}File has mixed line endings",
+                                   termion::clear::CurrentLine).as_str())?;
+        }
+
         self.render_lines  = false;
         self.render_cursor = false;
 
-        renderer.move_cursor(cursor_row, cursor_col);
+        renderer.move_cursor(cursor_row, cursor_col)?;
 
         Ok(())
     }
@@ -0,0 +1,476 @@
+//! byt - views::file::motions
+//!
+//! Functions that find places and ranges in a FileView's text without
+//! moving the cursor, like where the next word starts or the extent of the
+//! parentheses around an offset. Editing modes build their movement and
+//! text selection out of these.
+//!
+//! Everything here works on byte offsets and reads the file a line at a
+//! time. Words are runs of letters, digits and underscores, or runs of
+//! other characters that aren't whitespace.
+
+// EXTERNS
+
+// LIBRARY INCLUDES
+use std::cmp;
+
+// SUBMODULES
+
+// LOCAL INCLUDES
+use super::{FileView, Line};
+
+/// The kinds of characters that words are made of.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum CharClass {
+    Space,
+    Word,
+    Punctuation,
+}
+
+impl CharClass {
+    /// Get the class of a character. The end of a line counts as
+    /// whitespace.
+    fn of(c : Option<char>) -> CharClass {
+        match c {
+            None                                     => CharClass::Space,
+            Some(c) if c.is_whitespace()             => CharClass::Space,
+            Some(c) if c.is_alphanumeric() || c == '_' => CharClass::Word,
+            Some(_)                                  => CharClass::Punctuation,
+        }
+    }
+}
+
+/// Steps through a FileView's characters one at a time, loading a line at
+/// a time. The end of each line is a place of its own, which is where its
+/// line ending is or, on the last line, the end of the file.
+struct Walker {
+    line  : Line,
+    /// The characters in the line and where they start in it.
+    chars : Vec<(usize, char)>,
+    /// The character the walker is on, which is `chars.len()` at the end
+    /// of the line.
+    index : usize,
+}
+
+impl Walker {
+    /// Start walking from the character at `offset`.
+    fn new(view : &mut FileView, offset : usize) -> Walker {
+        let line  = view.line_at(offset);
+        let chars = Walker::load(view, &line);
+        let index = chars
+            .iter()
+            .position(|&(start, c)| line.start() + start + c.len_utf8() > offset)
+            .unwrap_or(chars.len());
+
+        Walker { line, chars, index }
+    }
+
+    /// Read the characters of a line.
+    fn load(view : &mut FileView, line : &Line) -> Vec<(usize, char)> {
+        view.text(line.start(), line.content_end())
+            .unwrap_or_default()
+            .char_indices()
+            .collect()
+    }
+
+    /// Move to the start of another line.
+    fn enter(&mut self, view : &mut FileView, line : Line) {
+        self.chars = Walker::load(view, &line);
+        self.line  = line;
+        self.index = 0;
+    }
+
+    /// Get the character the walker is on, or None at the end of a line.
+    fn current(&self) -> Option<char> {
+        self.chars.get(self.index).map(|&(_, c)| c)
+    }
+
+    /// Check whether the walker is on a line with nothing on it.
+    fn on_empty_line(&self) -> bool {
+        self.chars.is_empty()
+    }
+
+    /// Get the offset of the place the walker is on.
+    fn offset(&self) -> usize {
+        match self.chars.get(self.index) {
+            Some(&(start, _)) => self.line.start() + start,
+            None              => self.line.content_end(),
+        }
+    }
+
+    /// Move to the next place, which can be on the next line. Returns
+    /// false at the end of the file.
+    fn forward(&mut self, view : &mut FileView) -> bool {
+        if self.index < self.chars.len() {
+            self.index += 1;
+            return true;
+        }
+
+        match view.get_line(self.line.number() + 1) {
+            Some(line) => {
+                self.enter(view, line);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Move to the previous place, which can be the end of the previous
+    /// line. Returns false at the start of the file.
+    fn backward(&mut self, view : &mut FileView) -> bool {
+        if self.index > 0 {
+            self.index -= 1;
+            return true;
+        }
+
+        match view.get_line(self.line.number() - 1) {
+            Some(line) => {
+                self.enter(view, line);
+                self.index = self.chars.len();
+                true
+            },
+            None => false,
+        }
+    }
+}
+
+impl FileView {
+    // #################################
+    // P R I V A T E  F U N C T I O N S
+    // #################################
+
+    /// Get the characters of the line `offset` is on and the index of the
+    /// character at `offset`, which is the number of characters at the
+    /// end of the line.
+    fn line_chars(&mut self, offset : usize) -> (Line, Vec<(usize, char)>, usize) {
+        let walker = Walker::new(self, offset);
+        (walker.line, walker.chars, walker.index)
+    }
+
+    // ###############################
+    // P U B L I C  F U N C T I O N S
+    // ###############################
+
+    /// Find the start of the word before `offset`. Empty lines count as
+    /// words. Stops at the start of the file.
+    pub fn prev_word_start(&mut self, offset : usize) -> usize {
+        let mut walker = Walker::new(self, offset);
+
+        // Skip back over whitespace to the end of the previous word.
+        loop {
+            if !walker.backward(self) {
+                return walker.offset();
+            }
+
+            if walker.on_empty_line() || CharClass::of(walker.current()) != CharClass::Space {
+                break;
+            }
+        }
+
+        let class = CharClass::of(walker.current());
+        while walker.index > 0 && CharClass::of(Some(walker.chars[walker.index - 1].1)) == class {
+            walker.index -= 1;
+        }
+
+        walker.offset()
+    }
+
+    /// Find the start of the word after `offset`. Empty lines count as
+    /// words. Stops at the end of the file.
+    pub fn next_word_start(&mut self, offset : usize) -> usize {
+        let mut walker = Walker::new(self, offset);
+        let class      = CharClass::of(walker.current());
+
+        // Get off of the word the offset is in.
+        if class != CharClass::Space {
+            while CharClass::of(walker.current()) == class {
+                walker.index += 1;
+            }
+        }
+
+        loop {
+            if CharClass::of(walker.current()) != CharClass::Space {
+                return walker.offset();
+            }
+
+            if !walker.forward(self) {
+                return walker.offset();
+            }
+
+            if walker.on_empty_line() {
+                return walker.offset();
+            }
+        }
+    }
+
+    /// Find the last character of the word that ends after `offset`.
+    /// Stops at the end of the file.
+    pub fn word_end(&mut self, offset : usize) -> usize {
+        let mut walker = Walker::new(self, offset);
+
+        loop {
+            if !walker.forward(self) {
+                return walker.offset();
+            }
+
+            if CharClass::of(walker.current()) != CharClass::Space {
+                break;
+            }
+        }
+
+        let class = CharClass::of(walker.current());
+        while walker.index + 1 < walker.chars.len() &&
+              CharClass::of(Some(walker.chars[walker.index + 1].1)) == class {
+            walker.index += 1;
+        }
+
+        walker.offset()
+    }
+
+    /// Find the start of the first empty line after the paragraph at
+    /// `offset`, or the end of the file if there isn't one.
+    pub fn next_paragraph(&mut self, offset : usize) -> usize {
+        let count      = self.lines.len();
+        let mut number = self.line_at(offset).number();
+
+        // Empty lines before a paragraph belong to it.
+        while number < count && self.get_line(number).unwrap().content_length == 0 {
+            number += 1;
+        }
+
+        while number < count {
+            number += 1;
+
+            let line = self.get_line(number).unwrap();
+            if line.content_length == 0 {
+                return line.start();
+            }
+        }
+
+        self.len()
+    }
+
+    /// Find the start of the first empty line before the paragraph at
+    /// `offset`, or the start of the file if there isn't one.
+    pub fn prev_paragraph(&mut self, offset : usize) -> usize {
+        let mut number = self.line_at(offset).number();
+
+        while number > 1 && self.get_line(number).unwrap().content_length == 0 {
+            number -= 1;
+        }
+
+        while number > 1 {
+            number -= 1;
+
+            let line = self.get_line(number).unwrap();
+            if line.content_length == 0 {
+                return line.start();
+            }
+        }
+
+        0
+    }
+
+    /// Find `target` on the same line as `offset`, after it or before it.
+    /// With `till`, the offset of the character next to `target` on the
+    /// side of `offset` is returned instead, like vim's `t`.
+    pub fn find_in_line(&mut self, offset : usize, target : char, forward : bool, till : bool) -> Option<usize> {
+        let (line, chars, index) = self.line_chars(offset);
+        let start                = line.start();
+
+        if forward {
+            let found = chars
+                .iter()
+                .enumerate()
+                .skip(index + 1)
+                .find(|&(_, &(_, c))| c == target)?
+                .0;
+
+            let found = if till { found - 1 } else { found };
+            Some(start + chars.get(found).map_or(line.content_length, |&(at, _)| at))
+        } else {
+            let found = chars[.. cmp::min(index, chars.len())]
+                .iter()
+                .rposition(|&(_, c)| c == target)?;
+
+            let found = if till { found + 1 } else { found };
+            Some(start + chars.get(found).map_or(line.content_length, |&(at, _)| at))
+        }
+    }
+
+    /// Get the offset `delta` lines away from `offset`, in the same column
+    /// or at the end of the line if it isn't that long. Returns None if
+    /// there is no such line.
+    pub fn line_offset(&self, offset : usize, delta : i64) -> Option<usize> {
+        let line   = self.line_at(offset);
+        let number = (line.number() as i64) + delta;
+
+        if number < 1 {
+            return None;
+        }
+
+        let dest = self.get_line(number as usize)?;
+        Some(dest.start() + cmp::min(offset - line.start(), dest.content_length))
+    }
+
+    /// Get the offset of the character after the one at `offset`, staying
+    /// on the same line.
+    pub fn next_char(&mut self, offset : usize) -> usize {
+        let line = self.line_at(offset);
+
+        if offset >= line.content_end() {
+            return offset;
+        }
+
+        offset + self.char_len_at(offset)
+    }
+
+    /// Get the offset of the character before the one at `offset`, staying
+    /// on the same line.
+    pub fn prev_char(&mut self, offset : usize) -> usize {
+        let (line, chars, index) = self.line_chars(offset);
+
+        match index.checked_sub(1).and_then(|index| chars.get(index)) {
+            Some(&(start, _)) => line.start() + start,
+            None              => offset,
+        }
+    }
+
+    /// Get the word at `offset` as a range of offsets, like vim's `iw`. On
+    /// whitespace, the whitespace is the word. With `around`, whitespace
+    /// after the word is included too, or before it if there isn't any
+    /// after, like vim's `aw`.
+    pub fn word_range(&mut self, offset : usize, around : bool) -> (usize, usize) {
+        let (line, chars, index) = self.line_chars(offset);
+        let at = |index : usize| -> usize {
+            line.start() + chars.get(index).map_or(line.content_length, |&(start, _)| start)
+        };
+        let class = |index : usize| CharClass::of(chars.get(index).map(|&(_, c)| c));
+
+        if index >= chars.len() {
+            return (offset, offset);
+        }
+
+        let kind      = class(index);
+        let mut first = index;
+        let mut last  = index + 1;
+
+        while first > 0 && class(first - 1) == kind {
+            first -= 1;
+        }
+
+        while last < chars.len() && class(last) == kind {
+            last += 1;
+        }
+
+        if around && kind != CharClass::Space {
+            if last < chars.len() && class(last) == CharClass::Space {
+                while last < chars.len() && class(last) == CharClass::Space {
+                    last += 1;
+                }
+            } else {
+                while first > 0 && class(first - 1) == CharClass::Space {
+                    first -= 1;
+                }
+            }
+        }
+
+        (at(first), at(last))
+    }
+
+    /// Get the range inside the innermost pair of `open` and `close` that
+    /// `offset` is in or on, like vim's `i(`. With `around`, the range
+    /// includes the pair itself. Pairs can span lines.
+    pub fn pair_range(&mut self, offset : usize, open : char, close : char, around : bool) -> Option<(usize, usize)> {
+        let mut walker = Walker::new(self, offset);
+
+        // Find the opening character, skipping over pairs that close
+        // before the offset.
+        let mut depth = 0;
+        if walker.current() != Some(open) {
+            loop {
+                if !walker.backward(self) {
+                    return None;
+                }
+
+                match walker.current() {
+                    Some(c) if c == close => depth += 1,
+                    Some(c) if c == open  => {
+                        if depth == 0 {
+                            break;
+                        }
+
+                        depth -= 1;
+                    },
+                    _ => {},
+                }
+            }
+        }
+
+        let start = walker.offset();
+
+        // Find the character that closes it.
+        loop {
+            if !walker.forward(self) {
+                return None;
+            }
+
+            match walker.current() {
+                Some(c) if c == open  => depth += 1,
+                Some(c) if c == close => {
+                    if depth == 0 {
+                        break;
+                    }
+
+                    depth -= 1;
+                },
+                _ => {},
+            }
+        }
+
+        let end = walker.offset();
+
+        if around {
+            Some((start, end + close.len_utf8()))
+        } else {
+            Some((start + open.len_utf8(), end))
+        }
+    }
+
+    /// Get the range inside the quotes that `offset` is in or on, on the
+    /// same line, like vim's `i"`. Quotes escaped with a backslash are
+    /// skipped. If the offset is before any quotes, the first quoted
+    /// string after it is used. With `around`, the range includes the
+    /// quotes and any whitespace after them.
+    pub fn quote_range(&mut self, offset : usize, quote : char, around : bool) -> Option<(usize, usize)> {
+        let (line, chars, index) = self.line_chars(offset);
+
+        let quotes : Vec<usize> = chars
+            .iter()
+            .enumerate()
+            .filter(|&(i, &(_, c))| c == quote && (i == 0 || chars[i - 1].1 != '\\'))
+            .map(|(i, _)| i)
+            .collect();
+
+        // Quotes pair up from the start of the line.
+        let pair = quotes
+            .chunks(2)
+            .filter(|pair| pair.len() == 2)
+            .find(|pair| index <= pair[1])?;
+
+        let (first, mut last) = (pair[0], pair[1] + 1);
+        let at = |index : usize| -> usize {
+            line.start() + chars.get(index).map_or(line.content_length, |&(start, _)| start)
+        };
+
+        if !around {
+            return Some((at(first + 1), at(last - 1)));
+        }
+
+        while last < chars.len() && chars[last].1.is_whitespace() {
+            last += 1;
+        }
+
+        Some((at(first), at(last)))
+    }
+}
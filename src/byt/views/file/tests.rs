@@ -4,6 +4,7 @@ use super::*;
 use byt::views::file::FileView;
 
 /// Make a FileView.
+#[cfg(test)]
 fn make_file() -> FileView {
     FileView::empty().unwrap()
 }
@@ -17,7 +18,7 @@ fn it_inserts_a_character() {
     assert_eq!(file.lines.len(), 1);
 
     {
-        let line = &file.lines[0];
+        let line = file.lines.get(0).unwrap();
         assert_eq!(line.start(), 0);
         assert_eq!(line.end(), 1);
         assert_eq!(line.len(), 1);
@@ -33,7 +34,7 @@ fn it_inserts_a_string() {
     assert_eq!(file.lines.len(), 1);
 
     {
-        let line = &file.lines[0];
+        let line = file.lines.get(0).unwrap();
         assert_eq!(line.start(), 0);
         assert_eq!(line.end(), 1);
         assert_eq!(line.len(), 1);
@@ -119,6 +120,42 @@ fn it_moves_left() {
     assert_eq!(file.cursor_offset, 0);
 }
 
+#[test]
+fn it_moves_over_whole_characters() {
+    let mut file = make_file();
+    file.insert_str("aéb");
+    file.set_cursor(1);
+
+    file.move_cursor_right();
+    assert_eq!(file.cursor_offset, 3);
+
+    file.move_cursor_left();
+    assert_eq!(file.cursor_offset, 1);
+}
+
+#[test]
+fn it_backspaces_whole_characters() {
+    let mut file = make_file();
+    file.insert_str("aéb");
+    file.set_cursor(3);
+
+    file.backspace();
+    assert_eq!(file.cursor_offset, 1);
+    assert_eq!(file.file.read_at(0, file.len()).unwrap().as_str(), "ab");
+}
+
+#[test]
+fn it_backspaces_whole_line_endings() {
+    let mut file = make_file();
+    file.insert_str("foo\r\nbar");
+    file.set_cursor(5);
+
+    file.backspace();
+    assert_eq!(file.cursor_offset, 3);
+    assert_eq!(file.lines.len(), 1);
+    assert_eq!(file.file.read_at(0, file.len()).unwrap().as_str(), "foobar");
+}
+
 #[test]
 fn it_deletes_this_line() {
     let mut file = make_file();
@@ -162,7 +199,7 @@ fn it_deletes_this_line_in_empty_file() {
     assert_eq!(file.lines.len(), 1);
 
     {
-        let line = &file.lines[0];
+        let line = file.lines.get(0).unwrap();
         assert_eq!(line.start(), 0);
         assert_eq!(line.end(), 0);
         assert_eq!(line.len(), 0);
@@ -240,6 +277,402 @@ fn it_goes_to_the_start_of_the_file() {
     assert_eq!(file.cursor_offset, 0);
 }
 
+#[test]
+fn it_joins_lines_when_deleting_a_newline() {
+    let mut file = make_file();
+    file.insert_str("foo\nbar\nbaz");
+    assert_eq!(file.lines.len(), 3);
+
+    file.delete(3, 1);
+    assert_eq!(file.lines.len(), 2);
+
+    let line = file.get_line(1).unwrap();
+    assert_eq!(line.start(), 0);
+    assert_eq!(line.len(), 7);
+
+    let line = file.get_line(2).unwrap();
+    assert_eq!(line.number(), 2);
+    assert_eq!(line.start(), 7);
+    assert!(file.get_line(3).is_none());
+}
+
+#[test]
+fn it_finishes_a_crlf_line_ending() {
+    let mut file = make_file();
+    file.insert_str("foo\rbar");
+    assert_eq!(file.lines.len(), 1);
+
+    file.set_cursor(4);
+    file.insert('\n');
+    assert_eq!(file.lines.len(), 2);
+
+    let line = file.get_line(1).unwrap();
+    assert_eq!(line.content_end(), 3);
+    assert_eq!(line.end_size(), 2);
+    assert_eq!(file.get_line(2).unwrap().start(), 5);
+}
+
+#[test]
+fn it_finds_lines_in_a_latin1_file() {
+    use std::env;
+    use std::process;
+
+    let path = env::temp_dir().join(format!("byt-{}-view-latin1", process::id()));
+    let path = String::from(path.to_str().unwrap());
+    fs::write(&path, b"caf\xe9\nna\xefve").unwrap();
+
+    // The file is converted from Latin-1, so the accents take up two
+    // bytes each.
+    let file = FileView::new(path.as_str()).unwrap();
+    assert_eq!(file.encoding(), Encoding::Latin1);
+    assert_eq!(file.lines.len(), 2);
+    assert_eq!(file.get_line(2).unwrap().start(), 6);
+    assert_eq!(file.get_line(2).unwrap().len(), 6);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn it_detects_crlf_line_endings() {
+    use std::env;
+    use std::process;
+
+    let path = env::temp_dir().join(format!("byt-{}-view-crlf", process::id()));
+    let path = String::from(path.to_str().unwrap());
+    fs::write(&path, "foo\r\nbar\r\n").unwrap();
+
+    let mut file = FileView::new(path.as_str()).unwrap();
+    assert_eq!(file.line_ending(), LineEnding::CrLf);
+    assert!(!file.has_mixed_line_endings());
+
+    file.set_cursor(3);
+    file.insert_newline();
+    assert_eq!(file.lines.len(), 4);
+    assert_eq!(file.get_line(2).unwrap().end_size(), 2);
+    assert_eq!(file.cursor_offset, 5);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn it_flags_mixed_line_endings() {
+    let mut file = make_file();
+    assert_eq!(file.line_ending(), LineEnding::Lf);
+
+    file.insert_str("foo\nbar\r\n");
+    assert!(file.has_mixed_line_endings());
+
+    file.backspace();
+    file.backspace();
+    assert!(!file.has_mixed_line_endings());
+}
+
+#[test]
+fn it_inserts_the_line_ending_it_is_set_to() {
+    let mut file = make_file();
+    file.set_line_ending(LineEnding::CrLf);
+
+    file.insert_str("foo");
+    file.insert_newline();
+    assert_eq!(file.file.read_at(0, file.len()).unwrap().as_str(), "foo\r\n");
+    assert_eq!(file.lines.len(), 2);
+}
+
+#[test]
+fn it_converts_line_endings() {
+    let mut file = make_file();
+    file.insert_str("foo\nbar\r\nbaz");
+    file.set_cursor(5);
+
+    file.convert_line_endings(LineEnding::CrLf);
+    assert_eq!(file.len(), 13);
+    assert_eq!(file.file.read_at(0, 13).unwrap().as_str(), "foo\r\nbar\r\nbaz");
+    assert_eq!(file.cursor_offset, 6);
+    assert!(!file.has_mixed_line_endings());
+
+    file.convert_line_endings(LineEnding::Lf);
+    assert_eq!(file.file.read_at(0, 11).unwrap().as_str(), "foo\nbar\nbaz");
+    assert_eq!(file.line_ending(), LineEnding::Lf);
+
+    // Each conversion is a single undo step.
+    file.undo();
+    assert_eq!(file.len(), 13);
+    assert_eq!(file.lines.len(), 3);
+}
+
+/// Check that the lines of a FileView match the ones it would find by
+/// scanning the whole file again.
+#[cfg(test)]
+fn assert_lines_match(file : &mut FileView) {
+    let spans = |file : &FileView| -> Vec<(usize, usize, usize)> {
+        file.lines.iter().map(|line| (line.start(), line.content_end(), line.end())).collect()
+    };
+
+    let lines = spans(file);
+    file.regenerate_lines();
+    assert_eq!(lines, spans(file));
+}
+
+#[test]
+fn it_updates_lines_when_moving_through_history() {
+    let mut file = make_file();
+    file.insert_str("foo\nbar\nbaz");
+
+    file.set_cursor(4);
+    file.insert_str("x\ny");
+    file.set_cursor(0);
+    file.delete_current_line();
+    file.set_cursor(file.len());
+    file.insert_newline();
+
+    for edit in [0, 5, file.file.num_edits(), 2, 14, 1] {
+        file.goto_edit(edit);
+        assert_lines_match(&mut file);
+    }
+
+    file.undo();
+    assert_lines_match(&mut file);
+    file.redo();
+    assert_lines_match(&mut file);
+}
+
+#[test]
+fn it_updates_lines_when_replacing_on_lines() {
+    let mut file = make_file();
+    file.insert_str("a-b\nc\nd-e-f\ng-h");
+
+    let pattern = Pattern::regex("-").unwrap();
+
+    assert_eq!(file.replace_lines(&pattern, "\n", 1, 3, true), vec![1, 0, 2]);
+    assert_eq!(file.file.read_at(0, file.len()).unwrap().as_str(), "a\nb\nc\nd\ne\nf\ng-h");
+    assert_eq!(file.lines.len(), 7);
+    assert_lines_match(&mut file);
+
+    file.undo();
+    assert_eq!(file.file.read_at(0, file.len()).unwrap().as_str(), "a-b\nc\nd-e-f\ng-h");
+    assert_lines_match(&mut file);
+
+    assert_eq!(file.replace_lines(&pattern, "", 3, 4, false), vec![1, 1]);
+    assert_eq!(file.file.read_at(0, file.len()).unwrap().as_str(), "a-b\nc\nde-f\ngh");
+    assert_lines_match(&mut file);
+}
+
+#[test]
+fn it_scrubs_through_history() {
+    let mut file = make_file();
+    file.insert_str("foo\nbar");
+    assert_eq!(file.lines.len(), 2);
+
+    file.earlier_edits(4);
+    assert_eq!(file.len(), 3);
+    assert_eq!(file.lines.len(), 1);
+    assert_eq!(file.cursor_offset, 3);
+
+    file.goto_edit(0);
+    assert_eq!(file.len(), 0);
+    assert_eq!(file.cursor_offset, 0);
+
+    file.later_edits(7);
+    assert_eq!(file.len(), 7);
+    assert_eq!(file.lines.len(), 2);
+}
+
+#[test]
+fn it_scrubs_through_history_by_time() {
+    let mut file = make_file();
+    file.insert_str("foo\nbar");
+
+    file.earlier(time::Duration::from_secs(60 * 60));
+    assert_eq!(file.len(), 0);
+    assert_eq!(file.lines.len(), 1);
+
+    file.later(time::Duration::from_secs(60 * 60));
+    assert_eq!(file.len(), 7);
+    assert_eq!(file.lines.len(), 2);
+}
+
+#[test]
+fn it_finds_lines_split_between_chunks() {
+    use std::env;
+    use std::process;
+
+    let path = env::temp_dir().join(format!("byt-{}-view-chunks", process::id()));
+    let path = String::from(path.to_str().unwrap());
+
+    // The line ending straddles the first two chunks.
+    let mut contents = "a".repeat(SCAN_CHUNK_SIZE - 1);
+    contents.push_str("\r\nb\n");
+    fs::write(&path, &contents).unwrap();
+
+    let file = FileView::new(path.as_str()).unwrap();
+    assert_eq!(file.lines.len(), 3);
+    assert_eq!(file.get_line(1).unwrap().end_size(), 2);
+    assert_eq!(file.get_line(2).unwrap().start(), SCAN_CHUNK_SIZE + 1);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn it_refuses_edits_to_a_modified_file() {
+    use std::env;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    use std::process;
+
+    let path = env::temp_dir().join(format!("byt-{}-view-modified", process::id()));
+    let path = String::from(path.to_str().unwrap());
+    fs::write(&path, "abc\ndef\n").unwrap();
+
+    let mut file = FileView::new(path.as_str()).unwrap();
+    OpenOptions::new().append(true).open(&path).unwrap().write_all(b"ghi\n").unwrap();
+
+    file.insert_str("X\nY\n");
+    assert_eq!(file.len(), 8);
+    assert_eq!(file.lines.len(), 3);
+    assert_eq!(file.cursor_offset, 0);
+
+    // Once the conflict is resolved the lines match the file again.
+    file.reload().unwrap();
+    file.insert_str("X\nY\n");
+    assert_eq!(file.len(), 16);
+    assert_eq!(file.lines.len(), 6);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn it_renders_a_file_cut_short_on_disk() {
+    use byt::render::Renderable;
+    use byt::render::terminal::TermRenderer;
+    use std::env;
+    use std::fs::OpenOptions;
+    use std::process;
+
+    let path = env::temp_dir().join(format!("byt-{}-view-truncated", process::id()));
+    let path = String::from(path.to_str().unwrap());
+    fs::write(&path, "abcdefg\n".repeat(4096)).unwrap();
+
+    let mut file = FileView::new(path.as_str()).unwrap();
+    file.set_viewport_top(file.lines.len() - 10);
+
+    // Reading the end of a mapped file that was truncated kills the
+    // process, so this only passes if nothing is read.
+    OpenOptions::new().write(true).open(&path).unwrap().set_len(0).unwrap();
+
+    let mut out      = Vec::new();
+    let mut renderer = TermRenderer::new(&mut out);
+    file.render(&mut renderer, (80, 24)).unwrap();
+
+    assert_eq!(file.file.conflict(), Some(Conflict::Modified));
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn it_diffs_and_reloads_a_changed_file() {
+    use std::env;
+    use std::process;
+
+    let path = env::temp_dir().join(format!("byt-{}-view-conflict", process::id()));
+    let path = String::from(path.to_str().unwrap());
+    fs::write(&path, "foo\nbar\n").unwrap();
+
+    let mut file = FileView::new(path.as_str()).unwrap();
+    file.insert_str("car\n");
+
+    // Replace the file like a formatter or checkout would.
+    let other = format!("{}.new", path);
+    fs::write(&other, "foo\nbaz\n").unwrap();
+    fs::rename(&other, &path).unwrap();
+    assert_eq!(file.conflict(), Some(Conflict::Replaced));
+
+    let changes = file.diff_disk().unwrap();
+    assert_eq!(changes, vec![
+        Change::Insert(String::from("car")),
+        Change::Same(String::from("foo")),
+        Change::Delete(String::from("baz")),
+        Change::Insert(String::from("bar")),
+    ]);
+
+    file.reload().unwrap();
+    assert_eq!(file.conflict(), None);
+    assert_eq!(file.len(), 8);
+    assert_eq!(file.lines.len(), 3);
+    assert_eq!(file.cursor_offset, 4);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn it_recovers_unsaved_edits() {
+    use std::env;
+    use std::mem;
+    use std::process;
+
+    let path = env::temp_dir().join(format!("byt-{}-view-recover", process::id()));
+    let path = String::from(path.to_str().unwrap());
+    fs::write(&path, "foo\n").unwrap();
+
+    let mut file = FileView::new(path.as_str()).unwrap();
+    file.insert_str("bar\nbaz");
+    mem::forget(file);
+
+    let mut file = FileView::new(path.as_str()).unwrap();
+    assert!(file.has_recovery());
+    assert_eq!(file.lines.len(), 2);
+
+    file.recover().unwrap();
+    assert!(!file.has_recovery());
+    assert_eq!(file.len(), 11);
+    assert_eq!(file.lines.len(), 3);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn it_creates_a_new_file() {
+    use std::env;
+    use std::process;
+
+    let directory = env::temp_dir().join(format!("byt-{}-view-new", process::id()));
+    let path      = directory.join("sub").join("new.txt");
+    let path      = String::from(path.to_str().unwrap());
+
+    let mut file = FileView::new(path.as_str()).unwrap();
+    assert_eq!(file.len(), 0);
+    assert_eq!(file.lines.len(), 1);
+
+    file.insert_str("foo\n");
+    assert_eq!(file.save().unwrap(), 4);
+    assert_eq!(fs::read_to_string(&path).unwrap(), "foo\n");
+
+    // From then on it's saved like any other file.
+    file.insert_str("bar");
+    file.save().unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "foo\nbar");
+
+    fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn it_saves_as_another_file() {
+    use std::env;
+    use std::process;
+
+    let directory = env::temp_dir().join(format!("byt-{}-view-save-as", process::id()));
+    let path      = String::from(directory.join("a").join("b.txt").to_str().unwrap());
+
+    let mut file = make_file();
+    assert!(file.save().is_err());
+
+    file.insert_str("foo");
+    file.save_as(path.as_str()).unwrap();
+    assert_eq!(file.path, Some(path.clone()));
+    assert_eq!(fs::read_to_string(&path).unwrap(), "foo");
+
+    fs::remove_dir_all(&directory).unwrap();
+}
+
 //#[test]
 //fn it_clamps_the_cursor_to_the_viewport_above() {
     //let mut file = make_file();
@@ -254,3 +687,329 @@ fn it_goes_to_the_start_of_the_file() {
     //file.set_viewport_top(5);
     //assert_eq!(file.cursor_offset, 8);
 //}
+
+#[test]
+fn it_finds_the_next_match() {
+    let mut file = make_file();
+    file.insert_str("foo bar\nbar foo");
+    file.set_cursor(0);
+
+    let pattern = Pattern::literal("foo");
+
+    assert_eq!(file.find_next(&pattern), Some(Match { start : 12, end : 15 }));
+    assert_eq!(file.cursor_offset, 12);
+
+    // It wraps around to the start.
+    assert_eq!(file.find_next(&pattern), Some(Match { start : 0, end : 3 }));
+    assert_eq!(file.cursor_offset, 0);
+    assert_eq!(file.find_next(&Pattern::literal("baz")), None);
+    assert_eq!(file.cursor_offset, 0);
+}
+
+#[test]
+fn it_finds_the_previous_match() {
+    let mut file = make_file();
+    file.insert_str("foo bar\nbar foo");
+    file.set_cursor(9);
+
+    let pattern = Pattern::regex(r"(?m)^\w+").unwrap();
+
+    assert_eq!(file.find_prev(&pattern), Some(Match { start : 8, end : 11 }));
+    assert_eq!(file.find_prev(&pattern), Some(Match { start : 0, end : 3 }));
+
+    // It wraps around to the end.
+    assert_eq!(file.find_prev(&pattern), Some(Match { start : 8, end : 11 }));
+}
+
+#[test]
+fn it_highlights_visible_matches() {
+    let mut file = make_file();
+    file.insert_str("foo bar\nbar foo");
+    assert_eq!(file.visible_matches(), vec![]);
+
+    file.find_next(&Pattern::literal("bar"));
+    let matches = file.visible_matches();
+    assert_eq!(matches, vec![Match { start : 4, end : 7 }, Match { start : 8, end : 11 }]);
+
+    assert_eq!(FileView::highlight_matches(b"foo bar", 0, &matches),
+               format!("foo {}bar{}", termion::style::Invert, termion::style::Reset));
+
+    file.clear_search();
+    assert_eq!(file.visible_matches(), vec![]);
+}
+
+#[test]
+fn it_replaces_all_matches_in_a_range() {
+    let mut file = make_file();
+    file.insert_str("foo\nfoo\nfoo");
+
+    assert_eq!(file.replace_all(&Pattern::literal("foo"), "a\nb", Some((4, 11))), 2);
+    assert_eq!(file.file.read_at(0, file.len()).unwrap().as_str(), "foo\na\nb\na\nb");
+    assert_eq!(file.lines.len(), 5);
+
+    file.undo();
+    assert_eq!(file.file.read_at(0, file.len()).unwrap().as_str(), "foo\nfoo\nfoo");
+    assert_eq!(file.lines.len(), 3);
+}
+
+#[test]
+fn it_replaces_matches_interactively() {
+    let mut file = make_file();
+    file.insert_str("x1 x2 x3 x4");
+
+    let pattern = Pattern::regex(r"x(\d)").unwrap();
+
+    assert_eq!(file.begin_replace(&pattern, "y${1}y", None), Some(Match { start : 0, end : 2 }));
+    assert_eq!(file.replace_current(), Some(Match { start : 4, end : 6 }));
+    assert_eq!(file.cursor_offset, 4);
+    assert_eq!(file.skip_current(), Some(Match { start : 7, end : 9 }));
+    assert_eq!(file.replace_remaining(), 3);
+    assert_eq!(file.replacing(), None);
+    assert_eq!(file.file.read_at(0, file.len()).unwrap().as_str(), "y1y x2 y3y y4y");
+
+    // The remaining matches are undone at once, and the ones before them
+    // one at a time.
+    file.undo();
+    assert_eq!(file.file.read_at(0, file.len()).unwrap().as_str(), "y1y x2 x3 x4");
+    file.undo();
+    assert_eq!(file.file.read_at(0, file.len()).unwrap().as_str(), "x1 x2 x3 x4");
+}
+
+#[test]
+fn it_finishes_replacing_when_there_are_no_more_matches() {
+    let mut file = make_file();
+    file.insert_str("x1 x2");
+
+    let pattern = Pattern::literal("x");
+
+    assert_eq!(file.begin_replace(&pattern, "xx", None), Some(Match { start : 0, end : 1 }));
+    assert_eq!(file.replace_current(), Some(Match { start : 4, end : 5 }));
+    assert_eq!(file.replace_current(), None);
+    assert_eq!(file.replacing(), None);
+    assert_eq!(file.finish_replace(), 0);
+    assert_eq!(file.file.read_at(0, file.len()).unwrap().as_str(), "xx1 xx2");
+}
+
+#[test]
+fn it_inserts_at_every_cursor() {
+    let mut file = make_file();
+    file.insert_str("foo\nbar\nbaz");
+    file.set_cursor(0);
+    file.add_cursor_below();
+    file.add_cursor_below();
+    assert_eq!(file.cursors(), vec![0, 4, 8]);
+
+    file.insert_str("> ");
+    assert_eq!(file.file.read_at(0, file.len()).unwrap().as_str(), "> foo\n> bar\n> baz");
+    assert_eq!(file.cursors(), vec![2, 8, 14]);
+    assert_eq!(file.cursor_offset, 2);
+    assert_eq!(file.lines.len(), 3);
+
+    file.backspace();
+    assert_eq!(file.file.read_at(0, file.len()).unwrap().as_str(), ">foo\n>bar\n>baz");
+    assert_eq!(file.cursors(), vec![1, 6, 11]);
+}
+
+#[test]
+fn it_adds_cursors_above() {
+    let mut file = make_file();
+    file.insert_str("foo\nb\nbaz");
+    file.set_cursor(8);
+
+    file.add_cursor_above();
+    file.add_cursor_above();
+    assert_eq!(file.cursors(), vec![2, 5, 8]);
+}
+
+#[test]
+fn it_moves_every_cursor() {
+    let mut file = make_file();
+    file.insert_str("foo\nlonger\nx");
+    file.set_cursor(2);
+    file.add_cursor(9);
+
+    file.move_cursor_right();
+    assert_eq!(file.cursors(), vec![3, 10]);
+
+    // Cursors that end up in the same place become one.
+    file.move_cursor_down();
+    assert_eq!(file.cursors(), vec![7, 12]);
+    file.move_cursor_down();
+    assert_eq!(file.cursors(), vec![12]);
+}
+
+#[test]
+fn it_keeps_cursors_after_an_edit_in_place() {
+    let mut file = make_file();
+    file.insert_str("foo bar baz");
+    file.set_cursor(0);
+    file.add_cursor(8);
+
+    file.delete(2, 3);
+    assert_eq!(file.cursors(), vec![0, 5]);
+
+    file.clear_cursors();
+    assert_eq!(file.cursors(), vec![0]);
+}
+
+#[test]
+fn it_highlights_other_cursors() {
+    let cursors = [Match { start : 1, end : 2 }, Match { start : 4, end : 5 }];
+
+    assert_eq!(FileView::highlight_matches("aé cd".as_bytes(), 0, &cursors),
+               format!("a{}é{} {}c{}d",
+                       termion::style::Invert, termion::style::Reset,
+                       termion::style::Invert, termion::style::Reset));
+}
+
+#[test]
+fn it_selects_characters() {
+    let mut file = make_file();
+    file.insert_str("foo bar\nbaz");
+    file.set_cursor(6);
+    file.select(SelectionKind::Char);
+    file.move_cursor_down();
+
+    assert_eq!(file.selection(), Some(Selection { kind : SelectionKind::Char, anchor : 6, cursor : 11 }));
+    assert_eq!(file.selection_ranges(), vec![(6, 11)]);
+    assert_eq!(file.selected_text(), Some(String::from("r\nbaz")));
+
+    // The selection includes the character under the cursor.
+    file.set_cursor(4);
+    assert_eq!(file.selected_text(), Some(String::from("bar")));
+
+    assert_eq!(file.delete_selection(), Some(String::from("bar")));
+    assert_eq!(file.file.read_at(0, file.len()).unwrap().as_str(), "foo \nbaz");
+    assert_eq!(file.cursor_offset, 4);
+    assert_eq!(file.selection(), None);
+}
+
+#[test]
+fn it_selects_lines() {
+    let mut file = make_file();
+    file.insert_str("foo\nbar\nbaz\n");
+    file.set_cursor(5);
+    file.select(SelectionKind::Line);
+    file.move_cursor_up();
+
+    assert_eq!(file.selected_text(), Some(String::from("foo\nbar\n")));
+    assert_eq!(file.replace_selection("x\n"), Some(String::from("foo\nbar\n")));
+    assert_eq!(file.file.read_at(0, file.len()).unwrap().as_str(), "x\nbaz\n");
+
+    file.undo();
+    assert_eq!(file.file.read_at(0, file.len()).unwrap().as_str(), "foo\nbar\nbaz\n");
+}
+
+#[test]
+fn it_selects_blocks() {
+    let mut file = make_file();
+    file.insert_str("abcd\nx\nefgh");
+    file.set_cursor(1);
+    file.select(SelectionKind::Block);
+    file.set_cursor(9);
+
+    assert_eq!(file.selection_ranges(), vec![(1, 3), (6, 6), (8, 10)]);
+    assert_eq!(file.selected_text(), Some(String::from("bc\n\nfg")));
+
+    file.replace_selection("-");
+    assert_eq!(file.file.read_at(0, file.len()).unwrap().as_str(), "a-d\nx\ne-h");
+    assert_eq!(file.cursor_offset, 1);
+}
+
+#[test]
+fn it_moves_the_anchor_with_edits() {
+    let mut file = make_file();
+    file.insert_str("foo bar");
+    file.set_cursor(4);
+    file.select(SelectionKind::Char);
+    file.set_cursor(6);
+    file.delete(0, 2);
+
+    assert_eq!(file.selection(), Some(Selection { kind : SelectionKind::Char, anchor : 2, cursor : 4 }));
+    assert_eq!(file.selected_text(), Some(String::from("bar")));
+}
+
+#[test]
+fn it_finds_word_boundaries() {
+    let mut file = make_file();
+    file.insert_str("foo.bar  baz\n\nqux");
+
+    assert_eq!(file.next_word_start(0), 3);
+    assert_eq!(file.next_word_start(3), 4);
+    assert_eq!(file.next_word_start(4), 9);
+    assert_eq!(file.next_word_start(9), 13);
+    assert_eq!(file.next_word_start(13), 14);
+    assert_eq!(file.next_word_start(14), 17);
+
+    assert_eq!(file.word_end(0), 2);
+    assert_eq!(file.word_end(2), 3);
+    assert_eq!(file.word_end(9), 11);
+    assert_eq!(file.word_end(11), 16);
+
+    assert_eq!(file.prev_word_start(16), 14);
+    assert_eq!(file.prev_word_start(14), 13);
+    assert_eq!(file.prev_word_start(13), 9);
+    assert_eq!(file.prev_word_start(9), 4);
+    assert_eq!(file.prev_word_start(2), 0);
+    assert_eq!(file.prev_word_start(0), 0);
+}
+
+#[test]
+fn it_finds_paragraphs() {
+    let mut file = make_file();
+    file.insert_str("a\nb\n\n\nc\nd");
+
+    assert_eq!(file.next_paragraph(0), 4);
+    assert_eq!(file.next_paragraph(4), file.len());
+    assert_eq!(file.prev_paragraph(8), 5);
+    assert_eq!(file.prev_paragraph(5), 0);
+}
+
+#[test]
+fn it_finds_characters_in_a_line() {
+    let mut file = make_file();
+    file.insert_str("a(b)c(d\nx(");
+
+    assert_eq!(file.find_in_line(0, '(', true, false), Some(1));
+    assert_eq!(file.find_in_line(1, '(', true, false), Some(5));
+    assert_eq!(file.find_in_line(1, '(', true, true), Some(4));
+    assert_eq!(file.find_in_line(5, 'x', true, false), None);
+    assert_eq!(file.find_in_line(5, '(', false, false), Some(1));
+    assert_eq!(file.find_in_line(5, '(', false, true), Some(2));
+}
+
+#[test]
+fn it_finds_words_around_an_offset() {
+    let mut file = make_file();
+    file.insert_str("one two  three");
+
+    assert_eq!(file.word_range(5, false), (4, 7));
+    assert_eq!(file.word_range(5, true), (4, 9));
+    assert_eq!(file.word_range(11, true), (7, 14));
+    assert_eq!(file.word_range(7, false), (7, 9));
+}
+
+#[test]
+fn it_finds_pairs_around_an_offset() {
+    let mut file = make_file();
+    file.insert_str("f(a, (b),\n  c) (d)");
+
+    assert_eq!(file.pair_range(3, '(', ')', false), Some((2, 13)));
+    assert_eq!(file.pair_range(11, '(', ')', true), Some((1, 14)));
+    assert_eq!(file.pair_range(6, '(', ')', false), Some((6, 7)));
+    assert_eq!(file.pair_range(7, '(', ')', true), Some((5, 8)));
+    assert_eq!(file.pair_range(1, '(', ')', false), Some((2, 13)));
+    assert_eq!(file.pair_range(0, '(', ')', false), None);
+}
+
+#[test]
+fn it_finds_quotes_around_an_offset() {
+    let mut file = make_file();
+    file.insert_str(r#"x = "a\"b" + "c"  ;"#);
+
+    assert_eq!(file.quote_range(0, '"', false), Some((5, 9)));
+    assert_eq!(file.quote_range(6, '"', true), Some((4, 11)));
+    assert_eq!(file.quote_range(14, '"', false), Some((14, 15)));
+    assert_eq!(file.quote_range(14, '"', true), Some((13, 18)));
+    assert_eq!(file.quote_range(18, '"', false), None);
+}
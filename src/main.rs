@@ -1,5 +1,6 @@
 // EXTERNS
 extern crate libc;
+extern crate regex;
 extern crate termion;
 
 // LIBRARY INCLUDES